@@ -32,10 +32,10 @@ fn main() -> Result<(), Socks5Error> {
 
         let mut stream = Socks5Client::connect("127.0.0.1:9050", &addr, None).await?;
         stream.write_all(REQUEST).await?;
+        stream.shutdown(Shutdown::Write)?;
 
         let mut buf = vec![0u8; 1024];
         let _ = stream.read(&mut buf).await?;
-        stream.shutdown(Shutdown::Both)?;
 
         println!("{}", String::from_utf8(buf.clone()).unwrap());
 
@@ -44,10 +44,10 @@ fn main() -> Result<(), Socks5Error> {
         let mut stream =
             Socks5Client::connect_with_domain("127.0.0.1:9050", "icanhazip.com", 80, None).await?;
         stream.write_all(REQUEST).await?;
+        stream.shutdown(Shutdown::Write)?;
 
         let mut buf = vec![0u8; 1024];
         let _ = stream.read(&mut buf).await?;
-        stream.shutdown(Shutdown::Both)?;
 
         println!("{}", String::from_utf8(buf).unwrap());
 
@@ -85,10 +85,10 @@ fn main() -> Result<(), Socks5Error> {
         .await?;
 
         stream.write_all(REQUEST).await?;
+        stream.shutdown(Shutdown::Write)?;
 
         let mut buf = vec![0u8; 1024];
         let _ = stream.read(&mut buf).await?;
-        stream.shutdown(Shutdown::Both)?;
 
         println!("{}", String::from_utf8(buf).unwrap());
 
@@ -0,0 +1,95 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{ConnectionId, Socks5Error, TargetAddr};
+
+/// Observer of what a [`Socks5Server`](crate::Socks5Server) does with
+/// its clients, for audit logs or monitoring.
+///
+/// Every event carries the [`ConnectionId`] the server assigned to the
+/// client, so the events of one client can be tied together. All methods
+/// default to doing nothing. They are called from the task serving the
+/// client, so they should return quickly.
+#[allow(unused_variables)]
+pub trait ServerObserver: Send + Sync {
+    /// A client connected from `peer`.
+    fn accepted(&self, id: ConnectionId, peer: SocketAddr) {}
+
+    /// The client sent RFC 1929 credentials for `username`, which were
+    /// accepted if `success` is true.
+    fn authenticated(&self, id: ConnectionId, username: &[u8], success: bool) {}
+
+    /// The server connected to `target` for the client: the target of a
+    /// CONNECT, or the peer that connected for a BIND.
+    fn connected(&self, id: ConnectionId, target: &TargetAddr) {}
+
+    /// Relaying ended after `sent` bytes from the client to the target
+    /// and `received` bytes back.
+    fn relayed(&self, id: ConnectionId, sent: u64, received: u64) {}
+
+    /// The client's connection closed.
+    fn closed(&self, id: ConnectionId, summary: &ClientSummary) {}
+}
+
+impl<T: ServerObserver + ?Sized> ServerObserver for Arc<T> {
+    fn accepted(&self, id: ConnectionId, peer: SocketAddr) {
+        (**self).accepted(id, peer)
+    }
+
+    fn authenticated(&self, id: ConnectionId, username: &[u8], success: bool) {
+        (**self).authenticated(id, username, success)
+    }
+
+    fn connected(&self, id: ConnectionId, target: &TargetAddr) {
+        (**self).connected(id, target)
+    }
+
+    fn relayed(&self, id: ConnectionId, sent: u64, received: u64) {
+        (**self).relayed(id, sent, received)
+    }
+
+    fn closed(&self, id: ConnectionId, summary: &ClientSummary) {
+        (**self).closed(id, summary)
+    }
+}
+
+/// What happened over a client's connection, passed to
+/// [`ServerObserver::closed`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ClientSummary {
+    /// Address the client connected from
+    pub peer: SocketAddr,
+    /// Username the client authenticated with, if any
+    pub username: Option<Vec<u8>>,
+    /// What the server connected to for the client, as in
+    /// [`ServerObserver::connected`]
+    pub target: Option<TargetAddr>,
+    /// Time from accepting the client to closing its connection
+    pub duration: Duration,
+    /// Bytes relayed from the client to the target
+    pub bytes_sent: u64,
+    /// Bytes relayed from the target to the client
+    pub bytes_received: u64,
+    /// Why serving the client failed, if it did
+    pub error: Option<Socks5Error>,
+}
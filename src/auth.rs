@@ -0,0 +1,75 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_lite::io::{AsyncRead, AsyncWrite};
+
+use crate::{Credentials, Socks5Client, Socks5Error};
+
+/// Boxed future returned by [`AuthMethod::authenticate`]
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Socks5Error>> + Send + 'a>>;
+
+/// Stream to the proxy an [`AuthMethod`] runs its sub-negotiation over
+pub trait AuthStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AuthStream for S {}
+
+/// Authentication method a client can offer in the SOCKS5 greeting,
+/// such as GSSAPI or a vendor-specific scheme.
+///
+/// Methods are registered with [`Socks5Config::auth_method`]. When the
+/// proxy selects one, its [`AuthMethod::authenticate`] runs right after
+/// the method selection, before the request is sent.
+///
+/// [`Socks5Config::auth_method`]: crate::Socks5Config::auth_method
+pub trait AuthMethod: fmt::Debug + Send + Sync {
+    /// METHOD byte advertised in the greeting
+    fn method_byte(&self) -> u8;
+
+    /// Run the method's sub-negotiation, failing with
+    /// [`Socks5Error::AuthenticationFailed`] if the proxy rejects it.
+    fn authenticate<'a>(&'a self, stream: &'a mut dyn AuthStream) -> AuthFuture<'a>;
+}
+
+/// Shared methods, e.g. to look at their state after connecting
+impl<T: AuthMethod + ?Sized> AuthMethod for Arc<T> {
+    fn method_byte(&self) -> u8 {
+        (**self).method_byte()
+    }
+
+    fn authenticate<'a>(&'a self, stream: &'a mut dyn AuthStream) -> AuthFuture<'a> {
+        (**self).authenticate(stream)
+    }
+}
+
+/// RFC 1929 username/password authentication
+impl AuthMethod for Credentials {
+    fn method_byte(&self) -> u8 {
+        0x02
+    }
+
+    fn authenticate<'a>(&'a self, mut stream: &'a mut dyn AuthStream) -> AuthFuture<'a> {
+        Box::pin(async move {
+            Socks5Client::authenticate(&mut stream, &(self.username(), self.password())).await
+        })
+    }
+}
@@ -0,0 +1,75 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Running many connection attempts with bounded concurrency.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+
+use futures_lite::future;
+
+/// Run `futures` with at most `limit` of them in flight at a time and
+/// return their outputs in the original order. A `limit` of 0 is taken
+/// as 1.
+pub(crate) async fn buffered<T, Fut>(futures: impl IntoIterator<Item = Fut>, limit: usize) -> Vec<T>
+where
+    Fut: Future<Output = T>,
+{
+    let limit = limit.max(1);
+    let mut pending = futures.into_iter().enumerate();
+    let mut running: Vec<(usize, Pin<Box<Fut>>)> = Vec::new();
+    let mut outputs: Vec<Option<T>> = Vec::new();
+
+    future::poll_fn(|cx| loop {
+        while running.len() < limit {
+            match pending.next() {
+                Some((i, future)) => {
+                    outputs.push(None);
+                    running.push((i, Box::pin(future)));
+                }
+                None => break,
+            }
+        }
+
+        if running.is_empty() {
+            return Poll::Ready(());
+        }
+
+        let mut finished = false;
+        let mut i = 0;
+        while i < running.len() {
+            match running[i].1.as_mut().poll(cx) {
+                Poll::Ready(output) => {
+                    outputs[running[i].0] = Some(output);
+                    drop(running.swap_remove(i));
+                    finished = true;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        // Room for more attempts, or done
+        if !finished {
+            return Poll::Pending;
+        }
+    })
+    .await;
+
+    outputs.into_iter().map(|output| output.unwrap()).collect()
+}
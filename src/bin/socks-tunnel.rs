@@ -0,0 +1,110 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Local port forwarder: listens on a TCP port and forwards every
+//! connection through a SOCKS5 proxy to a fixed target, like
+//! `socat TCP-LISTEN:... SOCKS5:...`.
+//!
+//! ```text
+//! socks-tunnel 127.0.0.1:6667 socks5h://127.0.0.1:9050 irc.example.org:6667
+//! ```
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use async_executor::Executor;
+use async_net::{TcpListener, TcpStream};
+use async_socks5::{relay, Resolution, Socks5Config, Socks5Error, TargetAddr};
+
+const USAGE: &str = "usage: socks-tunnel LISTEN_ADDR PROXY TARGET
+
+  LISTEN_ADDR  local address to listen on, such as 127.0.0.1:8080
+  PROXY        proxy as host:port, resolving the target through it, or
+               as a socks5:// or socks5h:// URL with optional credentials
+  TARGET       host:port to forward connections to";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [listen, proxy, target] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let config = if proxy.contains("://") {
+        Socks5Config::from_url(proxy)
+    } else {
+        Ok(Socks5Config::new(proxy).resolution(Resolution::Remote))
+    };
+    let (config, target) = match (config, target.parse::<TargetAddr>()) {
+        (Ok(config), Ok(target)) => (config, target),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("socks-tunnel: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let executor = Executor::new();
+    let result = async_io::block_on(executor.run(serve(&executor, listen, config, target)));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("socks-tunnel: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Accept connections on `listen` forever, forwarding each on its own task.
+async fn serve(
+    executor: &Executor<'_>,
+    listen: &str,
+    config: Socks5Config,
+    target: TargetAddr,
+) -> Result<(), Socks5Error> {
+    let listener = TcpListener::bind(listen).await?;
+    eprintln!(
+        "socks-tunnel: forwarding {} to {}",
+        listener.local_addr()?,
+        target
+    );
+
+    let config = Arc::new(config);
+    let target = Arc::new(target);
+    loop {
+        let (client, peer) = listener.accept().await?;
+        let config = config.clone();
+        let target = target.clone();
+        executor
+            .spawn(async move {
+                if let Err(e) = forward(client, &config, &target).await {
+                    eprintln!("socks-tunnel: {}: {}", peer, e);
+                }
+            })
+            .detach();
+    }
+}
+
+async fn forward(
+    client: TcpStream,
+    config: &Socks5Config,
+    target: &TargetAddr,
+) -> Result<(), Socks5Error> {
+    let upstream = config.connect(target).await?;
+    relay(client, upstream).await?;
+    Ok(())
+}
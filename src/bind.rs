@@ -0,0 +1,78 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use async_net::TcpStream;
+
+use crate::{protocol, Command, Socks5Client, Socks5Error, TargetAddr};
+
+/// Pending inbound connection set up with the SOCKS5 BIND command.
+///
+/// The proxy replies twice to a BIND request: first with the address it
+/// listens on, available from [`Socks5Listener::bind_addr`] to be handed
+/// to the peer, then once the peer has connected, which
+/// [`Socks5Listener::accept`] waits for.
+#[derive(Debug)]
+pub struct Socks5Listener {
+    stream: TcpStream,
+    bind_addr: TargetAddr,
+}
+
+impl Socks5Client {
+    /// Ask the given SOCKS5 proxy to accept a connection from `target`,
+    /// which is the peer expected to connect back, for protocols like FTP
+    /// active mode. Many proxies only use the address to filter who may
+    /// connect, and some ignore it.
+    /// Optionally, provide credentials in the form of username and password.
+    /// Returns a [`Socks5Listener`] once the proxy listens.
+    pub async fn bind(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5Listener, Socks5Error> {
+        let request = protocol::encode_request(Command::Bind.into(), target)?;
+
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        let mut bind_addr =
+            Socks5Client::negotiate_request(&mut stream, &request, &credentials).await?;
+
+        // As with UDP relays, an unspecified address means the proxy's own
+        if let TargetAddr::Ip(addr) = &mut bind_addr {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(stream.peer_addr()?.ip());
+            }
+        }
+
+        Ok(Socks5Listener { stream, bind_addr })
+    }
+}
+
+impl Socks5Listener {
+    /// Address the proxy listens on for the inbound connection, from its
+    /// first reply
+    pub fn bind_addr(&self) -> &TargetAddr {
+        &self.bind_addr
+    }
+
+    /// Wait for the peer to connect to the proxy, which sends a second
+    /// reply carrying the peer's address. Returns the stream, now relaying
+    /// the inbound connection, along with that address.
+    pub async fn accept(mut self) -> Result<(TcpStream, TargetAddr), Socks5Error> {
+        let peer_addr = Socks5Client::read_reply(&mut self.stream).await?;
+        Ok((self.stream, peer_addr))
+    }
+}
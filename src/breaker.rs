@@ -0,0 +1,126 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Socks5Error;
+
+/// Circuit breaker tripping after repeated authentication failures
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Fail with [`Socks5Error::CircuitOpen`] while the circuit is open.
+    pub(crate) fn check(&self) -> Result<(), Socks5Error> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if Instant::now() < until => Err(Socks5Error::CircuitOpen),
+            _ => Ok(()),
+        }
+    }
+
+    /// Update the circuit with the result of an attempt. Once the cooldown
+    /// has passed, a single further failure opens the circuit again.
+    pub(crate) fn record<T>(&self, result: &Result<T, Socks5Error>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(_) => *state = BreakerState::default(),
+            Err(Socks5Error::AuthenticationFailed) => {
+                state.failures = state.failures.saturating_add(1);
+                if state.failures >= self.threshold {
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Server side limit on failed authentication attempts per source IP
+/// within a sliding window
+#[derive(Debug)]
+pub(crate) struct AuthRateLimiter {
+    max_failures: u32,
+    window: Duration,
+    failures: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl AuthRateLimiter {
+    pub(crate) fn new(max_failures: u32, window: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            window,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `ip` failed too often lately to be let in.
+    pub(crate) fn is_blocked(&self, ip: IpAddr) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        match failures.get_mut(&ip) {
+            Some(times) => {
+                self.expire(times, now);
+                times.len() >= self.max_failures as usize
+            }
+            None => false,
+        }
+    }
+
+    /// Count a failed attempt from `ip`, forgetting sources whose
+    /// failures have all expired.
+    pub(crate) fn record_failure(&self, ip: IpAddr) {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        failures.retain(|_, times| {
+            self.expire(times, now);
+            !times.is_empty()
+        });
+        failures.entry(ip).or_default().push_back(now);
+    }
+
+    fn expire(&self, times: &mut VecDeque<Instant>, now: Instant) {
+        while times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= self.window)
+        {
+            times.pop_front();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::SocketAddr;
+
+use async_net::TcpStream;
+
+use crate::{Socks5Client, Socks5Error, TargetAddr};
+
+impl Socks5Client {
+    /// Connect to `target_addr` through a chain of SOCKS5 proxies. The
+    /// first proxy is dialed directly, and every following hop is reached
+    /// with a CONNECT through the previous one, all on the same stream.
+    /// Each hop is given as its `host:port` address along with optional
+    /// credentials in the form of username and password.
+    pub async fn connect_chain(
+        proxies: &[(&str, Option<(&str, &str)>)],
+        target_addr: &SocketAddr,
+    ) -> Result<TcpStream, Socks5Error> {
+        let ((first, _), _) = proxies
+            .split_first()
+            .ok_or(Socks5Error::InvalidInput("proxy chain is empty"))?;
+
+        // Parse every hop up front so a typo doesn't cost a connection
+        let mut targets = proxies[1..]
+            .iter()
+            .map(|(addr, _)| addr.parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        targets.push(TargetAddr::Ip(*target_addr));
+
+        let mut stream = TcpStream::connect(first).await?;
+        for ((_, credentials), target) in proxies.iter().zip(&targets) {
+            Socks5Client::negotiate(&mut stream, target, credentials).await?;
+        }
+
+        Ok(stream)
+    }
+}
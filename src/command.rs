@@ -0,0 +1,117 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use crate::trace::debug;
+use crate::{protocol, Credentials, ReplyCode, Socks5Client, Socks5Error, TargetAddr};
+
+/// Optional settings of [`Socks5Client::send_command`]
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct CommandOptions {
+    /// Username and password, offered in the method selection
+    pub credentials: Option<Credentials>,
+    /// The stream already went through the method selection, so only the
+    /// request is sent
+    pub negotiated: bool,
+}
+
+/// Reply to a request sent with [`Socks5Client::send_command`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CommandReply {
+    /// REP field, whose meaning beyond the RFC 1928 codes is up to the
+    /// command
+    pub code: u8,
+    /// BND.ADDR and BND.PORT
+    pub bound_addr: TargetAddr,
+}
+
+impl CommandReply {
+    /// Whether the proxy replied with success (0x00)
+    pub fn is_success(&self) -> bool {
+        self.code == 0x00
+    }
+
+    /// The reply code as it would be reported for a CONNECT request
+    pub fn reply_code(&self) -> ReplyCode {
+        ReplyCode::from(self.code)
+    }
+}
+
+impl Socks5Client {
+    /// Send a request with any CMD value, such as Tor's extensions or a
+    /// private command, over `stream` to the proxy, and return the reply
+    /// without interpreting it. Unless `options` say the stream was
+    /// already negotiated, the method selection and authentication are
+    /// run first. Replies with a nonzero REP are returned, not turned
+    /// into errors; only a malformed reply fails. The stream carries
+    /// whatever the command sets up afterwards.
+    pub async fn send_command<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        cmd: u8,
+        target: &TargetAddr,
+        options: &CommandOptions,
+    ) -> Result<CommandReply, Socks5Error> {
+        let request = protocol::request_frame(cmd, target)?;
+
+        if !options.negotiated {
+            let methods: &[u8] = match options.credentials {
+                Some(_) => &[0x00, 0x02],
+                None => &[0x00],
+            };
+            let method = Socks5Client::select_method(stream, methods).await?;
+            if let (0x02, Some(credentials)) = (method, &options.credentials) {
+                let credentials = (credentials.username(), credentials.password());
+                Socks5Client::authenticate(stream, &credentials).await?;
+            }
+        }
+
+        Socks5Client::write_request(stream, &request).await?;
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x05 || header[2] != 0x00 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+        debug!(
+            "proxy replied to command {:#04x} with {:#04x}",
+            cmd, header[1]
+        );
+
+        let bound_addr = match Socks5Client::read_reply_addr(stream, header[3]).await {
+            Ok(addr) => addr,
+            // Proxies rejecting a command they don't know may close the
+            // connection right after the code
+            Err(Socks5Error::IoError(e))
+                if header[1] != 0x00 && e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                TargetAddr::Ip(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(CommandReply {
+            code: header[1],
+            bound_addr,
+        })
+    }
+}
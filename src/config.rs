@@ -0,0 +1,1514 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_io::Async;
+use async_net::TcpStream;
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+use crate::batch;
+use crate::breaker::CircuitBreaker;
+use crate::eyeballs::{self, CONNECTION_ATTEMPT_DELAY};
+use crate::idna;
+use crate::stream::Counted;
+use crate::timer::{self, AsyncIoTimer, ReadTimeout, Timer};
+use crate::trace::{debug, in_span};
+use crate::{
+    protocol, AuthMethod, ConnectTimings, ConnectionId, Credentials, CredentialsProvider,
+    IsolationToken, Phase, ReplyCode, Socks5Client, Socks5ConnectInfo, Socks5Error, Socks5Metrics,
+    Socks5Stream, Socks5UdpSocket, TargetAddr, TorError,
+};
+
+impl Socks5Client {
+    /// Configuration for a local Tor daemon with default settings:
+    /// the proxy at `127.0.0.1:9050`, [`Resolution::Remote`] so no
+    /// hostname is ever resolved locally, a 60 second
+    /// [`Socks5Config::timeout`] to allow for circuit building, and
+    /// [`Socks5Config::tor_errors`] to tell onion service failures apart.
+    /// Any of these can be overridden with the usual builder methods.
+    pub fn tor() -> Socks5Config {
+        Socks5Config::new("127.0.0.1:9050")
+            .resolution(Resolution::Remote)
+            .timeout(Duration::from_secs(60))
+            .tor_errors(true)
+    }
+
+    /// Connect through the given SOCKS5 proxy to whichever of `targets`
+    /// answers first, see [`Socks5Config::connect_any`].
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_any(
+        proxy_addr: &str,
+        targets: &[SocketAddr],
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream, Socks5Error> {
+        let mut config = Socks5Config::new(proxy_addr);
+        if let Some((username, password)) = credentials {
+            config = config.credentials(username, password);
+        }
+        config.connect_any(targets).await
+    }
+
+    /// Connect through the given SOCKS5 proxy to each of `targets`, at
+    /// most `limit` at a time, see [`Socks5Config::connect_many`].
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_many(
+        proxy_addr: &str,
+        targets: &[TargetAddr],
+        credentials: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Vec<Result<TcpStream, Socks5Error>> {
+        let mut config = Socks5Config::new(proxy_addr);
+        if let Some((username, password)) = credentials {
+            config = config.credentials(username, password);
+        }
+        config.connect_many(targets, limit).await
+    }
+}
+
+/// IP version used to reach a proxy whose hostname resolves to both
+/// IPv4 and IPv6 addresses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IpVersion {
+    /// Use any address, in the order the resolver returned them
+    #[default]
+    Auto,
+    /// Only use IPv4 addresses
+    V4,
+    /// Only use IPv6 addresses
+    V6,
+}
+
+impl IpVersion {
+    fn matches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Where [`Socks5Config::connect`] resolves domain targets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// Resolve locally and send the proxy an IP address
+    #[default]
+    Local,
+    /// Only ever let the proxy resolve hostnames. Every method that
+    /// would need a local lookup fails with
+    /// [`Socks5Error::LocalResolutionDisabled`] instead, so there is no
+    /// way to leak a DNS query.
+    Remote,
+    /// Let the proxy resolve hostnames, and resolve locally only if the
+    /// proxy doesn't support domain names (REP 0x08)
+    RemotePreferred,
+}
+
+/// Callback returning the username and password to authenticate with
+#[derive(Clone)]
+struct CredentialsFn(Arc<dyn Fn() -> (String, String) + Send + Sync>);
+
+impl fmt::Debug for CredentialsFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CredentialsFn(..)")
+    }
+}
+
+/// Shared [`CredentialsProvider`] of a configuration
+#[derive(Clone)]
+struct Provider(Arc<dyn CredentialsProvider>);
+
+impl fmt::Debug for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Provider(..)")
+    }
+}
+
+/// [`Socks5Metrics`] hook, shared between clones of a config
+#[derive(Clone)]
+struct Metrics(Arc<dyn Socks5Metrics>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Metrics(..)")
+    }
+}
+
+/// Reusable SOCKS5 client configuration holding the proxy address,
+/// optional credentials, and the DNS policy used for its connections.
+///
+/// Build it once and connect through it as often as needed:
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use async_socks5::Socks5Config;
+///
+/// # smol::block_on(async {
+/// let proxy = Socks5Config::new("127.0.0.1:1080")
+///     .credentials("user", "pass")
+///     .timeout(Duration::from_secs(30))
+///     .nodelay(true);
+///
+/// let first = proxy.connect_with_domain("example.com", 80).await?;
+/// let second = proxy.connect_with_domain("example.org", 443).await?;
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Socks5Config {
+    proxy_addr: String,
+    label: Option<String>,
+    credentials: Option<Credentials>,
+    credentials_fn: Option<CredentialsFn>,
+    provider: Option<Provider>,
+    max_auth_attempts: u32,
+    require_auth: bool,
+    offered_methods: Option<Vec<u8>>,
+    auth_methods: Vec<Arc<dyn AuthMethod>>,
+    resolution: Resolution,
+    timeout: Option<Duration>,
+    reply_timeouts: Option<(Duration, Duration)>,
+    read_timeout: Option<Duration>,
+    phase_timeouts: Vec<(Phase, Duration)>,
+    timer: Arc<dyn Timer>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    retry: Option<(u32, Duration)>,
+    retry_jitter: bool,
+    domain_fallback: bool,
+    local_addr: Option<SocketAddr>,
+    nodelay: Option<bool>,
+    keepalive: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    ttl: Option<u32>,
+    device: Option<String>,
+    strict_socket_options: bool,
+    proxy_ip_version: IpVersion,
+    strict_hostnames: bool,
+    tor_errors: bool,
+    happy_eyeballs: Option<Duration>,
+    metrics: Option<Metrics>,
+    pipelining: bool,
+    udp_fragment_size: Option<usize>,
+}
+
+impl Socks5Config {
+    /// Create a new configuration for the SOCKS5 proxy at `proxy_addr`,
+    /// either a `host:port` string or an already resolved [`SocketAddr`].
+    /// IPv6 addresses in strings must be written in brackets, as in
+    /// `[::1]:9050`.
+    pub fn new(proxy_addr: impl ToString) -> Self {
+        Self {
+            proxy_addr: proxy_addr.to_string(),
+            label: None,
+            credentials: None,
+            credentials_fn: None,
+            provider: None,
+            max_auth_attempts: 1,
+            require_auth: false,
+            offered_methods: None,
+            auth_methods: Vec::new(),
+            resolution: Resolution::Local,
+            timeout: None,
+            reply_timeouts: None,
+            read_timeout: None,
+            phase_timeouts: Vec::new(),
+            timer: Arc::new(AsyncIoTimer),
+            breaker: None,
+            retry: None,
+            retry_jitter: false,
+            domain_fallback: false,
+            local_addr: None,
+            nodelay: None,
+            keepalive: None,
+            keepalive_interval: None,
+            ttl: None,
+            device: None,
+            strict_socket_options: false,
+            proxy_ip_version: IpVersion::Auto,
+            strict_hostnames: false,
+            tor_errors: false,
+            happy_eyeballs: None,
+            metrics: None,
+            pipelining: false,
+            udp_fragment_size: None,
+        }
+    }
+
+    /// Authenticate to the proxy with the given username and password.
+    pub fn credentials(self, username: &str, password: &str) -> Self {
+        self.with_credentials(Credentials::unchecked(username, password))
+    }
+
+    /// Authenticate to the proxy with `credentials`, which may hold a
+    /// username or password that isn't UTF-8.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Authenticate with the credentials of `token`, so Tor keeps the
+    /// streams of this configuration on their own circuits. This takes
+    /// the place of [`Socks5Config::credentials`].
+    pub fn isolation(self, token: &IsolationToken) -> Self {
+        let (username, password) = token.credentials();
+        self.credentials(username, password)
+    }
+
+    /// Ask `credentials` for the username and password each time the
+    /// proxy requests authentication, instead of using fixed ones. This
+    /// takes precedence over [`Socks5Config::credentials`].
+    pub fn credentials_fn(
+        mut self,
+        credentials: impl Fn() -> (String, String) + Send + Sync + 'static,
+    ) -> Self {
+        self.credentials_fn = Some(CredentialsFn(Arc::new(credentials)));
+        self
+    }
+
+    /// Query `provider` for the credentials whenever the proxy selects
+    /// username and password authentication, and not before, so nothing
+    /// is fetched for proxies that don't need it. This takes precedence
+    /// over [`Socks5Config::credentials_fn`] and
+    /// [`Socks5Config::credentials`].
+    pub fn credentials_provider(mut self, provider: impl CredentialsProvider + 'static) -> Self {
+        self.provider = Some(Provider(Arc::new(provider)));
+        self
+    }
+
+    /// Try authenticating up to `attempts` times on the same connection,
+    /// fetching fresh credentials from [`Socks5Config::credentials_fn`] or
+    /// the [`Socks5Config::credentials_provider`] before each retry. Most
+    /// proxies close the connection after the first failure, so this
+    /// defaults to 1.
+    pub fn max_auth_attempts(mut self, attempts: u32) -> Self {
+        self.max_auth_attempts = attempts.max(1);
+        self
+    }
+
+    /// Name this proxy in log messages, so failures can be attributed
+    /// when several proxies are in use. UDP associations created from
+    /// this configuration carry the label too.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Only offer username/password authentication, so a proxy can't
+    /// silently downgrade the connection to no-auth. Selecting anything
+    /// else fails with [`Socks5Error::UnexpectedResponse`]. Requires
+    /// credentials or an [`Socks5Config::auth_method`] to be configured.
+    pub fn require_auth(mut self, require: bool) -> Self {
+        self.require_auth = require;
+        self
+    }
+
+    /// Offer exactly `methods`, in this order, in the greeting instead of
+    /// picking them from the configured credentials. Some proxies insist
+    /// on a specific method list. The list must hold 1 to 255 distinct
+    /// methods including one we can perform (no-auth, username/password if
+    /// credentials are set, or a registered [`Socks5Config::auth_method`]),
+    /// which is checked when connecting and by
+    /// [`Socks5Config::dry_run`].
+    /// Overrides [`Socks5Config::require_auth`].
+    pub fn offered_methods(mut self, methods: Vec<u8>) -> Self {
+        self.offered_methods = Some(methods);
+        self
+    }
+
+    /// Offer `method` in the greeting after the built-in methods and run
+    /// it when the proxy selects it. A method with the username/password
+    /// byte takes the place of the configured credentials. Can be called
+    /// several times to register more methods.
+    pub fn auth_method(mut self, method: impl AuthMethod + 'static) -> Self {
+        self.auth_methods.push(Arc::new(method));
+        self
+    }
+
+    /// Never resolve hostnames locally, the same as
+    /// [`Resolution::Remote`]. Turning it off again means
+    /// [`Resolution::Local`].
+    pub fn force_remote_dns(self, force: bool) -> Self {
+        self.resolution(match force {
+            true => Resolution::Remote,
+            false => Resolution::Local,
+        })
+    }
+
+    /// Choose where domain targets are resolved, see [`Resolution`].
+    /// [`Resolution::Remote`] is recommended when using Tor, where a
+    /// local lookup is a DNS leak.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Abort a connection attempt with [`Socks5Error::Timeout`] if
+    /// connecting to the proxy, the handshake, and the reply together
+    /// take longer than `timeout`. The error carries [`Phase::Total`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Read the CONNECT reply in two stages: the first four bytes, which
+    /// carry the REP code, within `header`, and the bound address within
+    /// `rest`. An error REP then fails fast even if a proxy is slow to
+    /// send the remainder. Both apply on top of [`Socks5Config::timeout`].
+    pub fn reply_timeouts(mut self, header: Duration, rest: Duration) -> Self {
+        self.reply_timeouts = Some((header, rest));
+        self
+    }
+
+    /// Abort with [`Socks5Error::Timeout`] when the proxy sends nothing for
+    /// `timeout` while we wait on it during negotiation: for the method
+    /// selection, the authentication reply or the request reply. Unlike
+    /// [`Socks5Config::timeout`] this doesn't cover connecting to the proxy,
+    /// and the clock restarts whenever some data arrives, so it catches a
+    /// proxy that stalls halfway through a reply.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Bound a single phase of each connection attempt by `timeout`,
+    /// failing with [`Socks5Error::Timeout`] carrying that phase. The
+    /// connect timeout applies to each proxy address tried. Only
+    /// [`Phase::Connect`], [`Phase::MethodSelection`],
+    /// [`Phase::Authentication`] and [`Phase::Reply`] can be bounded this
+    /// way; [`Socks5Config::timeout`] covers the whole attempt.
+    pub fn phase_timeout(mut self, phase: Phase, timeout: Duration) -> Self {
+        self.phase_timeouts.retain(|(p, _)| *p != phase);
+        self.phase_timeouts.push((phase, timeout));
+        self
+    }
+
+    /// Use a custom [`Timer`] to enforce timeouts instead of the default
+    /// [`AsyncIoTimer`]. Mostly useful for deterministic tests.
+    pub fn timer(mut self, timer: impl Timer + 'static) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+
+    /// After `threshold` consecutive [`Socks5Error::AuthenticationFailed`]
+    /// errors, fail fast with [`Socks5Error::CircuitOpen`] for `cooldown`
+    /// instead of contacting the proxy again. This avoids hammering a proxy
+    /// with bad credentials, which can get them locked out or banned.
+    /// A successful connection resets the failure count. Clones of this
+    /// configuration share the same circuit.
+    pub fn circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = Some(Arc::new(CircuitBreaker::new(threshold, cooldown)));
+        self
+    }
+
+    /// Make up to `max_attempts` attempts when a connection fails with a
+    /// transient error: the proxy reporting a general failure, the
+    /// network or host as unreachable, TTL expired or connection refused,
+    /// or the attempt timing out. Tor answers like this while a circuit
+    /// is still being built. The first retry waits `base_delay`, and the
+    /// delay doubles for every further retry. Other errors, such as
+    /// failed authentication or a connection not allowed by the ruleset,
+    /// are returned right away.
+    /// The [`Socks5Config::timeout`] applies to each attempt separately.
+    pub fn retry(mut self, max_attempts: u32, base_delay: Duration) -> Self {
+        self.retry = Some((max_attempts.max(1), base_delay));
+        self
+    }
+
+    /// Wait a random time between half and all of each retry delay, so
+    /// clients failing together don't all retry at once.
+    pub fn retry_jitter(mut self, jitter: bool) -> Self {
+        self.retry_jitter = jitter;
+        self
+    }
+
+    /// Send the CONNECT request along with the greeting instead of
+    /// waiting for the method selection, saving a round trip per
+    /// connection (a whole circuit round trip over Tor). Only done when
+    /// no authentication is configured, so credentials, and with them
+    /// Tor stream isolation, are never skipped. A proxy that then doesn't
+    /// select no-auth fails the connection just as without pipelining.
+    pub fn pipelining(mut self, pipelining: bool) -> Self {
+        self.pipelining = pipelining;
+        self
+    }
+
+    /// Fragment UDP payloads longer than `max_payload` bytes and
+    /// reassemble fragmented datagrams on associations created from
+    /// this configuration, see [`Socks5UdpSocket::with_fragmentation`].
+    pub fn udp_fragmentation(mut self, max_payload: usize) -> Self {
+        self.udp_fragment_size = Some(max_payload);
+        self
+    }
+
+    /// When a hostname was resolved locally and the proxy rejects the
+    /// resulting address type (REP 0x08, typically an IPv6 address sent to
+    /// an IPv4-only proxy), retry once with the hostname and let the proxy
+    /// resolve it. IP literal targets have nothing to fall back to.
+    pub fn domain_fallback(mut self, fallback: bool) -> Self {
+        self.domain_fallback = fallback;
+        self
+    }
+
+    /// Bind the connection to the proxy to `addr` before dialing, e.g. to
+    /// pick the interface on a multi-homed host. Use port 0 for any port.
+    /// Only proxy addresses of the same IP version are tried, and a
+    /// failure to bind is returned as it is instead of falling back to an
+    /// unbound connection.
+    pub fn local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Set `TCP_NODELAY` on the connection to the proxy, disabling Nagle's
+    /// algorithm for latency-sensitive protocols.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable TCP keepalive on the connection to the proxy, probing after
+    /// it has been idle for `idle`, so dead peers are noticed on long-lived
+    /// tunnels.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Wait `interval` between keepalive probes once the connection went
+    /// idle, see [`Socks5Config::keepalive`]. Left to the system default
+    /// on platforms that can't set it.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set the IP time-to-live, or the hop limit for IPv6, of packets on
+    /// the connection to the proxy.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Bind the connection to the proxy to the network interface named
+    /// `interface` before dialing (`SO_BINDTODEVICE`). Like
+    /// [`Socks5Config::local_addr`], failing to bind fails the connection.
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn bind_device(mut self, interface: &str) -> Self {
+        self.device = Some(interface.to_string());
+        self
+    }
+
+    /// Fail the connection if a socket option can't be set. By default
+    /// options are best-effort: failures are logged at debug level and
+    /// the connection goes ahead, since some sandboxed environments
+    /// disallow `setsockopt`.
+    pub fn strict_socket_options(mut self, strict: bool) -> Self {
+        self.strict_socket_options = strict;
+        self
+    }
+
+    /// Only connect to the proxy over the given IP version. The proxy
+    /// address is resolved and filtered accordingly, failing with
+    /// [`Socks5Error::NoMatchingProxyAddress`] if nothing is left.
+    pub fn proxy_ip_version(mut self, version: IpVersion) -> Self {
+        self.proxy_ip_version = version;
+        self
+    }
+
+    /// Reject raw hosts passed to [`Socks5Config::connect_with_raw_host`]
+    /// that contain NUL or other ASCII control bytes, which some proxies
+    /// mishandle when parsing the domain field.
+    pub fn strict_hostnames(mut self, strict: bool) -> Self {
+        self.strict_hostnames = strict;
+        self
+    }
+
+    /// Decode Tor's extended reply codes into [`Socks5Error::Tor`]
+    /// instead of the generic [`Socks5Error::Reply`].
+    pub fn tor_errors(mut self, decode: bool) -> Self {
+        self.tor_errors = decode;
+        self
+    }
+
+    /// Race connection attempts, to the proxy and in
+    /// [`Socks5Config::connect_any`], Happy Eyeballs style: alternate
+    /// between IPv6 and IPv4 addresses and start the next attempt when
+    /// the previous one failed or hasn't succeeded within `delay`,
+    /// instead of trying one address after the other.
+    /// [`CONNECTION_ATTEMPT_DELAY`] is the delay RFC 8305 recommends.
+    ///
+    /// [`CONNECTION_ATTEMPT_DELAY`]: crate::CONNECTION_ATTEMPT_DELAY
+    pub fn happy_eyeballs(mut self, delay: Duration) -> Self {
+        self.happy_eyeballs = Some(delay);
+        self
+    }
+
+    /// Report connection latency, authentication results, handshake
+    /// durations and reply codes of this config's connections to
+    /// `metrics`.
+    pub fn metrics(mut self, metrics: impl Socks5Metrics + 'static) -> Self {
+        self.metrics = Some(Metrics(Arc::new(metrics)));
+        self
+    }
+
+    /// Call `event` with the metrics hook, if there is one.
+    fn record(&self, event: impl FnOnce(&dyn Socks5Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            event(metrics.0.as_ref());
+        }
+    }
+
+    /// Report the outcome of authenticating with `method`. Only a
+    /// rejection by the proxy counts as failed authentication.
+    fn record_auth(&self, method: u8, result: &Result<(), Socks5Error>) {
+        match result {
+            Ok(()) => self.record(|m| m.authenticated(method, true)),
+            Err(Socks5Error::AuthenticationFailed) => {
+                self.record(|m| m.authenticated(method, false))
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Resolve the proxy address and connect to the first candidate of
+    /// the preferred IP version that accepts the connection. Returns the
+    /// stream along with the address it was dialed at.
+    async fn connect_proxy(&self) -> Result<(TcpStream, SocketAddr), Socks5Error> {
+        let candidates: Vec<SocketAddr> = async_net::resolve(self.proxy_addr.as_str())
+            .await?
+            .into_iter()
+            .filter(|addr| self.proxy_ip_version.matches(addr))
+            .filter(|addr| match self.local_addr {
+                Some(local) => local.is_ipv4() == addr.is_ipv4(),
+                None => true,
+            })
+            .collect();
+
+        if let Some(delay) = self.happy_eyeballs {
+            let candidates = eyeballs::interleave(&candidates);
+            let dial = |addr| self.dial_proxy(addr);
+            return eyeballs::race(candidates, delay, self.timer.as_ref(), dial).await;
+        }
+
+        let mut last_err = Socks5Error::NoMatchingProxyAddress;
+        for addr in candidates {
+            match self.dial_proxy(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = e,
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Connect to the proxy at `addr`, one of the addresses its name
+    /// resolved to.
+    async fn dial_proxy(&self, addr: SocketAddr) -> Result<(TcpStream, SocketAddr), Socks5Error> {
+        debug!("[{}] connecting to proxy at {}", self.log_label(), addr);
+        let started = Instant::now();
+        let connected = self.within(Phase::Connect, async {
+            Ok(match (self.local_addr, &self.device) {
+                (None, None) => TcpStream::connect(addr).await?,
+                (local, device) => connect_from(local, device.as_deref(), addr).await?,
+            })
+        });
+
+        let stream = connected.await.map_err(|e| {
+            debug!(
+                "[{}] connecting to proxy at {} failed: {}",
+                self.log_label(),
+                addr,
+                e
+            );
+            e
+        })?;
+
+        self.record(|m| m.proxy_connected(addr, started.elapsed()));
+        Ok((stream, addr))
+    }
+
+    /// Apply the configured socket options to `stream`.
+    fn apply_socket_options(&self, stream: &TcpStream) -> Result<(), Socks5Error> {
+        if let Some(nodelay) = self.nodelay {
+            if let Err(e) = stream.set_nodelay(nodelay) {
+                if self.strict_socket_options {
+                    return Err(e.into());
+                }
+                debug!("[{}] skipping TCP_NODELAY: {}", self.log_label(), e);
+            }
+        }
+
+        if let Some(idle) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(idle);
+            #[cfg(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "netbsd",
+                windows
+            ))]
+            let keepalive = match self.keepalive_interval {
+                Some(interval) => keepalive.with_interval(interval),
+                None => keepalive,
+            };
+            if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+                if self.strict_socket_options {
+                    return Err(e.into());
+                }
+                debug!("[{}] skipping SO_KEEPALIVE: {}", self.log_label(), e);
+            }
+        }
+
+        if let Some(ttl) = self.ttl {
+            let socket = SockRef::from(stream);
+            let result = stream.peer_addr().and_then(|addr| match addr {
+                SocketAddr::V4(_) => socket.set_ttl_v4(ttl),
+                SocketAddr::V6(_) => socket.set_unicast_hops_v6(ttl),
+            });
+            if let Err(e) = result {
+                if self.strict_socket_options {
+                    return Err(e.into());
+                }
+                debug!("[{}] skipping IP_TTL: {}", self.log_label(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connect to the proxy, apply socket options, and negotiate a
+    /// connection to `target`.
+    async fn connect_target(&self, target: &TargetAddr) -> Result<Socks5Stream, Socks5Error> {
+        let request = protocol::connect_frame(target)?;
+        let connected = self.connect_request(&request, target);
+        in_span!(connected, "socks5_connect", target = %target).await
+    }
+
+    /// Connect to the proxy, apply socket options, and send a prebuilt
+    /// request frame for `target`.
+    async fn connect_request(
+        &self,
+        request: &[u8],
+        target: &TargetAddr,
+    ) -> Result<Socks5Stream, Socks5Error> {
+        let no_auth = self.credentials.is_none()
+            && self.credentials_fn.is_none()
+            && self.provider.is_none()
+            && self.auth_methods.is_empty();
+        if !self.pipelining || !no_auth || self.methods(false)? != [0x00] {
+            let opened = self.open_once().await?;
+            return self.send_over(opened, request, target).await;
+        }
+
+        let mut opened = self.dial().await?;
+        let started = Instant::now();
+        let mut counted = Counted::new(&mut opened.stream);
+        let (method_selection, bound_addr) = match self.read_timeout {
+            Some(duration) => {
+                let mut timed = ReadTimeout::new(&mut counted, self.timer.as_ref(), duration);
+                self.negotiate_pipelined(&mut timed, request).await?
+            }
+            None => self.negotiate_pipelined(&mut counted, request).await?,
+        };
+
+        opened.handshake_bytes_read = counted.read;
+        opened.timings.method_selection = method_selection;
+        opened.timings.request = started.elapsed().saturating_sub(method_selection);
+        Ok(opened.into_stream(target.clone(), bound_addr))
+    }
+
+    /// Send the greeting and the request in a single write, then read
+    /// both answers. Only used when no-auth is the one method offered,
+    /// so the proxy can't take the request for an authentication
+    /// message. Returns how long the method selection took and the
+    /// address the reply reports.
+    async fn negotiate_pipelined<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream: &mut S,
+        request: &[u8],
+    ) -> Result<(Duration, TargetAddr), Socks5Error> {
+        let mut frame = protocol::greeting_frame(&[0x00])?;
+        frame.extend_from_slice(request);
+
+        let started = Instant::now();
+        self.within(Phase::MethodSelection, async {
+            debug!("[{}] pipelining greeting and request", self.log_label());
+            stream.write_all(&frame).await?;
+
+            let mut response = [0u8; 2];
+            stream.read_exact(&mut response).await?;
+            if response[0] != 0x05 {
+                return Err(Socks5Error::UnexpectedResponse);
+            }
+            protocol::check_method(&[0x00], response[1])
+        })
+        .await?;
+        let method_selection = started.elapsed();
+        self.record(|m| m.handshake_completed(method_selection));
+
+        Ok((method_selection, self.reply(stream, None).await?))
+    }
+
+    /// Connect to the proxy and apply socket options.
+    async fn dial(&self) -> Result<Opened, Socks5Error> {
+        let started = Instant::now();
+        let (stream, proxy_addr) = self.connect_proxy().await?;
+        let timings = ConnectTimings {
+            connect: started.elapsed(),
+            ..ConnectTimings::default()
+        };
+        self.apply_socket_options(&stream)?;
+
+        Ok(Opened {
+            stream,
+            proxy_addr,
+            auth_method: 0x00,
+            handshake_bytes_read: 0,
+            timings,
+        })
+    }
+
+    /// Connect to the proxy, apply socket options and run the handshake,
+    /// leaving the stream ready for a request.
+    async fn open_once(&self) -> Result<Opened, Socks5Error> {
+        let opened = self.dial().await?;
+        self.greet(opened).await
+    }
+
+    /// Run the handshake over a connection [`Socks5Config::dial`] made.
+    async fn greet(&self, mut opened: Opened) -> Result<Opened, Socks5Error> {
+        let mut counted = Counted::new(&mut opened.stream);
+        let (auth_method, (method_selection, authentication)) = match self.read_timeout {
+            Some(duration) => {
+                let mut timed = ReadTimeout::new(&mut counted, self.timer.as_ref(), duration);
+                self.handshake(&mut timed).await?
+            }
+            None => self.handshake(&mut counted).await?,
+        };
+
+        opened.handshake_bytes_read = counted.read;
+        opened.auth_method = auth_method;
+        opened.timings.method_selection = method_selection;
+        opened.timings.authentication = authentication;
+        Ok(opened)
+    }
+
+    /// Connect to the proxy, apply socket options and run the handshake,
+    /// leaving the stream ready for a request. Retried, timed and guarded
+    /// by the circuit breaker like a connection attempt.
+    pub(crate) async fn open(&self) -> Result<Opened, Socks5Error> {
+        self.attempt(|| self.open_once()).await
+    }
+
+    /// Send a prebuilt request frame for `target` over a connection
+    /// [`Socks5Config::open`] returned and read the reply.
+    pub(crate) async fn send_over(
+        &self,
+        mut opened: Opened,
+        request: &[u8],
+        target: &TargetAddr,
+    ) -> Result<Socks5Stream, Socks5Error> {
+        let started = Instant::now();
+        let mut counted = Counted::new(&mut opened.stream);
+        let bound_addr = match self.read_timeout {
+            Some(duration) => {
+                let mut timed = ReadTimeout::new(&mut counted, self.timer.as_ref(), duration);
+                self.request(&mut timed, request).await?
+            }
+            None => self.request(&mut counted, request).await?,
+        };
+
+        opened.handshake_bytes_read += counted.read;
+        opened.timings.request = started.elapsed();
+        Ok(opened.into_stream(target.clone(), bound_addr))
+    }
+
+    /// Send a prebuilt request frame over a negotiated stream and read
+    /// the reply, within the reply timeouts.
+    async fn request<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream: &mut S,
+        request: &[u8],
+    ) -> Result<TargetAddr, Socks5Error> {
+        self.reply(stream, Some(request)).await
+    }
+
+    /// Read the reply to a request, sending `request` first unless it
+    /// was already pipelined with the greeting, and return the address
+    /// it reports.
+    async fn reply<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream: &mut S,
+        request: Option<&[u8]>,
+    ) -> Result<TargetAddr, Socks5Error> {
+        let reply = async {
+            if let Some(request) = request {
+                Socks5Client::write_request(stream, request).await?;
+            }
+
+            match self.reply_timeouts {
+                Some((header, rest)) => {
+                    let timer = self.timer.as_ref();
+                    let reply = Socks5Client::read_reply_header(stream);
+                    let reply = timer::timeout(timer, header, Phase::Reply, reply).await?;
+                    let addr = Socks5Client::read_reply_addr(stream, reply[3]);
+                    timer::timeout(timer, rest, Phase::Reply, addr).await
+                }
+                None => Socks5Client::read_reply(stream).await,
+            }
+        };
+
+        let result = self.within(Phase::Reply, reply).await;
+        match &result {
+            Ok(_) => self.record(|m| m.reply(ReplyCode::Succeeded)),
+            Err(Socks5Error::Reply(code)) => self.record(|m| m.reply(*code)),
+            Err(_) => {}
+        }
+
+        match result {
+            Err(Socks5Error::Reply(ReplyCode::Other(rep))) if self.tor_errors => {
+                Err(TorError::from_reply(rep)
+                    .map_or(Socks5Error::from_reply(rep), Socks5Error::Tor))
+            }
+            result => result,
+        }
+    }
+
+    /// Run `future` as the given phase of an attempt: within its
+    /// configured timeout, and with reads aborted by [`ReadTimeout`]
+    /// reported as timeouts of that phase.
+    async fn within<T>(
+        &self,
+        phase: Phase,
+        future: impl Future<Output = Result<T, Socks5Error>>,
+    ) -> Result<T, Socks5Error> {
+        let limit = self.phase_timeouts.iter().find(|(p, _)| *p == phase);
+        let future = in_span!(future, "socks5_phase", phase = ?phase);
+        let result = match limit {
+            Some((_, duration)) => {
+                timer::timeout(self.timer.as_ref(), *duration, phase, future).await
+            }
+            None => future.await,
+        };
+        result.map_err(|e| timed_out(e, phase))
+    }
+
+    /// Run a connection attempt made by `attempt`, retrying it as
+    /// configured.
+    async fn attempt<T, F, Fut>(&self, attempt: F) -> Result<T, Socks5Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Socks5Error>>,
+    {
+        let (max_attempts, mut delay) = self.retry.unwrap_or((1, Duration::ZERO));
+
+        let mut n = 1;
+        loop {
+            match self.attempt_once(attempt()).await {
+                Err(e) if n < max_attempts && is_transient(&e) => {
+                    let wait = match self.retry_jitter {
+                        true => jittered(delay),
+                        false => delay,
+                    };
+                    debug!(
+                        "[{}] attempt {} failed, retrying in {:?}",
+                        self.log_label(),
+                        n,
+                        wait
+                    );
+                    self.timer.sleep(wait).await;
+                    delay = delay.saturating_mul(2);
+                    n += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Run a single connection attempt, applying the circuit breaker and
+    /// the timeout.
+    async fn attempt_once<T>(
+        &self,
+        future: impl Future<Output = Result<T, Socks5Error>>,
+    ) -> Result<T, Socks5Error> {
+        if let Some(breaker) = &self.breaker {
+            breaker.check()?;
+        }
+
+        let future = in_span!(
+            future,
+            "socks5",
+            proxy = %self.proxy_addr,
+            label = self.log_label()
+        );
+        let result = match self.timeout {
+            Some(duration) => {
+                timer::timeout(self.timer.as_ref(), duration, Phase::Total, future).await
+            }
+            None => future.await,
+        };
+
+        if let Some(breaker) = &self.breaker {
+            breaker.record(&result);
+        }
+
+        if let Err(e) = &result {
+            debug!("[{}] proxy request failed: {}", self.log_label(), e);
+            self.record(|m| m.failed(e));
+        }
+
+        result
+    }
+
+    /// Apply the configured hostname policy to `host`.
+    fn check_host(&self, host: &[u8]) -> Result<(), Socks5Error> {
+        if self.strict_hostnames && host.iter().any(|b| b.is_ascii_control()) {
+            return Err(Socks5Error::InvalidInput("host contains control bytes"));
+        }
+
+        Ok(())
+    }
+
+    /// Label used in log messages
+    fn log_label(&self) -> &str {
+        self.label.as_deref().unwrap_or("-")
+    }
+
+    /// Current credentials, asking the provider if there is one
+    async fn creds(&self) -> Result<Option<Credentials>, Socks5Error> {
+        match &self.provider {
+            Some(provider) => provider.0.get_credentials().await.map(Some),
+            None => Ok(self.fixed_creds()),
+        }
+    }
+
+    /// Current credentials, if they are known without a provider
+    fn fixed_creds(&self) -> Option<Credentials> {
+        if self.provider.is_some() {
+            return None;
+        }
+
+        match &self.credentials_fn {
+            Some(f) => {
+                let (username, password) = (f.0)();
+                Some(Credentials::unchecked(username, password))
+            }
+            None => self.credentials.clone(),
+        }
+    }
+
+    /// Methods to offer in the greeting
+    fn methods(&self, has_creds: bool) -> Result<Vec<u8>, Socks5Error> {
+        if let Some(methods) = &self.offered_methods {
+            if methods.is_empty() || methods.len() > 255 {
+                return Err(Socks5Error::InvalidInput("must offer 1 to 255 methods"));
+            }
+
+            if methods
+                .iter()
+                .enumerate()
+                .any(|(i, m)| methods[..i].contains(m))
+            {
+                return Err(Socks5Error::InvalidInput(
+                    "offered methods contain duplicates",
+                ));
+            }
+
+            if !methods.iter().any(|m| self.can_perform(*m, has_creds)) {
+                return Err(Socks5Error::InvalidInput(
+                    "no offered method can be performed",
+                ));
+            }
+
+            return Ok(methods.clone());
+        }
+
+        let mut methods = vec![];
+        if !self.require_auth {
+            methods.push(0x00);
+        }
+        if has_creds {
+            methods.push(0x02);
+        }
+        for method in &self.auth_methods {
+            if !methods.contains(&method.method_byte()) {
+                methods.push(method.method_byte());
+            }
+        }
+
+        if methods.is_empty() {
+            return Err(Socks5Error::InvalidInput("no credentials to require"));
+        }
+
+        Ok(methods)
+    }
+
+    /// Whether we can go through with `method` if the proxy selects it
+    fn can_perform(&self, method: u8, has_creds: bool) -> bool {
+        method == 0x00
+            || (method == 0x02 && has_creds)
+            || self.auth_methods.iter().any(|m| m.method_byte() == method)
+    }
+
+    /// Perform the SOCKS5 handshake, retrying authentication as
+    /// configured. Returns the selected method and how long the method
+    /// selection and the authentication took.
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream: &mut S,
+    ) -> Result<(u8, (Duration, Duration)), Socks5Error> {
+        let creds = self.fixed_creds();
+        if let Some(creds) = &creds {
+            Socks5Client::check_credentials(creds.username(), creds.password())?;
+        }
+        let methods = self.methods(creds.is_some() || self.provider.is_some())?;
+
+        let started = Instant::now();
+        let selected = self
+            .within(Phase::MethodSelection, async {
+                Socks5Client::select_method(stream, &methods).await
+            })
+            .await?;
+        let method_selection = started.elapsed();
+
+        let authenticating = Instant::now();
+        self.within(
+            Phase::Authentication,
+            self.authenticate(stream, selected, creds),
+        )
+        .await?;
+
+        self.record(|m| m.handshake_completed(started.elapsed()));
+        Ok((selected, (method_selection, authenticating.elapsed())))
+    }
+
+    /// Run the authentication method the proxy `selected`.
+    async fn authenticate<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        stream: &mut S,
+        selected: u8,
+        creds: Option<Credentials>,
+    ) -> Result<(), Socks5Error> {
+        if let Some(method) = self
+            .auth_methods
+            .iter()
+            .find(|m| m.method_byte() == selected)
+        {
+            let result = method.authenticate(stream).await;
+            self.record_auth(selected, &result);
+            return result;
+        }
+
+        let mut creds = match (selected, creds) {
+            (0x00, _) => return Ok(()),
+            (0x02, Some(creds)) => creds,
+            (0x02, None) => match self.creds().await? {
+                Some(creds) => creds,
+                None => return Err(Socks5Error::CredentialsRequired),
+            },
+            // Offered through offered_methods, but not something we can do
+            _ => return Err(Socks5Error::NoAcceptableAuthMethods),
+        };
+
+        let mut attempt = 1;
+        loop {
+            let result =
+                Socks5Client::authenticate(stream, &(creds.username(), creds.password())).await;
+            self.record_auth(selected, &result);
+            match result {
+                Err(Socks5Error::AuthenticationFailed) if attempt < self.max_auth_attempts => {
+                    debug!(
+                        "[{}] authentication attempt {} failed, retrying",
+                        self.log_label(),
+                        attempt
+                    );
+                    attempt += 1;
+                    creds = self
+                        .creds()
+                        .await?
+                        .ok_or(Socks5Error::CredentialsRequired)?;
+                }
+                // The proxy closing on us after a failure is still a failure
+                Err(Socks5Error::IoError(e))
+                    if attempt > 1
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+                        ) =>
+                {
+                    return Err(Socks5Error::AuthenticationFailed)
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connect through the configured proxy to the given [`TargetAddr`].
+    /// Domain targets are resolved as the [`Socks5Config::resolution`]
+    /// policy says, locally by default.
+    pub async fn connect(&self, target: &TargetAddr) -> Result<TcpStream, Socks5Error> {
+        self.connect_stream(target)
+            .await
+            .map(Socks5Stream::into_inner)
+    }
+
+    /// Like [`Socks5Config::connect`], but returns a [`Socks5Stream`]
+    /// carrying [`Socks5ConnectInfo`], byte counters and the idle timeout.
+    pub async fn connect_stream(&self, target: &TargetAddr) -> Result<Socks5Stream, Socks5Error> {
+        let (domain, port) = match target {
+            TargetAddr::Ip(_) => return self.attempt(|| self.connect_target(target)).await,
+            TargetAddr::Domain(domain, port) => (domain, *port),
+        };
+
+        match self.resolution {
+            Resolution::Local => self.resolved_stream(domain, port).await,
+            Resolution::Remote => self.domain_stream(domain, port).await,
+            Resolution::RemotePreferred => match self.domain_stream(domain, port).await {
+                Err(Socks5Error::Reply(ReplyCode::AddressTypeNotSupported)) => {
+                    debug!(
+                        "[{}] proxy can't resolve hostnames, resolving {} locally",
+                        self.log_label(),
+                        domain
+                    );
+                    self.resolved_stream(domain, port).await
+                }
+                result => result,
+            },
+        }
+    }
+
+    /// Connect through the configured proxy to each of `targets`, with
+    /// at most `limit` (at least one) connection attempts in flight at a
+    /// time. Returns the result for each target, in the order of
+    /// `targets`. Every attempt is made like [`Socks5Config::connect`].
+    pub async fn connect_many(
+        &self,
+        targets: &[TargetAddr],
+        limit: usize,
+    ) -> Vec<Result<TcpStream, Socks5Error>> {
+        let attempts = targets.iter().map(|target| self.connect(target));
+        batch::buffered(attempts, limit).await
+    }
+
+    /// Connect through the configured proxy to whichever of `targets`,
+    /// the addresses of a single host, answers first. The attempts are
+    /// raced as set with [`Socks5Config::happy_eyeballs`], with the delay
+    /// RFC 8305 recommends if none is set, each over its own connection
+    /// to the proxy.
+    pub async fn connect_any(&self, targets: &[SocketAddr]) -> Result<TcpStream, Socks5Error> {
+        if targets.is_empty() {
+            return Err(Socks5Error::InvalidInput("no target addresses"));
+        }
+
+        let delay = self.happy_eyeballs.unwrap_or(CONNECTION_ATTEMPT_DELAY);
+        let stream = self
+            .attempt(|| {
+                let connect =
+                    |addr| async move { self.connect_target(&TargetAddr::Ip(addr)).await };
+                eyeballs::race(
+                    eyeballs::interleave(targets),
+                    delay,
+                    self.timer.as_ref(),
+                    connect,
+                )
+            })
+            .await?;
+        Ok(stream.into_inner())
+    }
+
+    /// Connect through the configured proxy to the given host and port,
+    /// letting the proxy do the DNS resolution.
+    pub async fn connect_with_domain(
+        &self,
+        domain: &str,
+        port: u16,
+    ) -> Result<TcpStream, Socks5Error> {
+        self.domain_stream(domain, port)
+            .await
+            .map(Socks5Stream::into_inner)
+    }
+
+    /// [`Socks5Config::connect_with_domain`], keeping the stream wrapper
+    pub(crate) async fn domain_stream(
+        &self,
+        domain: &str,
+        port: u16,
+    ) -> Result<Socks5Stream, Socks5Error> {
+        let target = TargetAddr::Domain(domain.to_string(), port);
+        self.attempt(|| self.connect_target(&target)).await
+    }
+
+    /// Resolve `host` locally and connect through the configured proxy to
+    /// the first resulting address. IP literals are used as they are.
+    /// Fails with [`Socks5Error::LocalResolutionDisabled`] for hostnames
+    /// under [`Resolution::Remote`].
+    /// See [`Socks5Config::domain_fallback`] for proxies that can't handle
+    /// the resolved address type.
+    pub async fn connect_resolved(&self, host: &str, port: u16) -> Result<TcpStream, Socks5Error> {
+        self.resolved_stream(host, port)
+            .await
+            .map(Socks5Stream::into_inner)
+    }
+
+    /// [`Socks5Config::connect_resolved`], keeping the stream wrapper
+    async fn resolved_stream(&self, host: &str, port: u16) -> Result<Socks5Stream, Socks5Error> {
+        let ip = host.parse::<IpAddr>();
+        if ip.is_err() && self.resolution == Resolution::Remote {
+            return Err(Socks5Error::LocalResolutionDisabled);
+        }
+
+        let result = self
+            .attempt(|| async {
+                let addr = match ip {
+                    Ok(ip) => SocketAddr::new(ip, port),
+                    Err(_) => {
+                        let host = idna::to_ascii(host)?;
+                        match async_net::resolve((&*host, port)).await?.into_iter().next() {
+                            Some(addr) => addr,
+                            None => return Err(io::Error::from(io::ErrorKind::NotFound).into()),
+                        }
+                    }
+                };
+
+                self.connect_target(&TargetAddr::Ip(addr)).await
+            })
+            .await;
+
+        match result {
+            Err(Socks5Error::Reply(ReplyCode::AddressTypeNotSupported))
+                if self.domain_fallback && ip.is_err() =>
+            {
+                self.domain_stream(host, port).await
+            }
+            result => result,
+        }
+    }
+
+    /// Connect through the configured proxy to the given raw host and
+    /// port, sending `host` in the domain field as it is.
+    /// See [`Socks5Client::connect_with_raw_host`] and
+    /// [`Socks5Config::strict_hostnames`].
+    pub async fn connect_with_raw_host(
+        &self,
+        host: &[u8],
+        port: u16,
+    ) -> Result<TcpStream, Socks5Error> {
+        self.check_host(host)?;
+        let request = protocol::encode_raw_host_request(host, port)?;
+        let target = TargetAddr::Domain(String::from_utf8_lossy(host).into_owned(), port);
+        let stream = self
+            .attempt(|| self.connect_request(&request, &target))
+            .await?;
+        Ok(stream.into_inner())
+    }
+
+    /// Validate the configuration and `target` without any network IO:
+    /// the proxy address must have the `host:port` form, credentials must
+    /// fit the RFC 1929 fields, and the target must be encodable under the
+    /// configured hostname policy. Returns the first problem found.
+    pub fn dry_run(&self, target: &TargetAddr) -> Result<(), Socks5Error> {
+        match self.proxy_addr.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') && !host.starts_with('[') => {
+                return Err(Socks5Error::InvalidInput(
+                    "IPv6 proxy address must be in brackets",
+                ))
+            }
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => return Err(Socks5Error::InvalidInput("proxy address must be host:port")),
+        }
+
+        let creds = self.fixed_creds();
+        if let Some(creds) = &creds {
+            Socks5Client::check_credentials(creds.username(), creds.password())?;
+        }
+        self.methods(creds.is_some() || self.provider.is_some())?;
+
+        if let TargetAddr::Domain(domain, _) = target {
+            self.check_host(domain.as_bytes())?;
+        }
+
+        Socks5Client::build_connect_request(target)?;
+        Ok(())
+    }
+
+    /// Ask the configured proxy to relay UDP datagrams for us, with the
+    /// same timeout, circuit breaker, and authentication handling as TCP
+    /// connections. See [`Socks5Client::udp_associate_from`] for the
+    /// meaning of `local_addr` and `source_addr`. The returned socket
+    /// carries this configuration's [`Socks5Config::label`].
+    pub async fn udp_associate(
+        &self,
+        local_addr: Option<SocketAddr>,
+        source_addr: Option<SocketAddr>,
+    ) -> Result<Socks5UdpSocket, Socks5Error> {
+        let socket = self
+            .attempt(|| async {
+                let opened = self.dial().await?;
+                let socket = Socks5UdpSocket::bind(&opened.stream, local_addr).await?;
+                let mut control = self.greet(opened).await?.stream;
+                let request = Socks5UdpSocket::associate_frame(source_addr)?;
+                let relay = match self.read_timeout {
+                    Some(duration) => {
+                        let timer = self.timer.as_ref();
+                        let mut timed = ReadTimeout::new(&mut control, timer, duration);
+                        self.request(&mut timed, &request).await?
+                    }
+                    None => self.request(&mut control, &request).await?,
+                };
+                Socks5UdpSocket::relay_through(control, socket, relay).await
+            })
+            .await?;
+
+        let socket = socket.with_label(self.label.clone());
+        Ok(match self.udp_fragment_size {
+            Some(max_payload) => socket.with_fragmentation(max_payload),
+            None => socket,
+        })
+    }
+}
+
+/// Connection to the proxy ready for a request, with what is known
+/// about it so far
+#[derive(Debug)]
+pub(crate) struct Opened {
+    pub(crate) stream: TcpStream,
+    proxy_addr: SocketAddr,
+    auth_method: u8,
+    handshake_bytes_read: usize,
+    timings: ConnectTimings,
+}
+
+impl Opened {
+    /// Wrap the stream once the proxy connected it to `target`.
+    fn into_stream(self, target: TargetAddr, bound_addr: TargetAddr) -> Socks5Stream {
+        let info = Socks5ConnectInfo {
+            connection_id: ConnectionId::next(),
+            proxy_connected_ip: self.proxy_addr.ip(),
+            proxy_addr: self.proxy_addr,
+            target,
+            handshake_bytes_read: self.handshake_bytes_read,
+            auth_method: self.auth_method,
+            bound_addr,
+            timings: self.timings,
+        };
+        Socks5Stream::new(self.stream, info)
+    }
+}
+
+/// Turn reads aborted by [`ReadTimeout`] into [`Socks5Error::Timeout`]
+fn timed_out(e: Socks5Error, phase: Phase) -> Socks5Error {
+    match e {
+        Socks5Error::IoError(e) if e.kind() == io::ErrorKind::TimedOut => {
+            Socks5Error::Timeout(phase)
+        }
+        e => e,
+    }
+}
+
+/// Whether a failed attempt is worth retrying
+fn is_transient(e: &Socks5Error) -> bool {
+    match e {
+        Socks5Error::Timeout(_) => true,
+        Socks5Error::Reply(code) => matches!(
+            code,
+            ReplyCode::GeneralFailure
+                | ReplyCode::NetworkUnreachable
+                | ReplyCode::HostUnreachable
+                | ReplyCode::TtlExpired
+                | ReplyCode::ConnectionRefused
+        ),
+        Socks5Error::IoError(e) => e.kind() == io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+/// Random duration between half of `delay` and all of it
+fn jittered(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let half = delay / 2;
+    half + half.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// Connect to `addr` from a socket bound to `local` and/or `device`.
+/// The connect is nonblocking, so dropping the future, as timeouts do,
+/// abandons it.
+pub(crate) async fn connect_from(
+    local: Option<SocketAddr>,
+    device: Option<&str>,
+    addr: SocketAddr,
+) -> io::Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    if let Some(device) = device {
+        socket.bind_device(Some(device.as_bytes()))?;
+    }
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    let _ = device;
+    if let Some(local) = local {
+        socket.bind(&local.into())?;
+    }
+
+    socket.set_nonblocking(true)?;
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        #[cfg(unix)]
+        Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+        Err(e) => return Err(e),
+    }
+
+    // Writable once connected or failed, take_error tells which
+    let stream = Async::new(std::net::TcpStream::from(socket))?;
+    stream.writable().await?;
+    if let Some(e) = stream.get_ref().take_error()? {
+        return Err(e);
+    }
+
+    Ok(TcpStream::from(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tor_defaults() {
+        let config = Socks5Client::tor();
+        assert_eq!(config.proxy_addr, "127.0.0.1:9050");
+        assert_eq!(config.resolution, Resolution::Remote);
+        assert_eq!(config.timeout, Some(Duration::from_secs(60)));
+        assert!(config.tor_errors);
+        // Streams share circuits unless an isolation token is passed
+        assert!(config.credentials.is_none());
+
+        let config = Socks5Client::tor().isolation(&IsolationToken::new());
+        assert!(config.credentials.is_some());
+    }
+}
@@ -0,0 +1,157 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::{Socks5Client, Socks5Error};
+
+/// Boxed future returned by [`CredentialsProvider::get_credentials`]
+pub type CredentialsFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Credentials, Socks5Error>> + Send + 'a>>;
+
+/// Source of credentials queried each time a proxy asks for username and
+/// password authentication, for credentials that are short-lived or
+/// rotate, such as passwords issued by a secrets store.
+///
+/// Async closures returning `Result<Credentials, Socks5Error>` implement
+/// it, as do fixed [`Credentials`]. An error aborts the connection
+/// attempt with it.
+pub trait CredentialsProvider: Send + Sync {
+    /// Fetch the credentials to authenticate with.
+    fn get_credentials(&self) -> CredentialsFuture<'_>;
+}
+
+impl<F, Fut> CredentialsProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Credentials, Socks5Error>> + Send + 'static,
+{
+    fn get_credentials(&self) -> CredentialsFuture<'_> {
+        Box::pin(self())
+    }
+}
+
+impl<T: CredentialsProvider + ?Sized> CredentialsProvider for Arc<T> {
+    fn get_credentials(&self) -> CredentialsFuture<'_> {
+        (**self).get_credentials()
+    }
+}
+
+impl CredentialsProvider for Credentials {
+    fn get_credentials(&self) -> CredentialsFuture<'_> {
+        let credentials = self.clone();
+        Box::pin(async move { Ok(credentials) })
+    }
+}
+
+/// Owned username and password for RFC 1929 authentication, for callers
+/// that need to keep credentials around, e.g. in a [`ConnectRequest`]
+/// moved into a spawned task.
+///
+/// Both are checked to fit the protocol when the credentials are
+/// created: they must be 1 to 255 bytes long. They don't have to be
+/// UTF-8. With the `zeroize` feature, their memory is wiped on drop.
+///
+/// [`ConnectRequest`]: crate::ConnectRequest
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    username: Vec<u8>,
+    password: Vec<u8>,
+}
+
+impl Credentials {
+    /// Create credentials from a username and password, failing with
+    /// [`Socks5Error::InvalidInput`] if either is empty or longer than
+    /// 255 bytes.
+    pub fn new(
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<Self, Socks5Error> {
+        Self::from_bytes(username.into(), password.into())
+    }
+
+    /// Create credentials from raw bytes, for proxies expecting a
+    /// username or password that isn't UTF-8. Checked like
+    /// [`Credentials::new`].
+    pub fn from_bytes(
+        username: impl Into<Vec<u8>>,
+        password: impl Into<Vec<u8>>,
+    ) -> Result<Self, Socks5Error> {
+        let credentials = Self::unchecked(username, password);
+        Socks5Client::check_credentials(&credentials.username, &credentials.password)?;
+        Ok(credentials)
+    }
+
+    /// Create credentials without checking them, for configurations that
+    /// report invalid credentials when connecting.
+    pub(crate) fn unchecked(username: impl Into<Vec<u8>>, password: impl Into<Vec<u8>>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Username to authenticate with
+    pub fn username(&self) -> &[u8] {
+        &self.username
+    }
+
+    /// Password to authenticate with
+    pub fn password(&self) -> &[u8] {
+        &self.password
+    }
+}
+
+impl TryFrom<(&str, &str)> for Credentials {
+    type Error = Socks5Error;
+
+    fn try_from((username, password): (&str, &str)) -> Result<Self, Self::Error> {
+        Self::new(username, password)
+    }
+}
+
+impl TryFrom<(String, String)> for Credentials {
+    type Error = Socks5Error;
+
+    fn try_from((username, password): (String, String)) -> Result<Self, Self::Error> {
+        Self::new(username, password)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+
+        self.username.zeroize();
+        self.password.zeroize();
+    }
+}
+
+// Keep the password out of logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &String::from_utf8_lossy(&self.username))
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
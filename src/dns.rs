@@ -0,0 +1,279 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use futures_lite::future;
+
+use crate::timer::{self, AsyncIoTimer, Timer};
+use crate::trace::debug;
+use crate::{idna, Phase, Socks5Error, Socks5UdpSocket, TargetAddr};
+
+/// How long [`Socks5Resolver`] waits for an answer by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times [`Socks5Resolver`] sends a query by default
+const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// Largest DNS message over UDP without EDNS
+const MAX_MESSAGE: usize = 512;
+
+/// Record type of an IPv4 address
+const TYPE_A: u16 = 1;
+/// Record type of an IPv6 address
+const TYPE_AAAA: u16 = 28;
+/// The Internet class
+const CLASS_IN: u16 = 1;
+
+/// Resolves names by sending DNS queries to a chosen server through
+/// a SOCKS5 UDP association.
+///
+/// Useful behind networks that only allow traffic through the proxy,
+/// for protocols that need real IP addresses rather than a domain
+/// forwarded to the proxy. Only A and AAAA lookups are supported.
+///
+/// ```no_run
+/// # use async_socks5::{Socks5Client, Socks5Resolver};
+/// # async fn example() -> Result<(), async_socks5::Socks5Error> {
+/// let socket = Socks5Client::udp_associate("127.0.0.1:1080", None, None).await?;
+/// let resolver = Socks5Resolver::new(socket, "9.9.9.9:53".parse().unwrap());
+/// let addrs = resolver.lookup_ip("example.org").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Socks5Resolver {
+    socket: Socks5UdpSocket,
+    server: SocketAddr,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl Socks5Resolver {
+    /// Query the DNS server at `server` through `socket`.
+    pub fn new(socket: Socks5UdpSocket, server: SocketAddr) -> Self {
+        Self {
+            socket,
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            attempts: DEFAULT_ATTEMPTS,
+        }
+    }
+
+    /// How long to wait for the answer to each query (default 5 seconds).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send each query up to `attempts` times (default 3), evenly spread
+    /// over the timeout, as datagrams get lost on the way.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Return the underlying UDP socket.
+    pub fn into_inner(self) -> Socks5UdpSocket {
+        self.socket
+    }
+
+    /// Look up the IPv4 addresses of `name`.
+    ///
+    /// A name that does not exist fails with [`Socks5Error::Dns`] and
+    /// RCODE 3.
+    pub async fn lookup_ipv4(&self, name: &str) -> Result<Vec<Ipv4Addr>, Socks5Error> {
+        let addrs = self.query(name, TYPE_A).await?;
+        Ok(addrs
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect())
+    }
+
+    /// Look up the IPv6 addresses of `name`.
+    pub async fn lookup_ipv6(&self, name: &str) -> Result<Vec<Ipv6Addr>, Socks5Error> {
+        let addrs = self.query(name, TYPE_AAAA).await?;
+        Ok(addrs
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V6(ip) => Some(ip),
+                IpAddr::V4(_) => None,
+            })
+            .collect())
+    }
+
+    /// Look up both the IPv4 and IPv6 addresses of `name`, IPv4 first.
+    pub async fn lookup_ip(&self, name: &str) -> Result<Vec<IpAddr>, Socks5Error> {
+        let mut addrs = self.query(name, TYPE_A).await?;
+        addrs.extend(self.query(name, TYPE_AAAA).await?);
+        Ok(addrs)
+    }
+
+    /// Send a query and wait for its answer, dropping datagrams from
+    /// other sources or with another ID. The query is sent again when
+    /// no answer came in its share of the timeout.
+    async fn query(&self, name: &str, qtype: u16) -> Result<Vec<IpAddr>, Socks5Error> {
+        let id = query_id();
+        let query = encode_query(id, name, qtype)?;
+        let server = TargetAddr::Ip(self.server);
+        let interval = self.timeout / self.attempts;
+
+        timer::timeout(&AsyncIoTimer, self.timeout, Phase::Target, async {
+            let mut buf = [0u8; MAX_MESSAGE];
+            let mut sent = 0;
+            loop {
+                debug!("sending DNS query for {} to {}", name, self.server);
+                self.socket.send_to(&query, &server).await?;
+                sent += 1;
+
+                let answer = async {
+                    loop {
+                        let (n, from) = self.socket.recv_from(&mut buf).await?;
+                        if from != server {
+                            continue;
+                        }
+                        if let Some(addrs) = decode_response(id, qtype, &buf[..n])? {
+                            return Ok(Some(addrs));
+                        }
+                    }
+                };
+                let retransmit = async {
+                    match sent < self.attempts {
+                        true => AsyncIoTimer.sleep(interval).await,
+                        false => future::pending().await,
+                    }
+                    Ok::<_, Socks5Error>(None)
+                };
+
+                if let Some(addrs) = future::or(answer, retransmit).await? {
+                    return Ok(addrs);
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// Pick a fresh, unpredictable query ID.
+fn query_id() -> u16 {
+    RandomState::new().hash_one(Instant::now()) as u16
+}
+
+/// Encode a recursive query for `name` and `qtype`.
+fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, Socks5Error> {
+    let name = idna::to_ascii(name)?;
+    let name = name.strip_suffix('.').unwrap_or(&name);
+    if name.is_empty() || name.len() > 253 {
+        return Err(Socks5Error::InvalidInput("invalid DNS name"));
+    }
+
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Socks5Error::InvalidInput("invalid DNS name"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Parse the answer to query `id`, returning `None` if `message` is not
+/// one. Records of other types, such as the CNAMEs leading to the
+/// addresses, are skipped. A truncated answer fails with
+/// [`Socks5Error::DnsTruncated`], as its records may be incomplete.
+fn decode_response(
+    id: u16,
+    qtype: u16,
+    message: &[u8],
+) -> Result<Option<Vec<IpAddr>>, Socks5Error> {
+    if message.len() < 12 || message[..2] != id.to_be_bytes() || message[2] & 0x80 == 0 {
+        return Ok(None);
+    }
+
+    if message[2] & 0x02 != 0 {
+        return Err(Socks5Error::DnsTruncated);
+    }
+
+    let rcode = message[3] & 0x0f;
+    if rcode != 0 {
+        return Err(Socks5Error::Dns(rcode));
+    }
+
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(message, pos)?;
+        let fixed = message
+            .get(pos..pos + 10)
+            .ok_or(Socks5Error::UnexpectedResponse)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        pos += 10;
+
+        let data = message
+            .get(pos..pos + len)
+            .ok_or(Socks5Error::UnexpectedResponse)?;
+        pos += len;
+
+        if class != CLASS_IN || rtype != qtype {
+            continue;
+        }
+        match (rtype, <[u8; 4]>::try_from(data), <[u8; 16]>::try_from(data)) {
+            (TYPE_A, Ok(octets), _) => addrs.push(IpAddr::from(octets)),
+            (TYPE_AAAA, _, Ok(octets)) => addrs.push(IpAddr::from(octets)),
+            _ => return Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+
+    Ok(Some(addrs))
+}
+
+/// Return the position following the name at `pos`. A compression
+/// pointer ends the name.
+fn skip_name(message: &[u8], mut pos: usize) -> Result<usize, Socks5Error> {
+    loop {
+        let len = *message.get(pos).ok_or(Socks5Error::UnexpectedResponse)?;
+        match len {
+            0 => return Ok(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len if len & 0xc0 != 0 => return Err(Socks5Error::UnexpectedResponse),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::env;
+use std::net::IpAddr;
+
+use async_net::TcpStream;
+
+use crate::rules::in_network;
+use crate::{Socks5Client, Socks5Config, Socks5Error, TargetAddr};
+
+impl Socks5Config {
+    /// Create a configuration from the proxy URL in the `ALL_PROXY` or
+    /// `all_proxy` environment variable, see [`Socks5Config::from_url`].
+    /// Returns `None` if neither is set to a non-empty value.
+    pub fn from_env() -> Result<Option<Self>, Socks5Error> {
+        match var(&["ALL_PROXY", "all_proxy"]) {
+            Some(url) => Socks5Config::from_url(&url).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Socks5Client {
+    /// Connect to `target` the way curl would: through the proxy at
+    /// `proxy`, or the one configured in the environment if that is
+    /// `None`, unless `NO_PROXY` tells to bypass it for this target.
+    /// Without any proxy the target is connected to directly.
+    pub async fn connect_env(
+        proxy: Option<&str>,
+        target: &TargetAddr,
+    ) -> Result<TcpStream, Socks5Error> {
+        let config = match proxy {
+            Some(url) => Some(Socks5Config::from_url(url)?),
+            None => Socks5Config::from_env()?,
+        };
+
+        match config {
+            Some(config) if !NoProxy::from_env().matches(target) => config.connect(target).await,
+            _ => {
+                crate::trace::debug!("connecting to {} directly", target);
+                let stream = match target {
+                    TargetAddr::Ip(addr) => TcpStream::connect(addr).await?,
+                    TargetAddr::Domain(host, port) => {
+                        TcpStream::connect((host.as_str(), *port)).await?
+                    }
+                };
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// List of hosts to reach without a proxy, in the format of the
+/// `NO_PROXY` environment variable curl and many other tools follow.
+///
+/// Entries are separated by commas. A hostname matches itself and its
+/// subdomains, with an optional leading dot, an IP address matches
+/// itself and `addr/prefix_len` a whole network. A single `*` matches
+/// every host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoProxy {
+    all: bool,
+    entries: Vec<Entry>,
+}
+
+/// Single entry of a [`NoProxy`] list
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Entry {
+    Network(IpAddr, u8),
+    Domain(String),
+}
+
+impl NoProxy {
+    /// Parse a comma separated list of hosts.
+    pub fn new(list: &str) -> Self {
+        let mut no_proxy = Self::default();
+
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            if entry == "*" {
+                no_proxy.all = true;
+                continue;
+            }
+
+            let entry = entry.trim_start_matches("*.").trim_start_matches('.');
+            let (addr, prefix_len) = match entry.split_once('/') {
+                Some((addr, len)) => (addr, len.parse().ok()),
+                None => (entry, None),
+            };
+
+            let addr = addr.trim_start_matches('[').trim_end_matches(']');
+            no_proxy.entries.push(match addr.parse::<IpAddr>() {
+                Ok(ip) => {
+                    let width = if ip.is_ipv4() { 32 } else { 128 };
+                    Entry::Network(ip, prefix_len.unwrap_or(width).min(width))
+                }
+                Err(_) => Entry::Domain(entry.to_ascii_lowercase()),
+            });
+        }
+
+        no_proxy
+    }
+
+    /// Read the list from the `NO_PROXY` or `no_proxy` environment
+    /// variable, empty if neither is set.
+    pub fn from_env() -> Self {
+        Self::new(&var(&["NO_PROXY", "no_proxy"]).unwrap_or_default())
+    }
+
+    /// Whether `target` should be reached without the proxy
+    pub fn matches(&self, target: &TargetAddr) -> bool {
+        if self.all {
+            return true;
+        }
+
+        let (ip, host) = match target {
+            TargetAddr::Ip(addr) => (Some(addr.ip()), None),
+            TargetAddr::Domain(host, _) => (host.parse().ok(), Some(host)),
+        };
+
+        self.entries.iter().any(|entry| match (entry, ip, host) {
+            (Entry::Network(net, len), Some(ip), _) => in_network(ip, *net, *len),
+            (Entry::Domain(domain), _, Some(host)) => {
+                let host = host.trim_end_matches('.').to_ascii_lowercase();
+                host == *domain || host.ends_with(&format!(".{}", domain))
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Value of the first of `names` set to a non-empty value
+fn var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
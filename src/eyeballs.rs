@@ -0,0 +1,114 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Connection racing in the style of Happy Eyeballs (RFC 8305).
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures_lite::future;
+
+use crate::{Sleep, Socks5Error, Timer};
+
+/// Delay between starting two connection attempts recommended by RFC 8305
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Order `addrs` so IPv6 and IPv4 addresses alternate, starting with the
+/// family of the first address and keeping the order within a family.
+pub(crate) fn interleave(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut first, mut second): (Vec<_>, Vec<_>) =
+        addrs.iter().partition(|addr| addr.is_ipv6() == first_v6);
+    first.reverse();
+    second.reverse();
+
+    let mut ordered = Vec::with_capacity(addrs.len());
+    while let Some(addr) = first.pop() {
+        ordered.push(addr);
+        ordered.extend(second.pop());
+    }
+    ordered.extend(second.into_iter().rev());
+    ordered
+}
+
+/// Run `connect` for each of `candidates`, starting the next attempt
+/// after `delay` or as soon as the previous one failed, and return the
+/// first success. The attempts still running are dropped then. Fails
+/// with the last error if every attempt fails, or
+/// [`Socks5Error::NoMatchingProxyAddress`] without candidates.
+pub(crate) async fn race<T, F, Fut>(
+    candidates: Vec<SocketAddr>,
+    delay: Duration,
+    timer: &dyn Timer,
+    connect: F,
+) -> Result<T, Socks5Error>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, Socks5Error>>,
+{
+    let mut pending = candidates.into_iter();
+    let mut running: Vec<Pin<Box<Fut>>> = Vec::new();
+    let mut next_start: Option<Sleep> = None;
+    let mut last_err = Socks5Error::NoMatchingProxyAddress;
+
+    future::poll_fn(|cx| loop {
+        let stagger_done = match &mut next_start {
+            Some(sleep) => sleep.as_mut().poll(cx).is_ready(),
+            None => true,
+        };
+        if running.is_empty() || stagger_done {
+            if let Some(addr) = pending.next() {
+                running.push(Box::pin(connect(addr)));
+                next_start = Some(timer.sleep(delay));
+                continue;
+            }
+            next_start = None;
+        }
+
+        let mut failed = false;
+        let mut i = 0;
+        while i < running.len() {
+            match running[i].as_mut().poll(cx) {
+                Poll::Ready(Ok(value)) => return Poll::Ready(Ok(value)),
+                Poll::Ready(Err(e)) => {
+                    last_err = e;
+                    drop(running.swap_remove(i));
+                    failed = true;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        if running.is_empty() && pending.len() == 0 {
+            let err = std::mem::replace(&mut last_err, Socks5Error::NoMatchingProxyAddress);
+            return Poll::Ready(Err(err));
+        }
+
+        // A failure lets the next attempt start right away
+        if failed {
+            next_start = None;
+            continue;
+        }
+
+        return Poll::Pending;
+    })
+    .await
+}
@@ -0,0 +1,105 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Adaptor exchanging frames prefixed with their length as a big-endian
+/// u32 over an underlying stream.
+#[derive(Debug)]
+pub struct Framed<S> {
+    io: S,
+    max_frame: usize,
+}
+
+impl<S> Framed<S> {
+    /// Wrap `io`, refusing to send or receive frames longer than
+    /// `max_frame` bytes.
+    pub fn new(io: S, max_frame: usize) -> Self {
+        Self { io, max_frame }
+    }
+
+    /// Maximum frame length accepted in either direction
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.io
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.io
+    }
+
+    /// Consume the adaptor and return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.io
+    }
+
+    fn check_len(&self, len: usize) -> io::Result<()> {
+        if len > self.max_frame || len > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame of {} bytes exceeds maximum of {}",
+                    len, self.max_frame
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Framed<S> {
+    /// Write a single frame and flush the stream.
+    pub async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.check_len(frame.len())?;
+
+        self.io
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .await?;
+        self.io.write_all(frame).await?;
+        self.io.flush().await
+    }
+
+    /// Read a single frame. Returns `None` if the stream was closed
+    /// cleanly on a frame boundary.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut prefix = [0u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.io.read(&mut prefix[filled..]).await? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+
+        let len = u32::from_be_bytes(prefix) as usize;
+        self.check_len(len)?;
+
+        let mut frame = vec![0u8; len];
+        self.io.read_exact(&mut frame).await?;
+        Ok(Some(frame))
+    }
+}
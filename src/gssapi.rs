@@ -0,0 +1,241 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Mutex;
+
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{AuthFuture, AuthMethod, AuthStream, Socks5Error};
+
+/// Message type of a context establishment token
+const MTYP_AUTH: u8 = 0x01;
+
+/// Message type of a protection level negotiation message
+const MTYP_PROTECTION: u8 = 0x02;
+
+/// Message type of an abort
+const MTYP_ABORT: u8 = 0xff;
+
+/// GSS-API security context driving the RFC 1961 exchange.
+///
+/// This crate doesn't link a GSS-API library; implement this trait on
+/// top of one, e.g. the `libgssapi` crate with the proxy's service name.
+pub trait GssapiContext: fmt::Debug + Send {
+    /// Feed the token received from the proxy, `None` on the first call,
+    /// to `gss_init_sec_context` and return the token to send, if any.
+    fn step(&mut self, input: Option<&[u8]>) -> Result<Option<Vec<u8>>, Socks5Error>;
+
+    /// Whether the context is fully established
+    fn is_established(&self) -> bool;
+
+    /// Protect `data` with `gss_wrap`, encrypting it if `encrypt` is set.
+    fn wrap(&mut self, data: &[u8], encrypt: bool) -> Result<Vec<u8>, Socks5Error>;
+
+    /// Verify and decode a token protected by the proxy with `gss_wrap`.
+    fn unwrap(&mut self, token: &[u8]) -> Result<Vec<u8>, Socks5Error>;
+}
+
+/// Per-message protection level negotiated after authenticating
+/// (RFC 1961, section 4)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProtectionLevel {
+    /// Integrity protection of every message
+    Integrity,
+    /// Integrity and confidentiality protection
+    Confidentiality,
+    /// Protection selected per message
+    Selective,
+}
+
+impl TryFrom<u8> for ProtectionLevel {
+    type Error = Socks5Error;
+
+    fn try_from(level: u8) -> Result<Self, Self::Error> {
+        match level {
+            0x01 => Ok(Self::Integrity),
+            0x02 => Ok(Self::Confidentiality),
+            0x03 => Ok(Self::Selective),
+            _ => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+}
+
+impl From<ProtectionLevel> for u8 {
+    fn from(level: ProtectionLevel) -> Self {
+        match level {
+            ProtectionLevel::Integrity => 0x01,
+            ProtectionLevel::Confidentiality => 0x02,
+            ProtectionLevel::Selective => 0x03,
+        }
+    }
+}
+
+/// Security context established with the proxy, with the protection
+/// level the proxy picked. Traffic on the connection must be protected
+/// at that level with [`GssapiContext::wrap`] and [`GssapiContext::unwrap`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct GssapiSession {
+    /// The established context
+    pub context: Box<dyn GssapiContext>,
+    /// Protection level the proxy picked
+    pub level: ProtectionLevel,
+}
+
+/// Builds a fresh [`GssapiContext`] for each connection
+type ContextFactory = dyn Fn() -> Box<dyn GssapiContext> + Send + Sync;
+
+/// GSS-API authentication (method 0x01), registered with
+/// [`Socks5Config::auth_method`].
+///
+/// Each connection authenticates with its own context from `factory`.
+/// Once the context is established, the protection level is negotiated:
+/// we ask for `level` and the proxy answers with the level it picked.
+/// The resulting [`GssapiSession`] is then available from
+/// [`GssapiAuth::take_session`]. When connections authenticate
+/// concurrently, use one `GssapiAuth` per connection to tell their
+/// sessions apart.
+///
+/// [`Socks5Config::auth_method`]: crate::Socks5Config::auth_method
+pub struct GssapiAuth {
+    factory: Box<ContextFactory>,
+    level: ProtectionLevel,
+    session: Mutex<Option<GssapiSession>>,
+}
+
+impl fmt::Debug for GssapiAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GssapiAuth")
+            .field("level", &self.level)
+            .field("session", &self.session)
+            .finish()
+    }
+}
+
+impl GssapiAuth {
+    /// Authenticate with contexts built by `factory`, asking for
+    /// protection `level`.
+    pub fn new(
+        factory: impl Fn() -> Box<dyn GssapiContext> + Send + Sync + 'static,
+        level: ProtectionLevel,
+    ) -> Self {
+        Self {
+            factory: Box::new(factory),
+            level,
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Protection level the proxy picked, once authenticated
+    pub fn negotiated_level(&self) -> Option<ProtectionLevel> {
+        self.session.lock().unwrap().as_ref().map(|s| s.level)
+    }
+
+    /// Take the session of the last connection that authenticated
+    pub fn take_session(&self) -> Option<GssapiSession> {
+        self.session.lock().unwrap().take()
+    }
+
+    /// Run the context establishment and protection level negotiation.
+    async fn negotiate(
+        &self,
+        stream: &mut dyn AuthStream,
+        context: &mut dyn GssapiContext,
+    ) -> Result<ProtectionLevel, Socks5Error> {
+        let mut token = context.step(None)?;
+        loop {
+            match token {
+                Some(token) => write_message(stream, MTYP_AUTH, &token).await?,
+                None => return Err(Socks5Error::InvalidInput("GSS-API context has no token")),
+            }
+
+            let reply = read_message(stream, MTYP_AUTH).await?;
+            token = context.step(Some(&reply))?;
+            if context.is_established() {
+                break;
+            }
+        }
+
+        // A final token for the proxy doesn't get an answer
+        if let Some(token) = token {
+            write_message(stream, MTYP_AUTH, &token).await?;
+        }
+
+        let request = context.wrap(&[self.level.into()], false)?;
+        write_message(stream, MTYP_PROTECTION, &request).await?;
+
+        let reply = read_message(stream, MTYP_PROTECTION).await?;
+        match context.unwrap(&reply)?.as_slice() {
+            [level] => ProtectionLevel::try_from(*level),
+            _ => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+}
+
+impl AuthMethod for GssapiAuth {
+    fn method_byte(&self) -> u8 {
+        0x01
+    }
+
+    fn authenticate<'a>(&'a self, stream: &'a mut dyn AuthStream) -> AuthFuture<'a> {
+        Box::pin(async move {
+            // Owned by this attempt, so a cancelled one leaves nothing behind
+            let mut context = (self.factory)();
+            let level = self.negotiate(stream, context.as_mut()).await?;
+            *self.session.lock().unwrap() = Some(GssapiSession { context, level });
+            Ok(())
+        })
+    }
+}
+
+/// Send an RFC 1961 message: version, type, length and token.
+async fn write_message(
+    stream: &mut dyn AuthStream,
+    mtyp: u8,
+    token: &[u8],
+) -> Result<(), Socks5Error> {
+    let len = u16::try_from(token.len())
+        .map_err(|_| Socks5Error::InvalidInput("GSS-API token is longer than 65535 bytes"))?;
+
+    let mut message = vec![0x01, mtyp];
+    message.extend_from_slice(&len.to_be_bytes());
+    message.extend_from_slice(token);
+    stream.write_all(&message).await?;
+    Ok(())
+}
+
+/// Read an RFC 1961 message of type `mtyp` and return its token. An
+/// abort from the proxy fails with [`Socks5Error::AuthenticationFailed`].
+async fn read_message(stream: &mut dyn AuthStream, mtyp: u8) -> Result<Vec<u8>, Socks5Error> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+
+    match header {
+        [0x01, MTYP_ABORT] => return Err(Socks5Error::AuthenticationFailed),
+        [0x01, t] if t == mtyp => {}
+        _ => return Err(Socks5Error::UnexpectedResponse),
+    }
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut token = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut token).await?;
+    Ok(token)
+}
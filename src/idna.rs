@@ -0,0 +1,139 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Conversion of international domain names to the ASCII form sent to
+//! the proxy (RFC 3490 ToASCII with the punycode of RFC 3492). Labels are
+//! lowercased rather than fully UTS #46 mapped, which covers the names
+//! people actually type.
+
+use std::borrow::Cow;
+
+use crate::Socks5Error;
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+/// Convert every non-ASCII label of `domain` to `xn--` and its punycode.
+/// ASCII domains are returned as they are.
+pub(crate) fn to_ascii(domain: &str) -> Result<Cow<'_, str>, Socks5Error> {
+    if domain.is_ascii() {
+        return Ok(Cow::Borrowed(domain));
+    }
+
+    let mut ascii = String::with_capacity(domain.len() * 2);
+    for (i, label) in domain.split('.').enumerate() {
+        if i > 0 {
+            ascii.push('.');
+        }
+
+        if label.is_ascii() {
+            ascii.push_str(label);
+            continue;
+        }
+
+        let label: Vec<char> = label.chars().flat_map(char::to_lowercase).collect();
+        ascii.push_str("xn--");
+        encode(&label, &mut ascii)
+            .ok_or(Socks5Error::InvalidInput("domain label can't be encoded"))?;
+    }
+
+    Ok(Cow::Owned(ascii))
+}
+
+/// Append the punycode of `input` to `output`, `None` on overflow.
+fn encode(input: &[char], output: &mut String) -> Option<()> {
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+
+    output.extend(input.iter().filter(|c| c.is_ascii()));
+    let basic = input.iter().filter(|c| c.is_ascii()).count() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut handled = basic;
+    while (handled as usize) < input.len() {
+        // Smallest code point not handled yet
+        let m = input.iter().map(|c| *c as u32).filter(|c| *c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+
+        for c in input.iter().map(|c| *c as u32) {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        n = n.checked_add(1)?;
+    }
+
+    Some(())
+}
+
+/// Bias adaptation function, RFC 3492 section 6.1
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
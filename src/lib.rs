@@ -16,26 +16,232 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::convert::TryInto;
-use std::net::{IpAddr, SocketAddr};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_net::TcpStream;
-use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[cfg(feature = "async-std")]
+pub mod async_std;
+
+mod audit;
+pub use audit::{ClientSummary, ServerObserver};
+
+mod auth;
+pub use auth::{AuthFuture, AuthMethod, AuthStream};
+
+mod batch;
+
+mod bind;
+pub use bind::Socks5Listener;
+
+mod breaker;
+
+mod chain;
+
+mod command;
+pub use command::{CommandOptions, CommandReply};
+
+mod config;
+pub use config::{IpVersion, Resolution, Socks5Config};
+
+mod credentials;
+pub use credentials::{Credentials, CredentialsFuture, CredentialsProvider};
+
+mod env;
+pub use env::NoProxy;
+
+mod dns;
+pub use dns::Socks5Resolver;
+
+mod eyeballs;
+pub use eyeballs::CONNECTION_ATTEMPT_DELAY;
+
+mod framed;
+pub use framed::Framed;
+
+#[cfg(feature = "gssapi")]
+mod gssapi;
+#[cfg(feature = "gssapi")]
+pub use gssapi::{GssapiAuth, GssapiContext, GssapiSession, ProtectionLevel};
+
+mod idna;
+
+mod metrics;
+pub use metrics::Socks5Metrics;
+
+mod pool;
+pub use pool::Socks5Pool;
+
+mod probe;
+pub use probe::ProbeReport;
+
+pub mod protocol;
+
+mod proxy;
+pub use proxy::Proxy;
+
+mod proxy_pool;
+pub use proxy_pool::{ProxyPool, Selection};
+
+mod reconnect;
+pub use reconnect::ReconnectingStream;
+
+mod relay;
+pub use relay::{relay, relay_with_buffer_size, DEFAULT_RELAY_BUFFER_SIZE, MAX_RELAY_BUFFER_SIZE};
+
+mod request;
+pub use request::{ConnectOptions, ConnectRequest};
+
+mod rules;
+pub use rules::{Action, Rule, Rules};
+
+mod server;
+pub use server::{
+    InMemoryVerifier, Serve, Socks5Server, Socks5ServerListener, TargetResolver, UserPassVerifier,
+    VerifyFuture,
+};
+
+mod socks4;
+pub use socks4::Socks4Client;
+
+#[cfg(feature = "socks6")]
+mod socks6;
+#[cfg(feature = "socks6")]
+pub use socks6::Socks6Client;
+
+mod stream;
+use stream::Counted;
+pub use stream::{ConnectTimings, ConnectionId, IdleTimeout, Socks5ConnectInfo, Socks5Stream};
+use trace::{debug, in_span};
+
+mod udp;
+pub use udp::Socks5UdpSocket;
+
+#[cfg(feature = "sink")]
+mod udp_framed;
+#[cfg(feature = "sink")]
+pub use udp_framed::Socks5UdpFramed;
+
+#[cfg(unix)]
+mod unix;
+
+mod url;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+mod throttle;
+pub use throttle::Throttled;
+
+mod tor;
+pub use tor::{IsolationToken, TorError};
+
+mod trace;
+
+mod timer;
+pub use timer::{AsyncIoTimer, Sleep, Timer};
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use futures_rustls::rustls;
+
+#[cfg(feature = "tls-probe")]
+mod tls_probe;
+
+/// Size of the buffer the banner is read into by
+/// [`Socks5Client::connect_expect_banner`]
+const BANNER_BUFFER_SIZE: usize = 4096;
 
 /// Socks5 error types
+///
+/// More variants may be added in minor releases, so matches need a
+/// wildcard arm. I/O errors keep the original [`std::io::Error`], which
+/// [`std::error::Error::source`] returns, and timeouts report the
+/// [`Phase`] they happened in.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum Socks5Error {
     HandshakeFailed,
     ConnectionFailed,
+    Reply(ReplyCode),
+    /// Tor extended reply code, see [`Socks5Config::tor_errors`]
+    Tor(TorError),
     UnexpectedResponse,
     UnsupportedAddressType,
     AuthenticationFailed,
-    IoError(std::io::ErrorKind),
+    NoAcceptableAuthMethods,
+    CredentialsRequired,
+    LocalResolutionDisabled,
+    NoMatchingProxyAddress,
+    /// HTTP CONNECT proxy replied with this status, see [`Proxy`]
+    HttpStatus(u16),
+    /// DNS server answered with this RCODE, see [`Socks5Resolver`]
+    Dns(u8),
+    /// DNS answer didn't fit in a UDP message and was truncated
+    DnsTruncated,
+    InvalidInput(&'static str),
+    /// Gave up waiting, in the given phase
+    Timeout(Phase),
+    CircuitOpen,
+    /// I/O error, shared so the error stays cheap to clone
+    IoError(Arc<std::io::Error>),
+}
+
+impl Socks5Error {
+    /// Map a nonzero REP code from a proxy reply to an error.
+    fn from_reply(rep: u8) -> Self {
+        Self::Reply(ReplyCode::from(rep))
+    }
+}
+
+// I/O errors compare and hash by their kind, as `io::Error` itself
+// implements neither. Variants carrying data must be listed explicitly.
+impl PartialEq for Socks5Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Reply(a), Self::Reply(b)) => a == b,
+            (Self::Tor(a), Self::Tor(b)) => a == b,
+            (Self::InvalidInput(a), Self::InvalidInput(b)) => a == b,
+            (Self::Timeout(a), Self::Timeout(b)) => a == b,
+            (Self::HttpStatus(a), Self::HttpStatus(b)) => a == b,
+            (Self::Dns(a), Self::Dns(b)) => a == b,
+            (Self::IoError(a), Self::IoError(b)) => a.kind() == b.kind(),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Socks5Error {}
+
+impl Hash for Socks5Error {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Reply(code) => code.hash(state),
+            Self::Tor(err) => err.hash(state),
+            Self::InvalidInput(reason) => reason.hash(state),
+            Self::Timeout(phase) => phase.hash(state),
+            Self::HttpStatus(status) => status.hash(state),
+            Self::Dns(rcode) => rcode.hash(state),
+            Self::IoError(e) => e.kind().hash(state),
+            _ => {}
+        }
+    }
 }
 
 impl From<std::io::Error> for Socks5Error {
     fn from(err: std::io::Error) -> Self {
-        Socks5Error::IoError(err.kind())
+        Socks5Error::IoError(Arc::new(err))
     }
 }
 
@@ -44,26 +250,66 @@ impl std::fmt::Display for Socks5Error {
         match self {
             Self::HandshakeFailed => write!(f, "handhake failed"),
             Self::ConnectionFailed => write!(f, "connection failed"),
+            Self::Reply(code) => write!(f, "proxy replied: {}", code),
+            Self::Tor(err) => write!(f, "tor replied: {}", err),
             Self::UnexpectedResponse => write!(f, "unexpected response"),
             Self::UnsupportedAddressType => write!(f, "unsupported address type"),
             Self::AuthenticationFailed => write!(f, "authentication failed"),
+            Self::NoAcceptableAuthMethods => write!(f, "no acceptable authentication methods"),
+            Self::CredentialsRequired => write!(
+                f,
+                "proxy requires authentication but no credentials were provided"
+            ),
+            Self::LocalResolutionDisabled => write!(f, "local DNS resolution is disabled"),
+            Self::NoMatchingProxyAddress => {
+                write!(f, "no proxy address matches the requested IP version")
+            }
+            Self::HttpStatus(status) => write!(f, "HTTP proxy replied with status {}", status),
+            Self::Dns(rcode) => write!(f, "DNS server replied with RCODE {}", rcode),
+            Self::DnsTruncated => write!(f, "DNS answer was truncated"),
+            Self::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            Self::Timeout(phase) => write!(f, "timed out {}", phase),
+            Self::CircuitOpen => write!(f, "circuit open after repeated authentication failures"),
             Self::IoError(e) => write!(f, "{}", e),
         }
     }
 }
 
-impl std::error::Error for Socks5Error {}
+impl std::error::Error for Socks5Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::IoError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 /// Supported address types for the SOCKS5 client
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum AddrType {
     IPv4,
     DomainName,
     IPv6,
 }
 
-impl AddrType {
-    fn as_byte(&self) -> u8 {
-        match self {
+impl TryFrom<u8> for AddrType {
+    type Error = Socks5Error;
+
+    /// Parse an ATYP byte, failing with
+    /// [`Socks5Error::UnsupportedAddressType`] for unknown types.
+    fn try_from(atyp: u8) -> Result<Self, Self::Error> {
+        match atyp {
+            0x01 => Ok(AddrType::IPv4),
+            0x03 => Ok(AddrType::DomainName),
+            0x04 => Ok(AddrType::IPv6),
+            _ => Err(Socks5Error::UnsupportedAddressType),
+        }
+    }
+}
+
+impl From<AddrType> for u8 {
+    fn from(atyp: AddrType) -> Self {
+        match atyp {
             AddrType::IPv4 => 0x01,
             AddrType::DomainName => 0x03,
             AddrType::IPv6 => 0x04,
@@ -71,29 +317,255 @@ impl AddrType {
     }
 }
 
+/// Stage of a connection a [`Socks5Error::Timeout`] happened in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Establishing the TCP connection to the proxy
+    Connect,
+    /// Waiting for the proxy to select an authentication method
+    MethodSelection,
+    /// Running the selected authentication method
+    Authentication,
+    /// Waiting for the reply to a request
+    Reply,
+    /// Waiting for data from the target, such as a banner
+    Target,
+    /// The deadline for a whole connection attempt
+    Total,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connecting to the proxy"),
+            Self::MethodSelection => write!(f, "waiting for method selection"),
+            Self::Authentication => write!(f, "authenticating"),
+            Self::Reply => write!(f, "waiting for the reply"),
+            Self::Target => write!(f, "waiting for the target"),
+            Self::Total => write!(f, "connecting through the proxy"),
+        }
+    }
+}
+
+/// CMD field of a SOCKS5 request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    Connect,
+    Bind,
+    UdpAssociate,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Socks5Error;
+
+    /// Parse a CMD byte, failing with [`Socks5Error::InvalidInput`] for
+    /// unknown commands.
+    fn try_from(cmd: u8) -> Result<Self, Self::Error> {
+        match cmd {
+            0x01 => Ok(Command::Connect),
+            0x02 => Ok(Command::Bind),
+            0x03 => Ok(Command::UdpAssociate),
+            _ => Err(Socks5Error::InvalidInput("unsupported command")),
+        }
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(cmd: Command) -> Self {
+        match cmd {
+            Command::Connect => 0x01,
+            Command::Bind => 0x02,
+            Command::UdpAssociate => 0x03,
+        }
+    }
+}
+
+/// REP field of a SOCKS5 reply (RFC 1928, section 6)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReplyCode {
+    Succeeded,
+    GeneralFailure,
+    NotAllowed,
+    NetworkUnreachable,
+    HostUnreachable,
+    ConnectionRefused,
+    TtlExpired,
+    CommandNotSupported,
+    AddressTypeNotSupported,
+    /// A code not assigned by RFC 1928
+    Other(u8),
+}
+
+impl From<u8> for ReplyCode {
+    fn from(rep: u8) -> Self {
+        match rep {
+            0x00 => Self::Succeeded,
+            0x01 => Self::GeneralFailure,
+            0x02 => Self::NotAllowed,
+            0x03 => Self::NetworkUnreachable,
+            0x04 => Self::HostUnreachable,
+            0x05 => Self::ConnectionRefused,
+            0x06 => Self::TtlExpired,
+            0x07 => Self::CommandNotSupported,
+            0x08 => Self::AddressTypeNotSupported,
+            rep => Self::Other(rep),
+        }
+    }
+}
+
+impl From<ReplyCode> for u8 {
+    fn from(code: ReplyCode) -> Self {
+        match code {
+            ReplyCode::Succeeded => 0x00,
+            ReplyCode::GeneralFailure => 0x01,
+            ReplyCode::NotAllowed => 0x02,
+            ReplyCode::NetworkUnreachable => 0x03,
+            ReplyCode::HostUnreachable => 0x04,
+            ReplyCode::ConnectionRefused => 0x05,
+            ReplyCode::TtlExpired => 0x06,
+            ReplyCode::CommandNotSupported => 0x07,
+            ReplyCode::AddressTypeNotSupported => 0x08,
+            ReplyCode::Other(rep) => rep,
+        }
+    }
+}
+
+impl fmt::Display for ReplyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Succeeded => write!(f, "succeeded"),
+            Self::GeneralFailure => write!(f, "general SOCKS server failure"),
+            Self::NotAllowed => write!(f, "connection not allowed by ruleset"),
+            Self::NetworkUnreachable => write!(f, "network unreachable"),
+            Self::HostUnreachable => write!(f, "host unreachable"),
+            Self::ConnectionRefused => write!(f, "connection refused"),
+            Self::TtlExpired => write!(f, "TTL expired"),
+            Self::CommandNotSupported => write!(f, "command not supported"),
+            Self::AddressTypeNotSupported => write!(f, "address type not supported"),
+            Self::Other(rep) => write!(f, "unassigned reply code {:#04x}", rep),
+        }
+    }
+}
+
+/// Target address of a SOCKS5 connection
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TargetAddr {
+    /// An already resolved socket address
+    Ip(SocketAddr),
+    /// A hostname and port, to be resolved by the proxy
+    Domain(String, u16),
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(addr) => write!(f, "{}", addr),
+            Self::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
+
+impl TargetAddr {
+    /// ATYP this address is encoded with
+    pub fn addr_type(&self) -> AddrType {
+        match self {
+            Self::Ip(SocketAddr::V4(_)) => AddrType::IPv4,
+            Self::Ip(SocketAddr::V6(_)) => AddrType::IPv6,
+            Self::Domain(..) => AddrType::DomainName,
+        }
+    }
+}
+
+impl From<SocketAddr> for TargetAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Ip(addr)
+    }
+}
+
+impl From<(&str, u16)> for TargetAddr {
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse() {
+            Ok(ip) => Self::Ip(SocketAddr::new(ip, port)),
+            Err(_) => Self::Domain(host.to_string(), port),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = Socks5Error;
+
+    /// Parse a `host:port` string, where `host` is an IP address (IPv6
+    /// in brackets) or a hostname.
+    fn from_str(addr: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = addr.parse() {
+            return Ok(Self::Ip(addr));
+        }
+
+        match addr
+            .rsplit_once(':')
+            .map(|(host, port)| (host, port.parse()))
+        {
+            Some((host, Ok(port))) if !host.is_empty() && !host.contains(':') => {
+                Ok(Self::Domain(host.to_string(), port))
+            }
+            _ => Err(Socks5Error::InvalidInput("address must be host:port")),
+        }
+    }
+}
+
 /// Socks5 client instance
 pub struct Socks5Client;
 
 impl Socks5Client {
     /// Internal authentication method to authenticate to the proxy with
     /// given credentials (username and password).
-    async fn authenticate(
-        stream: &mut TcpStream,
-        credentials: &(&str, &str),
-    ) -> Result<(), Socks5Error> {
-        let mut request = vec![0x01]; // Version
-        request.push(credentials.0.len() as u8);
-        request.extend_from_slice(credentials.0.as_bytes());
-        request.push(credentials.1.len() as u8);
-        request.extend_from_slice(credentials.1.as_bytes());
-
+    pub(crate) async fn authenticate<S, U, P>(
+        stream: &mut S,
+        credentials: &(U, P),
+    ) -> Result<(), Socks5Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        U: AsRef<[u8]>,
+        P: AsRef<[u8]>,
+    {
+        let (username, password) = (credentials.0.as_ref(), credentials.1.as_ref());
+        Socks5Client::check_credentials(username, password)?;
+
+        // Never the password
+        debug!("authenticating as {}", String::from_utf8_lossy(username));
+        let request = protocol::auth_frame(username, password);
         stream.write_all(&request).await?;
 
         let mut response = [0u8; 2];
         stream.read_exact(&mut response).await?;
+        protocol::check_auth_status(response)
+    }
+
+    /// Check that credentials fit the one-byte RFC 1929 length fields,
+    /// which also don't allow empty values.
+    pub(crate) fn check_credentials(
+        username: impl AsRef<[u8]>,
+        password: impl AsRef<[u8]>,
+    ) -> Result<(), Socks5Error> {
+        let (username, password) = (username.as_ref(), password.as_ref());
+        if username.is_empty() {
+            return Err(Socks5Error::InvalidInput("username is empty"));
+        }
+
+        if password.is_empty() {
+            return Err(Socks5Error::InvalidInput("password is empty"));
+        }
+
+        if username.len() > 255 {
+            return Err(Socks5Error::InvalidInput(
+                "username is longer than 255 bytes",
+            ));
+        }
 
-        if response[1] != 0x00 {
-            return Err(Socks5Error::AuthenticationFailed);
+        if password.len() > 255 {
+            return Err(Socks5Error::InvalidInput(
+                "password is longer than 255 bytes",
+            ));
         }
 
         Ok(())
@@ -101,35 +573,290 @@ impl Socks5Client {
 
     /// Internal handshake method to initialize the connection with a
     /// SOCKS5 server.
-    async fn handshake(
-        stream: &mut TcpStream,
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
         credentials: &Option<(&str, &str)>,
-    ) -> Result<(), Socks5Error> {
-        let greeting = if credentials.is_some() {
-            vec![0x05, 0x02, 0x00, 0x02]
+    ) -> Result<u8, Socks5Error> {
+        let (method, _) = Socks5Client::timed_handshake(stream, credentials).await?;
+        Ok(method)
+    }
+
+    /// [`Socks5Client::handshake`], also returning how long the method
+    /// selection and the authentication took.
+    async fn timed_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        credentials: &Option<(&str, &str)>,
+    ) -> Result<(u8, (Duration, Duration)), Socks5Error> {
+        // Fail before the greeting rather than after the method selection
+        if let Some((username, password)) = credentials {
+            Socks5Client::check_credentials(username, password)?;
+        }
+
+        let methods: &[u8] = if credentials.is_some() {
+            &[0x00, 0x02]
         } else {
-            vec![0x05, 0x01, 0x00]
+            &[0x00]
         };
+        let started = Instant::now();
+        let method = Socks5Client::select_method(stream, methods).await?;
+        let method_selection = started.elapsed();
+
+        let started = Instant::now();
+        if let (0x02, Some(creds)) = (method, credentials) {
+            Socks5Client::authenticate(stream, creds).await?;
+        }
 
+        Ok((method, (method_selection, started.elapsed())))
+    }
+
+    /// Internal method sending a greeting offering `methods` and returning
+    /// the method the server selected. Selecting a method we didn't offer
+    /// is a protocol violation (and possibly a downgrade attempt), so it
+    /// fails with [`Socks5Error::UnexpectedResponse`].
+    pub(crate) async fn select_method<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        methods: &[u8],
+    ) -> Result<u8, Socks5Error> {
+        debug!("offering authentication methods {:02x?}", methods);
+        let greeting = protocol::greeting_frame(methods)?;
         stream.write_all(&greeting).await?;
 
         // Read the handshake response
         let mut response = [0u8; 2];
         stream.read_exact(&mut response).await?;
+        if response[0] != 0x05 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        debug!("proxy selected method {:#04x}", response[1]);
+        protocol::check_method(methods, response[1])
+    }
+
+    /// Internal method performing the handshake and the CONNECT request
+    /// to `target` over an already established stream to the proxy.
+    pub(crate) async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        target: &TargetAddr,
+        credentials: &Option<(&str, &str)>,
+    ) -> Result<TargetAddr, Socks5Error> {
+        let request = protocol::connect_frame(target)?;
+        Socks5Client::negotiate_request(stream, &request, credentials).await
+    }
+
+    /// Internal method performing the handshake and sending an already
+    /// built request frame over an established stream to the proxy.
+    /// Returns the bound address from the reply.
+    pub(crate) async fn negotiate_request<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        request: &[u8],
+        credentials: &Option<(&str, &str)>,
+    ) -> Result<TargetAddr, Socks5Error> {
+        // Perform SOCKS5 handshake
+        Socks5Client::handshake(stream, credentials).await?;
+        Socks5Client::send_request(stream, request).await
+    }
+
+    /// Internal method sending a request frame on a negotiated stream and
+    /// reading the reply. Exactly the reply is consumed, whatever the type
+    /// of its BND.ADDR, so tunnel bytes following it are left in the
+    /// stream. Returns the bound address.
+    pub(crate) async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        request: &[u8],
+    ) -> Result<TargetAddr, Socks5Error> {
+        Socks5Client::write_request(stream, request).await?;
+        Socks5Client::read_reply(stream).await
+    }
+
+    /// Internal method writing a request frame to the proxy.
+    pub(crate) async fn write_request<S: AsyncWrite + Unpin>(
+        stream: &mut S,
+        request: &[u8],
+    ) -> Result<(), Socks5Error> {
+        debug!("sending request with command {:#04x}", request[1]);
+        stream.write_all(request).await?;
+        Ok(())
+    }
+
+    /// Build the CONNECT request frame that would be sent to the proxy
+    /// for `target`, without doing any network IO.
+    /// Fails with [`Socks5Error::InvalidInput`] if a domain target isn't
+    /// 1 to 255 bytes long.
+    pub fn build_connect_request(target: &TargetAddr) -> Result<Vec<u8>, Socks5Error> {
+        protocol::encode_request(Command::Connect.into(), target)
+    }
+
+    /// Internal method reading a reply from the proxy and returning the
+    /// bound address it reports.
+    pub(crate) async fn read_reply<S: AsyncRead + Unpin>(
+        stream: &mut S,
+    ) -> Result<TargetAddr, Socks5Error> {
+        let header = Socks5Client::read_reply_header(stream).await?;
+        Socks5Client::read_reply_addr(stream, header[3]).await
+    }
 
-        match response[1] {
-            0x00 => {} // No authentication needed
-            0x02 => {
-                if let Some(creds) = credentials {
-                    Socks5Client::authenticate(stream, creds).await?;
-                } else {
-                    return Err(Socks5Error::AuthenticationFailed);
+    /// Internal method reading the VER, REP, RSV and ATYP bytes of a
+    /// reply, failing right away on a wrong VER or RSV, or a nonzero REP.
+    pub(crate) async fn read_reply_header<S: AsyncRead + Unpin>(
+        stream: &mut S,
+    ) -> Result<[u8; 4], Socks5Error> {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+
+        if header[0] != 0x05 || header[2] != 0x00 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        debug!("proxy replied: {}", ReplyCode::from(header[1]));
+        if header[1] != 0x00 {
+            return Err(Socks5Error::from_reply(header[1]));
+        }
+
+        Ok(header)
+    }
+
+    /// Internal method reading the BND.ADDR and BND.PORT that follow a
+    /// reply header with the given ATYP.
+    pub(crate) async fn read_reply_addr<S: AsyncRead + Unpin>(
+        stream: &mut S,
+        atyp: u8,
+    ) -> Result<TargetAddr, Socks5Error> {
+        // ATYP, the domain length byte and the longest domain and port
+        let mut addr = [0u8; 1 + 1 + 255 + 2];
+        addr[0] = atyp;
+
+        let (start, rest) = match AddrType::try_from(atyp)? {
+            AddrType::IPv4 => (1, 4 + 2),
+            AddrType::IPv6 => (1, 16 + 2),
+            AddrType::DomainName => {
+                stream.read_exact(&mut addr[1..2]).await?;
+                if addr[1] == 0 {
+                    return Err(Socks5Error::UnexpectedResponse);
                 }
+                (2, addr[1] as usize + 2)
             }
-            _ => return Err(Socks5Error::HandshakeFailed),
+        };
+
+        stream.read_exact(&mut addr[start..start + rest]).await?;
+
+        Ok(protocol::decode_addr(&addr[..start + rest])?.0)
+    }
+
+    /// Connect through the given SOCKS5 proxy to the given [`TargetAddr`].
+    /// Optionally, provide credentials in the form of username and password.
+    /// Returns a [`Socks5Stream`] carrying [`Socks5ConnectInfo`] about the
+    /// established connection.
+    pub async fn connect_stream(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5Stream, Socks5Error> {
+        let connected = async {
+            debug!("connecting to proxy at {}", proxy_addr);
+            let started = Instant::now();
+            let stream = TcpStream::connect(proxy_addr).await?;
+            let connect = started.elapsed();
+            let proxy_addr = stream.peer_addr()?;
+            Socks5Client::negotiate_stream(stream, proxy_addr, connect, target, credentials).await
+        };
+        in_span!(connected, "socks5", proxy = proxy_addr, target = %target).await
+    }
+
+    /// Internal method running the negotiation for `target` over a
+    /// `stream` to the proxy at `proxy_addr`, which took `connect` to
+    /// establish, and wrapping it in a [`Socks5Stream`].
+    pub(crate) async fn negotiate_stream<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        proxy_addr: SocketAddr,
+        connect: Duration,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5Stream<S>, Socks5Error> {
+        let request = protocol::connect_frame(target)?;
+
+        let mut counted = Counted::new(&mut stream);
+        let (auth_method, (method_selection, authentication)) =
+            Socks5Client::timed_handshake(&mut counted, &credentials).await?;
+        let started = Instant::now();
+        let bound_addr = Socks5Client::send_request(&mut counted, &request).await?;
+        let timings = ConnectTimings {
+            connect,
+            method_selection,
+            authentication,
+            request: started.elapsed(),
+        };
+
+        let info = Socks5ConnectInfo {
+            connection_id: ConnectionId::next(),
+            proxy_connected_ip: proxy_addr.ip(),
+            proxy_addr,
+            target: target.clone(),
+            handshake_bytes_read: counted.read,
+            auth_method,
+            bound_addr,
+            timings,
+        };
+        Ok(Socks5Stream::new(stream, info))
+    }
+
+    /// Perform the full SOCKS5 negotiation for `target` over a stream to
+    /// the proxy the caller already has, such as one made by a custom
+    /// connector, a pre-authenticated tunnel, or a unix socket bridge.
+    /// Optionally, provide credentials in the form of username and password.
+    /// Returns the stream, ready to carry data to `target`.
+    pub async fn handshake_over<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<S, Socks5Error> {
+        let negotiated = Socks5Client::negotiate(&mut stream, target, &credentials);
+        in_span!(negotiated, "socks5", target = %target).await?;
+        Ok(stream)
+    }
+
+    /// Connect through the given SOCKS5 proxy to a target that speaks
+    /// first (SMTP, FTP, ...) and wait up to `timeout` for its banner.
+    /// Returns the stream along with the bytes of the first read, or
+    /// [`Socks5Error::Timeout`] with [`Phase::Target`] if the target stays
+    /// silent. The timeout
+    /// only covers the banner, not establishing the connection.
+    pub async fn connect_expect_banner(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+        timeout: Duration,
+    ) -> Result<(Socks5Stream, Vec<u8>), Socks5Error> {
+        let mut stream = Socks5Client::connect_stream(proxy_addr, target, credentials).await?;
+
+        let mut banner = vec![0u8; BANNER_BUFFER_SIZE];
+        let n = timer::timeout(&AsyncIoTimer, timeout, Phase::Target, async {
+            Ok(stream.read(&mut banner).await?)
+        })
+        .await?;
+
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
         }
 
-        Ok(())
+        banner.truncate(n);
+        Ok((stream, banner))
+    }
+
+    /// Connect through the given SOCKS5 proxy to the given target, which
+    /// is anything convertible into a [`TargetAddr`]. Domain targets are
+    /// resolved by the proxy.
+    /// Optionally, provide credentials in the form of username and password.
+    /// Returns a [`TcpStream`] on success and [`Socks5Error`] in case anything
+    /// fails during the connection.
+    pub async fn connect_target(
+        proxy_addr: &str,
+        target: impl Into<TargetAddr>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream, Socks5Error> {
+        let mut request = ConnectRequest::new(proxy_addr, target.into());
+        request.credentials = credentials.map(Credentials::try_from).transpose()?;
+        request.options.force_remote_dns = true;
+        Socks5Client::execute(&request).await
     }
 
     /// Connect through the given SOCKS5 proxy to the given [`SocketAddr`].
@@ -141,37 +868,25 @@ impl Socks5Client {
         target_addr: &SocketAddr,
         credentials: Option<(&str, &str)>,
     ) -> Result<TcpStream, Socks5Error> {
-        let mut stream = TcpStream::connect(proxy_addr).await?;
-
-        // Perform SOCKS5 handshake
-        Socks5Client::handshake(&mut stream, &credentials).await?;
-
-        // Build the request
-        let mut request = vec![0x05, 0x01, 0x00];
-
-        match target_addr.ip() {
-            IpAddr::V4(ip) => {
-                request.push(AddrType::IPv4.as_byte());
-                request.extend_from_slice(&ip.octets());
-            }
-            IpAddr::V6(ip) => {
-                request.push(AddrType::IPv6.as_byte());
-                request.extend_from_slice(&ip.octets());
-            }
-        }
-
-        request.extend_from_slice(&target_addr.port().to_be_bytes());
-
-        stream.write_all(&request).await?;
-
-        let mut response = vec![0u8; 10];
-        stream.read_exact(&mut response).await?;
-
-        if response[1] != 0x00 {
-            return Err(Socks5Error::ConnectionFailed);
-        }
+        Socks5Client::connect_target(proxy_addr, *target_addr, credentials).await
+    }
 
-        Ok(stream)
+    /// Connect through the given SOCKS5 proxy to the given [`TargetAddr`],
+    /// failing with [`Socks5Error::Timeout`] if connecting to the proxy,
+    /// the handshake and authentication, and the reply together take
+    /// longer than `timeout`. Domain targets are resolved by the proxy.
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_with_timeout(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+        timeout: Duration,
+    ) -> Result<TcpStream, Socks5Error> {
+        let mut request = ConnectRequest::new(proxy_addr, target.clone());
+        request.credentials = credentials.map(Credentials::try_from).transpose()?;
+        request.timeout = Some(timeout);
+        request.options.force_remote_dns = true;
+        Socks5Client::execute(&request).await
     }
 
     /// Connect through the given SOCKS5 proxy to the given host and port.
@@ -185,31 +900,42 @@ impl Socks5Client {
         port: u16,
         credentials: Option<(&str, &str)>,
     ) -> Result<TcpStream, Socks5Error> {
-        let mut stream = TcpStream::connect(proxy_addr).await?;
-
-        // Perform SOCKS5 handshake
-        Socks5Client::handshake(&mut stream, &credentials).await?;
-
-        // Build the request
-        let mut request = vec![
-            0x05,
-            0x01,
-            0x00,
-            AddrType::DomainName.as_byte(),
-            domain.len().try_into().unwrap(),
-        ];
-        request.extend_from_slice(domain.as_bytes());
-        request.extend_from_slice(&port.to_be_bytes());
+        let target = TargetAddr::Domain(domain.into(), port);
+        Socks5Client::connect_target(proxy_addr, target, credentials).await
+    }
 
-        stream.write_all(&request).await?;
+    /// Like [`Socks5Client::connect_with_domain`], but also returns the
+    /// BND.ADDR and BND.PORT of the reply, which usually tell what the
+    /// proxy resolved the domain to and connected from. See
+    /// [`TargetAddr::addr_type`] for the address type the proxy used.
+    pub async fn connect_with_domain_full(
+        proxy_addr: &str,
+        domain: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<(TcpStream, TargetAddr), Socks5Error> {
+        let target = TargetAddr::Domain(domain.into(), port);
 
-        let mut response = vec![0u8; 10];
-        stream.read_exact(&mut response).await?;
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        let bound_addr = Socks5Client::negotiate(&mut stream, &target, &credentials).await?;
+        Ok((stream, bound_addr))
+    }
 
-        if response[1] != 0x00 {
-            return Err(Socks5Error::ConnectionFailed);
+    /// Connect through the given SOCKS5 proxy to the given raw host and
+    /// port. The SOCKS5 domain field is just bytes, and some proxies use it
+    /// for routing tokens that aren't valid hostnames or even UTF-8; this
+    /// sends `host` as it is, after checking it is 1 to 255 bytes long.
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_with_raw_host(
+        proxy_addr: &str,
+        host: &[u8],
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream, Socks5Error> {
+        let mut config = Socks5Config::new(proxy_addr);
+        if let Some(credentials) = credentials {
+            config = config.with_credentials(Credentials::try_from(credentials)?);
         }
-
-        Ok(stream)
+        config.connect_with_raw_host(host, port).await
     }
 }
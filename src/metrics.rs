@@ -0,0 +1,73 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{ReplyCode, Socks5Error};
+
+/// Hook for the metrics of connections made through a
+/// [`Socks5Config`](crate::Socks5Config), to feed prometheus, statsd
+/// and the like, see [`Socks5Config::metrics`].
+///
+/// All methods default to doing nothing. They are called in the middle
+/// of the connection attempt, so they should return quickly.
+///
+/// [`Socks5Config::metrics`]: crate::Socks5Config::metrics
+#[allow(unused_variables)]
+pub trait Socks5Metrics: Send + Sync {
+    /// The TCP connection to the proxy at `proxy` took `latency`.
+    fn proxy_connected(&self, proxy: SocketAddr, latency: Duration) {}
+
+    /// Authentication with the method the proxy selected finished,
+    /// successfully if `success` is true. Called for each attempt when
+    /// failed authentication is retried.
+    fn authenticated(&self, method: u8, success: bool) {}
+
+    /// Method selection and authentication took `duration`.
+    fn handshake_completed(&self, duration: Duration) {}
+
+    /// The proxy answered a request with `code`.
+    fn reply(&self, code: ReplyCode) {}
+
+    /// A connection attempt failed with `error`.
+    fn failed(&self, error: &Socks5Error) {}
+}
+
+impl<T: Socks5Metrics + ?Sized> Socks5Metrics for Arc<T> {
+    fn proxy_connected(&self, proxy: SocketAddr, latency: Duration) {
+        (**self).proxy_connected(proxy, latency)
+    }
+
+    fn authenticated(&self, method: u8, success: bool) {
+        (**self).authenticated(method, success)
+    }
+
+    fn handshake_completed(&self, duration: Duration) {
+        (**self).handshake_completed(duration)
+    }
+
+    fn reply(&self, code: ReplyCode) {
+        (**self).reply(code)
+    }
+
+    fn failed(&self, error: &Socks5Error) {
+        (**self).failed(error)
+    }
+}
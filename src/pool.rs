@@ -0,0 +1,167 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_net::TcpStream;
+use futures_lite::future;
+
+use crate::config::Opened;
+use crate::trace::debug;
+use crate::{protocol, Socks5Config, Socks5Error, Socks5Stream, TargetAddr};
+
+/// Pool of connections to a proxy that already went through the
+/// handshake and wait for their request.
+///
+/// A tunnel can't be reused once it carried a connection, so the pool
+/// saves the round trips to the proxy instead: [`Socks5Pool::fill`]
+/// dials ahead of time, and [`Socks5Pool::connect`] only has to send
+/// the CONNECT request over an idle connection. Idle connections are
+/// dropped after the idle timeout, or when the proxy closed them.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use async_socks5::{Socks5Config, Socks5Pool, TargetAddr};
+///
+/// # smol::block_on(async {
+/// let pool = Socks5Pool::new(Socks5Config::new("127.0.0.1:9050"))
+///     .max_idle(4)
+///     .idle_timeout(Duration::from_secs(30));
+/// pool.fill().await?;
+///
+/// let target = TargetAddr::Domain("example.com".into(), 80);
+/// let stream = pool.connect(&target).await?;
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Socks5Pool {
+    config: Socks5Config,
+    max_idle: usize,
+    idle_timeout: Duration,
+    idle: Mutex<VecDeque<(Opened, Instant)>>,
+}
+
+impl Socks5Pool {
+    /// Create an empty pool for the proxy `config` describes, keeping up
+    /// to 8 idle connections for 60 seconds each.
+    pub fn new(config: Socks5Config) -> Self {
+        Self {
+            config,
+            max_idle: 8,
+            idle_timeout: Duration::from_secs(60),
+            idle: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Keep at most `max_idle` connections waiting.
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Drop connections that waited longer than `timeout`. Proxies close
+    /// connections that don't send a request in time, so keep this below
+    /// the proxy's own limit.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Number of idle connections, including any that expired or were
+    /// closed since they were last checked
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Dial connections until `max_idle` of them are waiting. Failing
+    /// leaves the connections dialed so far in the pool.
+    pub async fn fill(&self) -> Result<(), Socks5Error> {
+        self.expire();
+        while self.idle_count() < self.max_idle {
+            let opened = self.config.open().await?;
+            self.push(opened);
+        }
+        Ok(())
+    }
+
+    /// Connect to `target` over an idle connection, or a new one if none
+    /// is left. Domain targets are resolved by the proxy. A pooled
+    /// connection failing with an I/O error is given up on and the
+    /// request sent over a new connection.
+    pub async fn connect(&self, target: &TargetAddr) -> Result<TcpStream, Socks5Error> {
+        self.connect_stream(target)
+            .await
+            .map(Socks5Stream::into_inner)
+    }
+
+    /// Like [`Socks5Pool::connect`], but returns a [`Socks5Stream`]. For
+    /// a pooled connection, its timings are those of dialing it ahead of
+    /// time.
+    pub async fn connect_stream(&self, target: &TargetAddr) -> Result<Socks5Stream, Socks5Error> {
+        let request = protocol::connect_frame(target)?;
+
+        if let Some(opened) = self.take().await {
+            match self.config.send_over(opened, &request, target).await {
+                Err(Socks5Error::IoError(e)) => {
+                    debug!("pooled connection failed, dialing a new one: {}", e);
+                }
+                result => return result,
+            }
+        }
+
+        let opened = self.config.open().await?;
+        self.config.send_over(opened, &request, target).await
+    }
+
+    /// Add a connection that completed the handshake, dropping it if the
+    /// pool is full.
+    fn push(&self, opened: Opened) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push_back((opened, Instant::now()));
+        }
+    }
+
+    /// Take the oldest idle connection that is still usable.
+    async fn take(&self) -> Option<Opened> {
+        self.expire();
+        loop {
+            let (opened, _) = self.idle.lock().unwrap().pop_front()?;
+            if is_healthy(&opened.stream).await {
+                return Some(opened);
+            }
+            debug!("dropping pooled connection closed by the proxy");
+        }
+    }
+
+    /// Drop connections that have been idle for too long.
+    fn expire(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|(_, since)| since.elapsed() < self.idle_timeout);
+    }
+}
+
+/// Whether an idle connection is still open, with nothing unexpected
+/// waiting to be read
+async fn is_healthy(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    future::poll_once(stream.peek(&mut buf)).await.is_none()
+}
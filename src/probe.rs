@@ -0,0 +1,122 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use async_net::TcpStream;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{protocol, ReplyCode, Socks5Client, Socks5Error, TargetAddr};
+
+/// Outcome of [`Socks5Client::probe`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProbeReport {
+    /// Address of the proxy the TCP connection was made to
+    pub proxy_addr: SocketAddr,
+    /// Time the TCP connection to the proxy took
+    pub connect_time: Duration,
+    /// Time the method selection and authentication took
+    pub handshake_time: Duration,
+    /// Authentication method the proxy selected
+    pub auth_method: u8,
+}
+
+impl Socks5Client {
+    /// Check that the given SOCKS5 proxy is up by connecting and running
+    /// the method selection, and authentication if `credentials` are
+    /// given and the proxy asks for them, without sending a request.
+    /// Cheaply validates credentials too, as rejected ones fail with
+    /// [`Socks5Error::AuthenticationFailed`].
+    /// Opens and closes its own connection to the proxy.
+    pub async fn probe(
+        proxy_addr: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<ProbeReport, Socks5Error> {
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        let connect_time = started.elapsed();
+
+        let started = Instant::now();
+        let auth_method = Socks5Client::handshake(&mut stream, &credentials).await?;
+
+        Ok(ProbeReport {
+            proxy_addr: stream.peer_addr()?,
+            connect_time,
+            handshake_time: started.elapsed(),
+            auth_method,
+        })
+    }
+
+    /// Check whether the given SOCKS5 proxy supports the command `cmd`
+    /// (e.g. 0x02 for BIND or 0x03 for UDP ASSOCIATE) by issuing it against
+    /// the dummy target `0.0.0.0:0`.
+    /// Returns `false` if the proxy replies "command not supported" (0x07),
+    /// `true` if it accepts the command, and an error for any other reply.
+    /// Each probe opens and closes its own connection to the proxy.
+    pub async fn supports_command(
+        proxy_addr: &str,
+        cmd: u8,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<bool, Socks5Error> {
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        Socks5Client::handshake(&mut stream, &credentials).await?;
+
+        let dummy = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        let request = protocol::encode_request(cmd, &TargetAddr::Ip(dummy))?;
+        stream.write_all(&request).await?;
+
+        // Read the whole reply, so a truncated or non-SOCKS5 one fails
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x05 || header[2] != 0x00 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+        Socks5Client::read_reply_addr(&mut stream, header[3]).await?;
+
+        match header[1] {
+            0x00 => Ok(true),
+            0x07 => Ok(false),
+            rep => Err(Socks5Error::Reply(ReplyCode::from(rep))),
+        }
+    }
+
+    /// Check whether the given SOCKS5 proxy requires authentication, by
+    /// offering it the no-auth and username/password methods. Returns
+    /// `false` if the proxy selects no-auth and `true` if it insists on a
+    /// username and password. A proxy accepting both may pick either.
+    /// Fails with [`Socks5Error::NoAcceptableAuthMethods`] if it needs
+    /// some other method (0xFF), and [`Socks5Error::UnexpectedResponse`]
+    /// if it selects one that wasn't offered.
+    /// Opens and closes its own connection to the proxy.
+    pub async fn requires_auth(proxy_addr: &str) -> Result<bool, Socks5Error> {
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        stream.write_all(&[0x05, 0x02, 0x00, 0x02]).await?;
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await?;
+
+        match response {
+            [0x05, 0x00] => Ok(false),
+            [0x05, 0x02] => Ok(true),
+            [0x05, 0xff] => Err(Socks5Error::NoAcceptableAuthMethods),
+            _ => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+}
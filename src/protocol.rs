@@ -0,0 +1,542 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Sans-IO encoding and decoding of SOCKS5 messages.
+//!
+//! Everything here works on byte slices, without any socket types, so
+//! it can be fuzzed, shared with the server or driven over any
+//! transport. Decoders of stream messages return `Ok(None)` until `buf`
+//! holds the whole message, and otherwise the message along with the
+//! number of bytes it took up. [`ClientHandshake`] chains them into the
+//! client side of a negotiation.
+//!
+//! ```
+//! use async_socks5::protocol::{ClientHandshake, Request};
+//! use async_socks5::TargetAddr;
+//!
+//! let target = TargetAddr::Domain("example.com".into(), 80);
+//! let mut handshake = ClientHandshake::new(&Request::connect(target), None).unwrap();
+//! assert_eq!(handshake.outgoing(), [0x05, 0x01, 0x00]);
+//!
+//! // Method selection, then the reply followed by the first tunnel bytes
+//! assert_eq!(handshake.received(&[0x05, 0x00]).unwrap(), 2);
+//! assert_eq!(handshake.outgoing()[..4], [0x05, 0x01, 0x00, 0x03]);
+//! let input = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90, b'h', b'i'];
+//! assert_eq!(handshake.received(&input).unwrap(), 10);
+//! assert_eq!(handshake.bound_addr(), Some(&"127.0.0.1:8080".parse().unwrap()));
+//! ```
+
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Deref;
+
+use crate::idna;
+use crate::{AddrType, Command, Credentials, ReplyCode, Socks5Error, TargetAddr};
+
+/// Greeting a client opens the connection with, offering
+/// authentication methods
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Greeting {
+    pub methods: Vec<u8>,
+}
+
+impl Greeting {
+    /// Encode the greeting, which has to offer 1 to 255 methods.
+    pub fn encode(&self) -> Result<Vec<u8>, Socks5Error> {
+        Ok(greeting_frame(&self.methods)?.to_vec())
+    }
+
+    /// Decode a greeting from the start of `buf`.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, Socks5Error> {
+        let len = match buf {
+            [0x05, n, ..] => 2 + *n as usize,
+            [0x05] | [] => return Ok(None),
+            _ => return Err(Socks5Error::UnexpectedResponse),
+        };
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        let methods = buf[2..len].to_vec();
+        Ok(Some((Self { methods }, len)))
+    }
+}
+
+/// Authentication method the server picked from a [`Greeting`], `0xff`
+/// if none was acceptable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodSelection {
+    pub method: u8,
+}
+
+impl MethodSelection {
+    pub fn encode(&self) -> Vec<u8> {
+        vec![0x05, self.method]
+    }
+
+    /// Decode a method selection from the start of `buf`.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, Socks5Error> {
+        match buf {
+            [0x05, method, ..] => Ok(Some((Self { method: *method }, 2))),
+            [0x05] | [] => Ok(None),
+            _ => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+}
+
+/// Request for the proxy to act on `target`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub command: Command,
+    pub target: TargetAddr,
+}
+
+impl Request {
+    /// CONNECT request for `target`
+    pub fn connect(target: TargetAddr) -> Self {
+        Self {
+            command: Command::Connect,
+            target,
+        }
+    }
+
+    /// Encode the request. Fails with [`Socks5Error::InvalidInput`] if
+    /// a domain target isn't 1 to 255 bytes long.
+    pub fn encode(&self) -> Result<Vec<u8>, Socks5Error> {
+        encode_request(self.command.into(), &self.target)
+    }
+
+    /// Decode a request from the start of `buf`. Unknown commands fail
+    /// with [`Socks5Error::InvalidInput`] and unknown address types with
+    /// [`Socks5Error::UnsupportedAddressType`].
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, Socks5Error> {
+        let (target, len) = match decode_framed(buf)? {
+            Some(framed) => framed,
+            None => return Ok(None),
+        };
+
+        let command = Command::try_from(buf[1])?;
+        Ok(Some((Self { command, target }, len)))
+    }
+}
+
+/// Reply of the proxy to a [`Request`], with the address it bound
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub code: ReplyCode,
+    pub bound_addr: TargetAddr,
+}
+
+impl Reply {
+    pub fn encode(&self) -> Result<Vec<u8>, Socks5Error> {
+        encode_request(self.code.into(), &self.bound_addr)
+    }
+
+    /// Decode a reply from the start of `buf`. Anything following it,
+    /// such as the first bytes of a tunnel, is left alone.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, Socks5Error> {
+        let (bound_addr, len) = match decode_framed(buf)? {
+            Some(framed) => framed,
+            None => return Ok(None),
+        };
+
+        let code = ReplyCode::from(buf[1]);
+        Ok(Some((Self { code, bound_addr }, len)))
+    }
+}
+
+/// Header in front of each datagram relayed by a UDP association
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UdpHeader {
+    /// Fragment number, 0 for a datagram that stands on its own
+    pub frag: u8,
+    pub target: TargetAddr,
+}
+
+impl UdpHeader {
+    pub fn encode(&self) -> Result<Vec<u8>, Socks5Error> {
+        let mut buf = Frame::new();
+        buf.extend_from_slice(&[0x00, 0x00, self.frag]);
+        encode_addr(&mut buf, &self.target)?;
+        Ok(buf.to_vec())
+    }
+
+    /// Decode the header at the start of `datagram`. Datagrams arrive
+    /// whole, so a truncated header is an error rather than `None`; the
+    /// payload follows at the returned length.
+    pub fn decode(datagram: &[u8]) -> Result<(Self, usize), Socks5Error> {
+        let frag = match datagram {
+            [0x00, 0x00, frag, ..] => *frag,
+            _ => return Err(Socks5Error::UnexpectedResponse),
+        };
+
+        let (target, len) = decode_addr(&datagram[3..])?;
+        Ok((Self { frag, target }, 3 + len))
+    }
+}
+
+/// Client side of a negotiation: the greeting, the optional RFC 1929
+/// authentication and a request
+///
+/// Write whatever [`ClientHandshake::outgoing`] returns to the proxy and
+/// hand what it sends back to [`ClientHandshake::received`], until
+/// [`ClientHandshake::bound_addr`] returns the address from the reply.
+/// After an error the handshake can't go on.
+#[derive(Debug)]
+pub struct ClientHandshake {
+    state: State,
+    methods: Vec<u8>,
+    credentials: Option<Credentials>,
+    request: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum State {
+    MethodSelection,
+    Authentication,
+    Reply,
+    Done(TargetAddr),
+}
+
+impl ClientHandshake {
+    /// Start negotiating `request`, offering username/password
+    /// authentication too if `credentials` are given.
+    pub fn new(request: &Request, credentials: Option<Credentials>) -> Result<Self, Socks5Error> {
+        let methods = match credentials {
+            Some(_) => vec![0x00, 0x02],
+            None => vec![0x00],
+        };
+        let outgoing = Greeting {
+            methods: methods.clone(),
+        }
+        .encode()?;
+
+        Ok(Self {
+            state: State::MethodSelection,
+            methods,
+            credentials,
+            request: request.encode()?,
+            outgoing,
+        })
+    }
+
+    /// Take the bytes to send to the proxy next, empty while waiting
+    /// for its answer.
+    pub fn outgoing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Process what the proxy sent, starting at `buf`. Returns the
+    /// number of bytes consumed, 0 if `buf` doesn't hold the whole
+    /// answer yet. Nothing past the reply is consumed.
+    pub fn received(&mut self, buf: &[u8]) -> Result<usize, Socks5Error> {
+        match self.state {
+            State::MethodSelection => {
+                let (selection, len) = match MethodSelection::decode(buf)? {
+                    Some(selection) => selection,
+                    None => return Ok(0),
+                };
+
+                match (
+                    check_method(&self.methods, selection.method)?,
+                    &self.credentials,
+                ) {
+                    (0x02, Some(creds)) => {
+                        self.outgoing = auth_frame(creds.username(), creds.password()).to_vec();
+                        self.state = State::Authentication;
+                    }
+                    _ => {
+                        self.outgoing = self.request.clone();
+                        self.state = State::Reply;
+                    }
+                }
+                Ok(len)
+            }
+            State::Authentication => {
+                let status = match buf {
+                    [version, status, ..] => [*version, *status],
+                    _ => return Ok(0),
+                };
+
+                check_auth_status(status)?;
+                self.outgoing = self.request.clone();
+                self.state = State::Reply;
+                Ok(2)
+            }
+            State::Reply => {
+                let (reply, len) = match Reply::decode(buf)? {
+                    Some(reply) => reply,
+                    None => return Ok(0),
+                };
+
+                if reply.code != ReplyCode::Succeeded {
+                    return Err(Socks5Error::Reply(reply.code));
+                }
+                self.state = State::Done(reply.bound_addr);
+                Ok(len)
+            }
+            State::Done(_) => Ok(0),
+        }
+    }
+
+    /// Address reported in the reply, once the handshake is done
+    pub fn bound_addr(&self) -> Option<&TargetAddr> {
+        match &self.state {
+            State::Done(addr) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+/// Check the method the proxy selected was one of the `offered` ones.
+/// Selecting another (possibly a downgrade attempt) is a protocol
+/// violation and fails with [`Socks5Error::UnexpectedResponse`].
+pub(crate) fn check_method(offered: &[u8], method: u8) -> Result<u8, Socks5Error> {
+    match method {
+        method if offered.contains(&method) => Ok(method),
+        // We only offered no-auth, so the proxy most likely wants credentials
+        0xff if offered == [0x00] => Err(Socks5Error::CredentialsRequired),
+        0xff => Err(Socks5Error::NoAcceptableAuthMethods),
+        // Some proxies pick username/password even when it wasn't offered
+        0x02 => Err(Socks5Error::CredentialsRequired),
+        _ => Err(Socks5Error::UnexpectedResponse),
+    }
+}
+
+/// Longest message the client sends: an RFC 1929 request with 255-byte
+/// username and password
+const MAX_FRAME_LEN: usize = 3 + 255 + 255;
+
+/// Encoded message in a stack buffer, so the handshake doesn't allocate
+pub(crate) struct Frame {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl Frame {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.extend_from_slice(&[byte]);
+    }
+
+    /// Append `bytes`. Lengths are checked before encoding, so running
+    /// out of room is a bug.
+    pub(crate) fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Encode a greeting offering `methods`, of which there have to be 1
+/// to 255.
+pub(crate) fn greeting_frame(methods: &[u8]) -> Result<Frame, Socks5Error> {
+    if methods.is_empty() || methods.len() > u8::MAX as usize {
+        return Err(Socks5Error::InvalidInput(
+            "greeting must offer 1 to 255 methods",
+        ));
+    }
+
+    let mut buf = Frame::new();
+    buf.extend_from_slice(&[0x05, methods.len() as u8]);
+    buf.extend_from_slice(methods);
+    Ok(buf)
+}
+
+/// Encode an RFC 1929 request. The lengths are assumed to be checked
+/// already, see [`crate::Socks5Client::check_credentials`].
+pub(crate) fn auth_frame(username: &[u8], password: &[u8]) -> Frame {
+    let mut buf = Frame::new();
+    buf.push(0x01); // Version
+    buf.push(username.len() as u8);
+    buf.extend_from_slice(username);
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password);
+    buf
+}
+
+/// Check the VER and STATUS of an RFC 1929 response.
+pub(crate) fn check_auth_status(response: [u8; 2]) -> Result<(), Socks5Error> {
+    // The sub-negotiation has its own version, not the SOCKS one
+    match response {
+        [0x01, 0x00] => Ok(()),
+        [0x01, _] => Err(Socks5Error::AuthenticationFailed),
+        _ => Err(Socks5Error::UnexpectedResponse),
+    }
+}
+
+/// Encode a request for command `cmd`, or with a REP in place of the
+/// command, a reply.
+pub(crate) fn encode_request(cmd: u8, target: &TargetAddr) -> Result<Vec<u8>, Socks5Error> {
+    Ok(request_frame(cmd, target)?.to_vec())
+}
+
+/// [`encode_request`] into a [`Frame`]
+pub(crate) fn request_frame(cmd: u8, target: &TargetAddr) -> Result<Frame, Socks5Error> {
+    let mut buf = Frame::new();
+    buf.extend_from_slice(&[0x05, cmd, 0x00]);
+    encode_addr(&mut buf, target)?;
+    Ok(buf)
+}
+
+/// Encode a CONNECT request for `target` into a [`Frame`].
+pub(crate) fn connect_frame(target: &TargetAddr) -> Result<Frame, Socks5Error> {
+    request_frame(Command::Connect.into(), target)
+}
+
+/// Build a CONNECT request for a raw domain field, checking it is
+/// 1 to 255 bytes long.
+pub(crate) fn encode_raw_host_request(host: &[u8], port: u16) -> Result<Vec<u8>, Socks5Error> {
+    check_domain_len(host)?;
+
+    let mut buf = Frame::new();
+    buf.extend_from_slice(&[0x05, 0x01, 0x00]);
+    encode_domain(&mut buf, host, port);
+    Ok(buf.to_vec())
+}
+
+/// Decode the address of a request or reply, checking VER and RSV.
+/// Returns the address and the length of the whole message.
+fn decode_framed(buf: &[u8]) -> Result<Option<(TargetAddr, usize)>, Socks5Error> {
+    match buf {
+        [0x05, _, 0x00, ..] => {}
+        [0x05] | [0x05, _] | [] => return Ok(None),
+        _ => return Err(Socks5Error::UnexpectedResponse),
+    }
+
+    match addr_len(&buf[3..])? {
+        Some(len) if buf.len() >= 3 + len => {
+            let (addr, len) = decode_addr(&buf[3..3 + len])?;
+            Ok(Some((addr, 3 + len)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Check a domain is 1 to 255 bytes long, as required by its single
+/// length byte.
+fn check_domain_len(domain: &[u8]) -> Result<(), Socks5Error> {
+    if domain.is_empty() || domain.len() > u8::MAX as usize {
+        return Err(Socks5Error::InvalidInput(
+            "domain must be 1 to 255 bytes long",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Append the ATYP, address and port of `target` to `buf`, as used by
+/// requests, replies and UDP headers. International domains are
+/// converted to their ASCII form, which has to be 1 to 255 bytes long.
+fn encode_addr(buf: &mut Frame, target: &TargetAddr) -> Result<(), Socks5Error> {
+    match target {
+        TargetAddr::Ip(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    buf.push(AddrType::IPv4.into());
+                    buf.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    buf.push(AddrType::IPv6.into());
+                    buf.extend_from_slice(&ip.octets());
+                }
+            }
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        TargetAddr::Domain(domain, port) => {
+            let domain = idna::to_ascii(domain)?;
+            check_domain_len(domain.as_bytes())?;
+            encode_domain(buf, domain.as_bytes(), *port);
+        }
+    }
+
+    Ok(())
+}
+
+/// Append a domain ATYP, address and port to `buf`.
+fn encode_domain(buf: &mut Frame, domain: &[u8], port: u16) {
+    buf.push(AddrType::DomainName.into());
+    buf.push(domain.len().try_into().unwrap());
+    buf.extend_from_slice(domain);
+    buf.extend_from_slice(&port.to_be_bytes());
+}
+
+/// Return the length of the ATYP, address and port at the start of
+/// `buf`, or `None` if `buf` is too short to tell.
+fn addr_len(buf: &[u8]) -> Result<Option<usize>, Socks5Error> {
+    let atyp = match buf.first() {
+        Some(atyp) => AddrType::try_from(*atyp)?,
+        None => return Ok(None),
+    };
+
+    let addr_len = match atyp {
+        AddrType::IPv4 => 4,
+        AddrType::IPv6 => 16,
+        AddrType::DomainName => match buf.get(1) {
+            Some(0) => return Err(Socks5Error::UnexpectedResponse),
+            Some(len) => 1 + *len as usize,
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some(1 + addr_len + 2))
+}
+
+/// Parse an ATYP, address and port from the start of `buf`. Returns the
+/// address and the number of bytes consumed. A truncated address, or a
+/// zero-length domain as it can't name anything, is rejected with
+/// [`Socks5Error::UnexpectedResponse`].
+pub(crate) fn decode_addr(buf: &[u8]) -> Result<(TargetAddr, usize), Socks5Error> {
+    let len = match addr_len(buf)? {
+        Some(len) if buf.len() >= len => len,
+        _ => return Err(Socks5Error::UnexpectedResponse),
+    };
+
+    let port = u16::from_be_bytes([buf[len - 2], buf[len - 1]]);
+    let addr = &buf[1..len - 2];
+
+    let target = match AddrType::try_from(buf[0])? {
+        AddrType::IPv4 => {
+            let octets: [u8; 4] = addr.try_into().unwrap();
+            TargetAddr::Ip(SocketAddr::new(octets.into(), port))
+        }
+        AddrType::IPv6 => {
+            let octets: [u8; 16] = addr.try_into().unwrap();
+            TargetAddr::Ip(SocketAddr::new(octets.into(), port))
+        }
+        AddrType::DomainName => match std::str::from_utf8(&addr[1..]) {
+            Ok(domain) => TargetAddr::Domain(domain.to_string(), port),
+            Err(_) => return Err(Socks5Error::UnexpectedResponse),
+        },
+    };
+
+    Ok((target, len))
+}
@@ -0,0 +1,192 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use async_net::TcpStream;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::trace::debug;
+use crate::url::{host_and_port, percent_decode};
+use crate::{Credentials, Socks5Config, Socks5Error, TargetAddr};
+
+/// Longest HTTP CONNECT response head read before giving up
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+/// Proxy of either kind, for applications that have to support HTTP
+/// proxies as well as SOCKS5 ones with a single code path.
+#[derive(Clone, Debug)]
+pub enum Proxy {
+    /// A SOCKS5 proxy
+    Socks5 { config: Box<Socks5Config> },
+    /// An HTTP proxy tunneling connections with the CONNECT method,
+    /// optionally with basic authentication
+    HttpConnect {
+        proxy_addr: String,
+        credentials: Option<Credentials>,
+    },
+}
+
+impl Proxy {
+    /// Create a proxy from a URL: `socks5://` and `socks5h://` URLs as
+    /// taken by [`Socks5Config::from_url`], or `http://` URLs for an HTTP
+    /// proxy, whose port defaults to 1080 as well.
+    pub fn from_url(url: &str) -> Result<Self, Socks5Error> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            _ => {
+                let config = Box::new(Socks5Config::from_url(url)?);
+                return Ok(Self::Socks5 { config });
+            }
+        };
+
+        let authority = rest.strip_suffix('/').unwrap_or(rest);
+        if authority.contains(['/', '?', '#']) {
+            return Err(Socks5Error::InvalidInput("proxy URL must not have a path"));
+        }
+
+        let (credentials, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => {
+                let (username, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                let credentials =
+                    Credentials::new(percent_decode(username)?, percent_decode(password)?)?;
+                (Some(credentials), host_port)
+            }
+            None => (None, authority),
+        };
+
+        Ok(Self::HttpConnect {
+            proxy_addr: host_and_port(host_port)?,
+            credentials,
+        })
+    }
+
+    /// Connect through the proxy to `target`. HTTP proxies always resolve
+    /// domain targets themselves.
+    pub async fn connect(&self, target: &TargetAddr) -> Result<TcpStream, Socks5Error> {
+        match self {
+            Self::Socks5 { config } => config.connect(target).await,
+            Self::HttpConnect {
+                proxy_addr,
+                credentials,
+            } => http_connect(proxy_addr, target, credentials.as_ref()).await,
+        }
+    }
+}
+
+impl From<Socks5Config> for Proxy {
+    fn from(config: Socks5Config) -> Self {
+        Self::Socks5 {
+            config: Box::new(config),
+        }
+    }
+}
+
+/// Open a tunnel to `target` through the HTTP proxy at `proxy_addr`.
+async fn http_connect(
+    proxy_addr: &str,
+    target: &TargetAddr,
+    credentials: Option<&Credentials>,
+) -> Result<TcpStream, Socks5Error> {
+    let authority = target.to_string();
+    if authority.contains(|c: char| c.is_ascii_control() || c == ' ') {
+        return Err(Socks5Error::InvalidInput(
+            "target contains invalid characters",
+        ));
+    }
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(credentials) = credentials {
+        let (username, password) = (credentials.username(), credentials.password());
+        if username.contains(&b':') {
+            return Err(Socks5Error::InvalidInput("username must not contain ':'"));
+        }
+
+        // Never the password
+        debug!(
+            "authenticating to HTTP proxy as {}",
+            String::from_utf8_lossy(username)
+        );
+        let token = base64(&[username, b":", password].concat());
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+
+    debug!("sending CONNECT {} to HTTP proxy {}", authority, proxy_addr);
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+
+    let status = read_status(&mut stream).await?;
+    debug!("HTTP proxy replied with status {}", status);
+    match status {
+        200..=299 => Ok(stream),
+        407 if credentials.is_some() => Err(Socks5Error::AuthenticationFailed),
+        407 => Err(Socks5Error::CredentialsRequired),
+        status => Err(Socks5Error::HttpStatus(status)),
+    }
+}
+
+/// Read the head of an HTTP response, up to the empty line, and return
+/// its status code. Reads byte by byte so nothing the target sends after
+/// the head is consumed.
+async fn read_status(stream: &mut TcpStream) -> Result<u16, Socks5Error> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == MAX_RESPONSE_HEAD {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+
+    // Status line: HTTP/1.x SP status code SP reason
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = line.splitn(3, |&b| b == b' ');
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with(b"HTTP/1.") && code.len() == 3 => {
+            std::str::from_utf8(code)
+                .ok()
+                .and_then(|code| code.parse().ok())
+                .ok_or(Socks5Error::UnexpectedResponse)
+        }
+        _ => Err(Socks5Error::UnexpectedResponse),
+    }
+}
+
+/// Standard base64 with padding, as basic authentication uses it
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,233 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_net::TcpStream;
+
+use crate::trace::debug;
+use crate::{Socks5Config, Socks5Error, TargetAddr};
+
+/// How [`ProxyPool`] orders the proxies it tries for each connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Selection {
+    /// Start with the next proxy in turn
+    #[default]
+    RoundRobin,
+    /// Start with the proxy that failed the fewest times, taking turns
+    /// between proxies that failed equally often
+    LeastFailures,
+}
+
+/// Several proxies, each with its own [`Socks5Config`] and credentials,
+/// sharing the connections between them.
+///
+/// Each connection tries the proxies in the order given by the
+/// [`Selection`], moving on to the next one when a proxy can't be
+/// reached or fails the handshake. A proxy failing several times in a
+/// row is quarantined for a while. Errors the proxy replies with, such
+/// as an unreachable target, are returned as is, since the next proxy
+/// would most likely report the same.
+///
+/// ```no_run
+/// use async_socks5::{ProxyPool, Selection, Socks5Config, TargetAddr};
+///
+/// # smol::block_on(async {
+/// let pool = ProxyPool::new()
+///     .proxy(Socks5Config::new("10.0.0.1:1080").credentials("alice", "secret"))
+///     .proxy(Socks5Config::new("10.0.0.2:1080"))
+///     .selection(Selection::LeastFailures);
+///
+/// let target = TargetAddr::Domain("example.com".into(), 80);
+/// let stream = pool.connect(&target).await?;
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct ProxyPool {
+    members: Vec<Member>,
+    selection: Selection,
+    threshold: u32,
+    quarantine: Duration,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Member {
+    config: Socks5Config,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u64,
+    consecutive: u32,
+    quarantined_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_quarantined(&self, now: Instant) -> bool {
+        self.quarantined_until.is_some_and(|until| now < until)
+    }
+}
+
+impl Default for ProxyPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProxyPool {
+    /// Create an empty pool quarantining a proxy for 30 seconds after
+    /// 3 failures in a row.
+    pub fn new() -> Self {
+        Self {
+            members: Vec::new(),
+            selection: Selection::RoundRobin,
+            threshold: 3,
+            quarantine: Duration::from_secs(30),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add the proxy `config` describes.
+    pub fn proxy(mut self, config: Socks5Config) -> Self {
+        self.members.push(Member {
+            config,
+            health: Mutex::new(Health::default()),
+        });
+        self
+    }
+
+    /// Choose how proxies are ordered (default [`Selection::RoundRobin`]).
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Quarantine a proxy for `duration` once it failed `threshold` times
+    /// in a row. After the quarantine, a single further failure puts it
+    /// back until it succeeds once.
+    pub fn quarantine(mut self, threshold: u32, duration: Duration) -> Self {
+        self.threshold = threshold.max(1);
+        self.quarantine = duration;
+        self
+    }
+
+    /// Number of proxies in the pool
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether no proxy was added
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Number of proxies not currently quarantined
+    pub fn available(&self) -> usize {
+        let now = Instant::now();
+        self.members
+            .iter()
+            .filter(|member| !member.health.lock().unwrap().is_quarantined(now))
+            .count()
+    }
+
+    /// Connect to `target` through the first proxy that works. Fails
+    /// with [`Socks5Error::CircuitOpen`] if every proxy is quarantined,
+    /// and with the last proxy's error if none of them worked.
+    pub async fn connect(&self, target: &TargetAddr) -> Result<TcpStream, Socks5Error> {
+        if self.members.is_empty() {
+            return Err(Socks5Error::InvalidInput("proxy pool is empty"));
+        }
+
+        let candidates = self.candidates();
+        if candidates.is_empty() {
+            return Err(Socks5Error::CircuitOpen);
+        }
+
+        let mut last_error = Socks5Error::ConnectionFailed;
+        for index in candidates {
+            let member = &self.members[index];
+            let result = member.config.connect(target).await;
+            self.record(index, &result);
+            match result {
+                Err(e) if is_proxy_failure(&e) => {
+                    debug!("proxy #{} failed, trying the next one: {}", index, e);
+                    last_error = e;
+                }
+                result => return result,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Indices of the proxies to try, in order, leaving out quarantined
+    /// ones.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let count = self.members.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % count;
+
+        let mut candidates: Vec<(usize, u64)> = (0..count)
+            .map(|i| (start + i) % count)
+            .filter_map(|index| {
+                let health = self.members[index].health.lock().unwrap();
+                (!health.is_quarantined(now)).then_some((index, health.failures))
+            })
+            .collect();
+
+        if self.selection == Selection::LeastFailures {
+            candidates.sort_by_key(|&(_, failures)| failures);
+        }
+        candidates.into_iter().map(|(index, _)| index).collect()
+    }
+
+    fn record<T>(&self, index: usize, result: &Result<T, Socks5Error>) {
+        let mut health = self.members[index].health.lock().unwrap();
+        match result {
+            Err(e) if is_proxy_failure(e) => {
+                health.failures = health.failures.saturating_add(1);
+                health.consecutive = health.consecutive.saturating_add(1);
+                if health.consecutive >= self.threshold {
+                    debug!("quarantining proxy #{} for {:?}", index, self.quarantine);
+                    health.quarantined_until = Some(Instant::now() + self.quarantine);
+                }
+            }
+            Err(_) => {}
+            Ok(_) => {
+                health.consecutive = 0;
+                health.quarantined_until = None;
+            }
+        }
+    }
+}
+
+/// Whether `e` means the proxy itself didn't work, rather than it
+/// answering about the target or the input being wrong.
+fn is_proxy_failure(e: &Socks5Error) -> bool {
+    !matches!(
+        e,
+        Socks5Error::Reply(_)
+            | Socks5Error::Tor(_)
+            | Socks5Error::InvalidInput(_)
+            | Socks5Error::LocalResolutionDisabled
+    )
+}
@@ -0,0 +1,267 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_net::TcpStream;
+use futures_lite::io::{AsyncRead, AsyncWrite};
+use futures_lite::ready;
+
+use crate::timer::{AsyncIoTimer, Timer};
+use crate::trace::debug;
+use crate::{Socks5Config, Socks5Error, TargetAddr};
+
+type Replay = Arc<
+    dyn Fn(TcpStream) -> Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>> + Send + Sync,
+>;
+
+/// Connection through a proxy that dials the target again when reading
+/// or writing fails.
+///
+/// Up to `max_attempts` attempts are made per failure, waiting the
+/// backoff before the first and twice as long before each further one.
+/// The callback set with [`ReconnectingStream::on_reconnect`] runs on
+/// every new connection, so the application can replay its own
+/// session handshake. Data in flight when the connection broke is lost;
+/// the failed read or write is retried on the new connection. Once the
+/// attempts are exhausted, the last error is returned and the stream
+/// stays closed.
+///
+/// ```no_run
+/// use async_socks5::{ReconnectingStream, Socks5Config, TargetAddr};
+/// use futures_lite::io::AsyncWriteExt;
+///
+/// # smol::block_on(async {
+/// let target = TargetAddr::Domain("irc.example.org".into(), 6667);
+/// let stream = ReconnectingStream::connect(Socks5Config::new("127.0.0.1:9050"), target)
+///     .await?
+///     .on_reconnect(|mut stream| async move {
+///         stream.write_all(b"NICK example\r\n").await?;
+///         Ok(stream)
+///     });
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+pub struct ReconnectingStream {
+    config: Socks5Config,
+    target: TargetAddr,
+    max_attempts: u32,
+    backoff: Duration,
+    replay: Option<Replay>,
+    reconnects: u64,
+    state: State,
+}
+
+enum State {
+    Connected(TcpStream),
+    Reconnecting(Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>),
+    Closed,
+}
+
+impl ReconnectingStream {
+    /// Connect to `target` through the proxy `config` describes. The
+    /// stream makes up to 5 attempts per failure, starting with a
+    /// backoff of 100 milliseconds.
+    pub async fn connect(config: Socks5Config, target: TargetAddr) -> Result<Self, Socks5Error> {
+        let stream = config.connect(&target).await?;
+        Ok(Self {
+            config,
+            target,
+            max_attempts: 5,
+            backoff: Duration::from_millis(100),
+            replay: None,
+            reconnects: 0,
+            state: State::Connected(stream),
+        })
+    }
+
+    /// Make at most `max_attempts` attempts to reconnect after a failure.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Wait `backoff` before the first attempt to reconnect, doubling
+    /// it for each further attempt.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run `replay` on each new connection before using it. An error
+    /// counts as a failed attempt.
+    pub fn on_reconnect<F, Fut>(mut self, replay: F) -> Self
+    where
+        F: Fn(TcpStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<TcpStream>> + Send + 'static,
+    {
+        self.replay = Some(Arc::new(move |stream| Box::pin(replay(stream))));
+        self
+    }
+
+    /// Number of times the connection was re-established
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Target the stream connects to
+    pub fn target(&self) -> &TargetAddr {
+        &self.target
+    }
+
+    /// The current connection, unless the stream is reconnecting or
+    /// gave up.
+    pub fn get_ref(&self) -> Option<&TcpStream> {
+        match &self.state {
+            State::Connected(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn reconnect(&mut self, error: io::Error) {
+        debug!(
+            "connection to {} failed, reconnecting: {}",
+            self.target, error
+        );
+
+        let config = self.config.clone();
+        let target = self.target.clone();
+        let replay = self.replay.clone();
+        let max_attempts = self.max_attempts;
+        let mut backoff = self.backoff;
+
+        self.state = State::Reconnecting(Box::pin(async move {
+            let mut last_error = error;
+            for _ in 0..max_attempts {
+                AsyncIoTimer.sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+
+                let result = match config.connect(&target).await {
+                    Ok(stream) => match &replay {
+                        Some(replay) => replay(stream).await,
+                        None => Ok(stream),
+                    },
+                    Err(e) => Err(into_io_error(e)),
+                };
+                match result {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => {
+                        debug!("reconnecting to {} failed: {}", target, e);
+                        last_error = e;
+                    }
+                }
+            }
+            Err(last_error)
+        }));
+    }
+
+    /// Drive the connection, reconnecting as needed, until `op` on the
+    /// current one completes.
+    fn poll_with<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(Pin<&mut TcpStream>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            match &mut self.state {
+                State::Connected(stream) => match ready!(op(Pin::new(stream), cx)) {
+                    Err(e) => self.reconnect(e),
+                    result => return Poll::Ready(result),
+                },
+                State::Reconnecting(future) => match ready!(future.as_mut().poll(cx)) {
+                    Ok(stream) => {
+                        self.reconnects += 1;
+                        self.state = State::Connected(stream);
+                    }
+                    Err(e) => {
+                        self.state = State::Closed;
+                        return Poll::Ready(Err(e));
+                    }
+                },
+                State::Closed => return Poll::Ready(Err(io::ErrorKind::NotConnected.into())),
+            }
+        }
+    }
+}
+
+/// Keep the kind of I/O errors, which callers may match on.
+fn into_io_error(e: Socks5Error) -> io::Error {
+    match &e {
+        Socks5Error::IoError(inner) => io::Error::new(inner.kind(), e),
+        _ => io::Error::other(e),
+    }
+}
+
+impl fmt::Debug for ReconnectingStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match &self.state {
+            State::Connected(_) => "connected",
+            State::Reconnecting(_) => "reconnecting",
+            State::Closed => "closed",
+        };
+        f.debug_struct("ReconnectingStream")
+            .field("target", &self.target)
+            .field("state", &state)
+            .field("reconnects", &self.reconnects)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for ReconnectingStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_with(cx, |stream, cx| stream.poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for ReconnectingStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_with(cx, |stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_with(cx, |stream, cx| stream.poll_flush(cx))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match &mut this.state {
+            State::Connected(stream) => Pin::new(stream).poll_close(cx),
+            _ => {
+                this.state = State::Closed;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
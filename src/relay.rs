@@ -0,0 +1,94 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+
+use futures_lite::future;
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Copy buffer size used by [`relay`]
+pub const DEFAULT_RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Largest copy buffer size accepted by [`relay_with_buffer_size`]
+pub const MAX_RELAY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Copy data in both directions between `a` and `b` until both sides
+/// reach EOF. When one side finishes sending, the write half of the
+/// other side is closed so the half-close propagates.
+/// Returns the number of bytes copied from `a` to `b` and from `b` to `a`.
+pub async fn relay<A, B>(a: A, b: B) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    relay_with_buffer_size(a, b, DEFAULT_RELAY_BUFFER_SIZE).await
+}
+
+/// Like [`relay`], but with a copy buffer of `buffer_size` bytes per
+/// direction. Larger buffers help high-bandwidth transfers, smaller ones
+/// reduce memory use with many concurrent relays.
+/// Fails with [`io::ErrorKind::InvalidInput`] unless `buffer_size` is in
+/// `1..=MAX_RELAY_BUFFER_SIZE`.
+pub async fn relay_with_buffer_size<A, B>(a: A, b: B, buffer_size: usize) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    if buffer_size == 0 || buffer_size > MAX_RELAY_BUFFER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "relay buffer size must be between 1 and {}",
+                MAX_RELAY_BUFFER_SIZE
+            ),
+        ));
+    }
+
+    let (a_read, a_write) = futures_lite::io::split(a);
+    let (b_read, b_write) = futures_lite::io::split(b);
+
+    future::try_zip(
+        copy_half(a_read, b_write, buffer_size),
+        copy_half(b_read, a_write, buffer_size),
+    )
+    .await
+}
+
+/// Copy from `reader` to `writer` until EOF, then close `writer`.
+async fn copy_half<R, W>(mut reader: R, mut writer: W, buffer_size: usize) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; buffer_size];
+    let mut copied = 0;
+
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+        copied += n as u64;
+    }
+
+    writer.close().await?;
+    Ok(copied)
+}
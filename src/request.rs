@@ -0,0 +1,128 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::time::Duration;
+
+use async_net::TcpStream;
+
+use crate::{
+    Credentials, IpVersion, Socks5Client, Socks5Config, Socks5Error, Socks5Stream, TargetAddr,
+};
+
+/// Everything needed for a single connection attempt, passed to
+/// [`Socks5Client::execute`].
+///
+/// New settings are added as fields with defaults, so code building a
+/// request with [`ConnectRequest::new`] keeps compiling.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ConnectRequest {
+    /// Address of the SOCKS5 proxy
+    pub proxy: String,
+    /// Where the proxy should connect to
+    pub target: TargetAddr,
+    /// Username and password, if the proxy needs them
+    pub credentials: Option<Credentials>,
+    /// Deadline for the whole attempt
+    pub timeout: Option<Duration>,
+    /// Less common settings
+    pub options: ConnectOptions,
+}
+
+/// Optional settings of a [`ConnectRequest`]. See the [`Socks5Config`]
+/// methods of the same names for what they do.
+///
+/// By default hostnames are resolved by the proxy, as with the
+/// `connect*` shorthands.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ConnectOptions {
+    pub force_remote_dns: bool,
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Duration>,
+    pub proxy_ip_version: IpVersion,
+    pub strict_hostnames: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            force_remote_dns: true,
+            nodelay: None,
+            keepalive: None,
+            proxy_ip_version: IpVersion::default(),
+            strict_hostnames: false,
+        }
+    }
+}
+
+impl ConnectRequest {
+    /// Create a request to connect to `target` through `proxy`, without
+    /// credentials or timeout and with default options. The proxy is given
+    /// as for [`Socks5Config::new`].
+    pub fn new(proxy: impl ToString, target: TargetAddr) -> Self {
+        Self {
+            proxy: proxy.to_string(),
+            target,
+            credentials: None,
+            timeout: None,
+            options: ConnectOptions::default(),
+        }
+    }
+
+    /// Build the equivalent [`Socks5Config`].
+    fn config(&self) -> Socks5Config {
+        let mut config = Socks5Config::new(&self.proxy)
+            .force_remote_dns(self.options.force_remote_dns)
+            .proxy_ip_version(self.options.proxy_ip_version)
+            .strict_hostnames(self.options.strict_hostnames);
+
+        if let Some(credentials) = &self.credentials {
+            config = config.with_credentials(credentials.clone());
+        }
+
+        if let Some(timeout) = self.timeout {
+            config = config.timeout(timeout);
+        }
+
+        if let Some(nodelay) = self.options.nodelay {
+            config = config.nodelay(nodelay);
+        }
+
+        if let Some(idle) = self.options.keepalive {
+            config = config.keepalive(idle);
+        }
+
+        config
+    }
+}
+
+impl Socks5Client {
+    /// Perform the connection attempt described by `request`.
+    /// This is the general form of the `connect*` methods, which are
+    /// shorthands for common requests.
+    pub async fn execute(request: &ConnectRequest) -> Result<TcpStream, Socks5Error> {
+        request.config().connect(&request.target).await
+    }
+
+    /// Like [`Socks5Client::execute`], but returns a [`Socks5Stream`]
+    /// carrying the connection info and byte counters.
+    pub async fn execute_stream(request: &ConnectRequest) -> Result<Socks5Stream, Socks5Error> {
+        request.config().connect_stream(&request.target).await
+    }
+}
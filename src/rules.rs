@@ -0,0 +1,217 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+
+use crate::Command;
+
+/// What happens to a request a [`Rule`] matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// Hosts a [`Rule`] applies to
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Hosts {
+    Any,
+    Network(IpAddr, u8),
+    DomainSuffix(String),
+}
+
+/// Condition on the target of a request, for [`Rules`].
+///
+/// A rule matches a target when its host, port and command conditions
+/// all hold; by default a rule matches every port and command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    hosts: Hosts,
+    ports: RangeInclusive<u16>,
+    commands: Vec<Command>,
+}
+
+impl Rule {
+    fn new(hosts: Hosts) -> Self {
+        Self {
+            hosts,
+            ports: 0..=u16::MAX,
+            commands: vec![],
+        }
+    }
+
+    /// Match every target.
+    pub fn any() -> Self {
+        Self::new(Hosts::Any)
+    }
+
+    /// Match addresses in the network `addr/prefix_len`, prefix lengths
+    /// beyond the address width being clamped to it. Domain targets
+    /// match if they resolve into the network.
+    pub fn network(addr: IpAddr, prefix_len: u8) -> Self {
+        let width = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Self::new(Hosts::Network(addr, prefix_len.min(width)))
+    }
+
+    /// Match domain targets equal to `suffix` or below it, so
+    /// `example.com` covers `www.example.com` too. Case is ignored.
+    pub fn domain_suffix(suffix: &str) -> Self {
+        let suffix = suffix.trim_start_matches('.').to_ascii_lowercase();
+        Self::new(Hosts::DomainSuffix(suffix))
+    }
+
+    /// Only match targets on a port in `ports`.
+    pub fn ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// Only match requests with `command`. Can be given more than once.
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    fn matches(
+        &self,
+        command: Command,
+        domain: Option<&str>,
+        ip: Option<IpAddr>,
+        port: u16,
+    ) -> bool {
+        if !self.commands.is_empty() && !self.commands.contains(&command) {
+            return false;
+        }
+        if !self.ports.contains(&port) {
+            return false;
+        }
+
+        match &self.hosts {
+            Hosts::Any => true,
+            Hosts::Network(net, prefix_len) => {
+                ip.is_some_and(|ip| in_network(ip, *net, *prefix_len))
+            }
+            Hosts::DomainSuffix(suffix) => domain.is_some_and(|domain| {
+                let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+                domain == *suffix || domain.ends_with(&format!(".{}", suffix))
+            }),
+        }
+    }
+}
+
+/// Ordered access control list for a [`Socks5Server`](crate::Socks5Server).
+///
+/// Rules are checked in the order they were added and the first match
+/// decides; targets no rule matches get the default action, which is
+/// [`Action::Allow`] unless changed.
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use async_socks5::{Action, Rule, Rules};
+///
+/// let rules = Rules::new()
+///     .deny(Rule::network(Ipv4Addr::new(10, 0, 0, 0).into(), 8))
+///     .allow(Rule::domain_suffix("example.com").ports(80..=443))
+///     .default_action(Action::Deny);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rules {
+    rules: Vec<(Action, Rule)>,
+    default: Action,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            default: Action::Allow,
+        }
+    }
+}
+
+impl Rules {
+    /// Create an empty rule list allowing everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a rule allowing the targets it matches.
+    pub fn allow(mut self, rule: Rule) -> Self {
+        self.rules.push((Action::Allow, rule));
+        self
+    }
+
+    /// Append a rule denying the targets it matches.
+    pub fn deny(mut self, rule: Rule) -> Self {
+        self.rules.push((Action::Deny, rule));
+        self
+    }
+
+    /// Set the action for targets no rule matches.
+    pub fn default_action(mut self, action: Action) -> Self {
+        self.default = action;
+        self
+    }
+
+    /// Whether a `command` request for `addr` is allowed. `domain` is the
+    /// name the client asked for, if `addr` was resolved from one.
+    pub fn allows(&self, command: Command, domain: Option<&str>, addr: &SocketAddr) -> bool {
+        self.decide(command, domain, Some(addr.ip()), addr.port())
+    }
+
+    /// Whether a `command` request for `domain` is allowed without
+    /// resolving it, as when an upstream proxy does. Network rules never
+    /// match such targets.
+    pub fn allows_unresolved(&self, command: Command, domain: &str, port: u16) -> bool {
+        self.decide(command, Some(domain), None, port)
+    }
+
+    fn decide(
+        &self,
+        command: Command,
+        domain: Option<&str>,
+        ip: Option<IpAddr>,
+        port: u16,
+    ) -> bool {
+        let action = self
+            .rules
+            .iter()
+            .find(|(_, rule)| rule.matches(command, domain, ip, port))
+            .map_or(self.default, |(action, _)| *action);
+        action == Action::Allow
+    }
+}
+
+/// Whether `ip` lies in the network `net/prefix_len`
+pub(crate) fn in_network(ip: IpAddr, net: IpAddr, prefix_len: u8) -> bool {
+    match (ip.to_canonical(), net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,1010 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_net::{AsyncToSocketAddrs, TcpListener, TcpStream, UdpSocket};
+use event_listener::Event;
+use futures_lite::future;
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::breaker::AuthRateLimiter;
+use crate::config::connect_from;
+use crate::protocol::Reply;
+use crate::relay::relay;
+use crate::throttle::Throttled;
+use crate::trace::debug;
+use crate::udp::MAX_DATAGRAM;
+use crate::{
+    ClientSummary, Command, ConnectionId, Phase, ReplyCode, Rules, ServerObserver, Socks5Client,
+    Socks5Config, Socks5Error, Socks5Stream, Socks5UdpSocket, TargetAddr,
+};
+
+/// Default for [`Socks5Server::udp_target_limit`]
+const UDP_TARGET_LIMIT: usize = 256;
+
+/// How long resolving a UDP target may hold up the relay
+const UDP_RESOLVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Boxed future returned by [`UserPassVerifier::verify`]
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+
+/// Backend checking the RFC 1929 username and password a client sent,
+/// such as a user database.
+///
+/// Plain closures taking the username and password work as verifiers,
+/// and [`InMemoryVerifier`] covers a fixed set of users.
+pub trait UserPassVerifier: Send + Sync {
+    /// Resolve to true if the client may use the server.
+    fn verify<'a>(&'a self, username: &'a [u8], password: &'a [u8]) -> VerifyFuture<'a>;
+}
+
+impl<F> UserPassVerifier for F
+where
+    F: Fn(&[u8], &[u8]) -> bool + Send + Sync,
+{
+    fn verify<'a>(&'a self, username: &'a [u8], password: &'a [u8]) -> VerifyFuture<'a> {
+        let valid = self(username, password);
+        Box::pin(async move { valid })
+    }
+}
+
+/// Verifier accepting a fixed set of username/password pairs
+#[derive(Clone, Default)]
+pub struct InMemoryVerifier {
+    users: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl fmt::Debug for InMemoryVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryVerifier")
+            .field("users", &self.users.len())
+            .finish()
+    }
+}
+
+impl InMemoryVerifier {
+    /// Create a verifier without any users.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `username` with `password`, replacing any earlier password.
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.users
+            .insert(username.as_bytes().to_vec(), password.as_bytes().to_vec());
+        self
+    }
+}
+
+impl UserPassVerifier for InMemoryVerifier {
+    fn verify<'a>(&'a self, username: &'a [u8], password: &'a [u8]) -> VerifyFuture<'a> {
+        let valid = self.users.get(username).map(Vec::as_slice) == Some(password);
+        Box::pin(async move { valid })
+    }
+}
+
+/// Hook rewriting the target of every CONNECT request before the server
+/// dials it, for split-horizon DNS, `.onion` mapping or redirecting
+/// hosts to local services. Closures of the same shape work too.
+pub trait TargetResolver: Send + Sync {
+    /// Return the target to dial instead of `target`, or the REP code to
+    /// refuse the request with.
+    fn resolve(&self, target: TargetAddr) -> Result<TargetAddr, ReplyCode>;
+}
+
+impl<F> TargetResolver for F
+where
+    F: Fn(TargetAddr) -> Result<TargetAddr, ReplyCode> + Send + Sync,
+{
+    fn resolve(&self, target: TargetAddr) -> Result<TargetAddr, ReplyCode> {
+        self(target)
+    }
+}
+
+/// Future serving one client, returned by [`Socks5ServerListener::accept`]
+pub type Serve = Pin<Box<dyn Future<Output = Result<(), Socks5Error>> + Send>>;
+
+/// Minimal SOCKS5 server, handling CONNECT, BIND and UDP ASSOCIATE
+/// requests.
+///
+/// The server is not tied to an executor: [`Socks5Server::bind`] it to
+/// an address and spawn the future [`Socks5ServerListener::accept`]
+/// returns for each client. Clients accepted elsewhere can be handed to
+/// [`Socks5Server::serve`] directly; cloning is cheap. Clones share
+/// their shutdown state, see [`Socks5Server::shutdown`].
+///
+/// ```no_run
+/// use async_socks5::Socks5Server;
+///
+/// smol::block_on(async {
+///     let server = Socks5Server::new().auth(|user, pass| user == b"me" && pass == b"secret");
+///     let listener = server.bind("127.0.0.1:1080").await.unwrap();
+///
+///     while let Some(client) = listener.accept().await.unwrap() {
+///         smol::spawn(client).detach();
+///     }
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Socks5Server {
+    auth: Option<Arc<dyn UserPassVerifier>>,
+    rules: Arc<Rules>,
+    upstream: Option<Arc<Socks5Config>>,
+    resolver: Option<Arc<dyn TargetResolver>>,
+    observer: Option<Arc<dyn ServerObserver>>,
+    auth_limiter: Option<Arc<AuthRateLimiter>>,
+    upload_limit: Option<u64>,
+    download_limit: Option<u64>,
+    outbound_ip: Option<IpAddr>,
+    outbound_device: Option<String>,
+    udp_target_limit: Option<usize>,
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl fmt::Debug for Socks5Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Server")
+            .field("auth", &self.auth.is_some())
+            .field("rules", &self.rules)
+            .field("upstream", &self.upstream)
+            .field("resolver", &self.resolver.is_some())
+            .field("observer", &self.observer.is_some())
+            .field("auth_limiter", &self.auth_limiter)
+            .field("upload_limit", &self.upload_limit)
+            .field("download_limit", &self.download_limit)
+            .field("outbound_ip", &self.outbound_ip)
+            .field("outbound_device", &self.outbound_device)
+            .field("udp_target_limit", &self.udp_target_limit)
+            .finish()
+    }
+}
+
+impl Socks5Server {
+    /// Create a server accepting clients without authentication.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require RFC 1929 username/password authentication, accepting the
+    /// clients for which `check` returns true.
+    pub fn auth<F>(self, check: F) -> Self
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.verifier(check)
+    }
+
+    /// Require RFC 1929 username/password authentication, accepting the
+    /// clients `verifier` approves of.
+    pub fn verifier(mut self, verifier: impl UserPassVerifier + 'static) -> Self {
+        self.auth = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Check every target against `rules` before acting on it. Denied
+    /// requests get the reply "connection not allowed by ruleset", and
+    /// denied datagrams are dropped.
+    pub fn rules(mut self, rules: Rules) -> Self {
+        self.rules = Arc::new(rules);
+        self
+    }
+
+    /// Forward CONNECT requests through the upstream proxy `config`
+    /// describes instead of dialing targets directly, for example into
+    /// Tor with [`Socks5Config::tor`]. Domains are passed on unresolved
+    /// and the upstream's reply codes are relayed to the client.
+    pub fn upstream(mut self, config: Socks5Config) -> Self {
+        self.upstream = Some(Arc::new(config));
+        self
+    }
+
+    /// Turn away clients from source IPs that failed authentication
+    /// `max_failures` times within `window`, until enough of those
+    /// failures are older than `window`. They are refused all methods
+    /// without getting to try credentials.
+    pub fn auth_rate_limit(mut self, max_failures: u32, window: Duration) -> Self {
+        self.auth_limiter = Some(Arc::new(AuthRateLimiter::new(max_failures, window)));
+        self
+    }
+
+    /// Pass the target of every CONNECT request through `resolver` first.
+    /// Rules and the upstream proxy see the target it returns.
+    pub fn resolver(mut self, resolver: impl TargetResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Remember at most `limit` targets per UDP association, 256 by
+    /// default. Only remembered targets may answer the client, so the
+    /// least recently used one is forgotten to make room for a new one.
+    pub fn udp_target_limit(mut self, limit: usize) -> Self {
+        self.udp_target_limit = Some(limit.max(1));
+        self
+    }
+
+    /// Relay at most `bytes_per_second` from each client to its target.
+    pub fn upload_limit(mut self, bytes_per_second: u64) -> Self {
+        self.upload_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Relay at most `bytes_per_second` from the target to each client.
+    pub fn download_limit(mut self, bytes_per_second: u64) -> Self {
+        self.download_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Connect to CONNECT targets and relay UDP datagrams to them from
+    /// `ip`, for example to pick the egress of a multi-homed host or a
+    /// VPN. Only targets of the same IP version are reached. The reply to
+    /// a CONNECT reports the address the connection was made from. Doesn't
+    /// apply with [`Socks5Server::upstream`], see
+    /// [`Socks5Config::local_addr`] for that.
+    pub fn outbound_addr(mut self, ip: IpAddr) -> Self {
+        self.outbound_ip = Some(ip);
+        self
+    }
+
+    /// Like [`Socks5Server::outbound_addr`], but bind outbound sockets to
+    /// the network interface named `interface` (`SO_BINDTODEVICE`).
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn outbound_device(mut self, interface: &str) -> Self {
+        self.outbound_device = Some(interface.to_string());
+        self
+    }
+
+    /// Report what happens to every client to `observer`.
+    pub fn observer(mut self, observer: impl ServerObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Stop accepting clients. Pending and later calls to
+    /// [`Socks5ServerListener::accept`] return `None`, while clients being
+    /// served carry on until [`Socks5Server::join`] aborts them.
+    pub fn shutdown(&self) {
+        self.lifecycle.stopping.store(true, Ordering::SeqCst);
+        self.lifecycle.changed.notify(usize::MAX);
+    }
+
+    /// Wait for the clients being served to finish, for at most `drain`
+    /// if given, and abort those still running after that. Only returns
+    /// once every client is done, so call [`Socks5Server::shutdown`]
+    /// first for it to end.
+    pub async fn join(&self, drain: Option<Duration>) {
+        let lifecycle = &self.lifecycle;
+        let idle = lifecycle.wait(|l| l.active.load(Ordering::SeqCst) == 0);
+
+        if let Some(drain) = drain {
+            let expired = async {
+                async_io::Timer::after(drain).await;
+            };
+            future::or(idle, expired).await;
+
+            let remaining = lifecycle.active.load(Ordering::SeqCst);
+            if remaining > 0 {
+                debug!("aborting {} clients after draining", remaining);
+            }
+            lifecycle.aborting.store(true, Ordering::SeqCst);
+            lifecycle.changed.notify(usize::MAX);
+        }
+
+        lifecycle
+            .wait(|l| l.active.load(Ordering::SeqCst) == 0)
+            .await;
+    }
+
+    /// Listen on `addr` for clients to serve.
+    pub async fn bind<A: AsyncToSocketAddrs>(self, addr: A) -> io::Result<Socks5ServerListener> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Socks5ServerListener {
+            listener,
+            server: self,
+        })
+    }
+
+    /// Serve one client: negotiate the method, authenticate it and carry
+    /// out its request. A CONNECT dials the target and a BIND waits for
+    /// the expected peer to connect, then both relay data until the two
+    /// sides are done. A UDP ASSOCIATE relays datagrams until the client
+    /// closes the connection.
+    pub async fn serve(&self, client: TcpStream) -> Result<(), Socks5Error> {
+        let mut session = Session {
+            id: ConnectionId::next(),
+            peer: client.peer_addr()?,
+            started: Instant::now(),
+            username: None,
+            target: None,
+            bytes: (0, 0),
+        };
+        self.observe(|o| o.accepted(session.id, session.peer));
+
+        let lifecycle = &self.lifecycle;
+        lifecycle.active.fetch_add(1, Ordering::SeqCst);
+        let _active = Active(lifecycle);
+
+        let aborted = async {
+            lifecycle.wait(|l| l.aborting.load(Ordering::SeqCst)).await;
+            Err(io::Error::new(io::ErrorKind::ConnectionAborted, "server shut down").into())
+        };
+        let result = future::or(self.serve_client(client, &mut session), aborted).await;
+
+        self.observe(|o| {
+            let summary = ClientSummary {
+                peer: session.peer,
+                username: session.username.take(),
+                target: session.target.take(),
+                duration: session.started.elapsed(),
+                bytes_sent: session.bytes.0,
+                bytes_received: session.bytes.1,
+                error: result.clone().err(),
+            };
+            o.closed(session.id, &summary)
+        });
+        result
+    }
+
+    async fn serve_client(
+        &self,
+        mut client: TcpStream,
+        session: &mut Session,
+    ) -> Result<(), Socks5Error> {
+        self.handshake(&mut client, session).await?;
+
+        let mut header = [0u8; 4];
+        client.read_exact(&mut header).await?;
+        if header[0] != 0x05 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        let target = match Socks5Client::read_reply_addr(&mut client, header[3]).await {
+            Ok(target) => target,
+            Err(Socks5Error::UnsupportedAddressType) => {
+                reply(&mut client, ReplyCode::AddressTypeNotSupported, None).await?;
+                return Err(Socks5Error::UnsupportedAddressType);
+            }
+            Err(e) => return Err(e),
+        };
+
+        match Command::try_from(header[1]) {
+            Ok(Command::Connect) => self.handle_connect(client, target, session).await,
+            Ok(Command::Bind) => self.handle_bind(client, target, session).await,
+            Ok(Command::UdpAssociate) => self.handle_udp_associate(client, target).await,
+            Err(e) => {
+                reply(&mut client, ReplyCode::CommandNotSupported, None).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Internal method choosing the authentication method from the
+    /// client's greeting and running it.
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        session: &mut Session,
+    ) -> Result<(), Socks5Error> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x05 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).await?;
+
+        if let Some(limiter) = &self.auth_limiter {
+            if limiter.is_blocked(session.peer.ip()) {
+                debug!(
+                    "refusing {} after repeated authentication failures",
+                    session.peer
+                );
+                stream.write_all(&[0x05, 0xff]).await?;
+                return Err(Socks5Error::AuthenticationFailed);
+            }
+        }
+
+        let method = if self.auth.is_some() { 0x02 } else { 0x00 };
+        if !methods.contains(&method) {
+            stream.write_all(&[0x05, 0xff]).await?;
+            return Err(Socks5Error::NoAcceptableAuthMethods);
+        }
+        stream.write_all(&[0x05, method]).await?;
+
+        let verifier = match &self.auth {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+
+        let (username, valid) = authenticate(stream, verifier.as_ref()).await?;
+        self.observe(|o| o.authenticated(session.id, &username, valid));
+        session.username = Some(username);
+
+        if let (false, Some(limiter)) = (valid, &self.auth_limiter) {
+            limiter.record_failure(session.peer.ip());
+        }
+
+        let status = if valid { 0x00 } else { 0x01 };
+        stream.write_all(&[0x01, status]).await?;
+
+        match valid {
+            true => Ok(()),
+            false => Err(Socks5Error::AuthenticationFailed),
+        }
+    }
+
+    fn observe(&self, event: impl FnOnce(&dyn ServerObserver)) {
+        if let Some(observer) = &self.observer {
+            event(observer.as_ref());
+        }
+    }
+
+    /// Relay between the client and the `target` the server connected
+    /// to, recording it in `session`.
+    async fn relay(
+        &self,
+        client: TcpStream,
+        upstream: TcpStream,
+        target: TargetAddr,
+        session: &mut Session,
+    ) -> Result<(), Socks5Error> {
+        self.observe(|o| o.connected(session.id, &target));
+        session.target = Some(target);
+
+        let mut client = Throttled::new(client);
+        if let Some(limit) = self.upload_limit {
+            client = client.read_limit(limit);
+        }
+        if let Some(limit) = self.download_limit {
+            client = client.write_limit(limit);
+        }
+
+        session.bytes = relay(client, upstream).await?;
+        let (sent, received) = session.bytes;
+        self.observe(|o| o.relayed(session.id, sent, received));
+        Ok(())
+    }
+}
+
+/// [`Socks5Server`] listening for clients
+#[derive(Debug)]
+pub struct Socks5ServerListener {
+    listener: TcpListener,
+    server: Socks5Server,
+}
+
+impl Socks5ServerListener {
+    /// Address the server listens on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Wait for a client and return the future serving it, to be spawned
+    /// on the executor of your choice. Returns `None` once the server is
+    /// shut down.
+    pub async fn accept(&self) -> io::Result<Option<Serve>> {
+        let lifecycle = &self.server.lifecycle;
+        let stopped = async {
+            lifecycle.wait(|l| l.stopping.load(Ordering::SeqCst)).await;
+            Ok(None)
+        };
+        let accepted = async { self.listener.accept().await.map(Some) };
+
+        let (stream, peer) = match future::or(stopped, accepted).await? {
+            Some(accepted) => accepted,
+            None => return Ok(None),
+        };
+        debug!("accepted client {}", peer);
+
+        let server = self.server.clone();
+        Ok(Some(Box::pin(async move { server.serve(stream).await })))
+    }
+}
+
+/// What is known about a client being served, for the observer
+struct Session {
+    id: ConnectionId,
+    peer: SocketAddr,
+    started: Instant,
+    username: Option<Vec<u8>>,
+    target: Option<TargetAddr>,
+    bytes: (u64, u64),
+}
+
+/// Shutdown state shared by a server, its clones and its clients
+#[derive(Default)]
+struct Lifecycle {
+    stopping: AtomicBool,
+    aborting: AtomicBool,
+    active: AtomicUsize,
+    changed: Event,
+}
+
+impl Lifecycle {
+    /// Wait until `done` holds, checking again whenever the state changes.
+    async fn wait(&self, done: impl Fn(&Self) -> bool) {
+        loop {
+            if done(self) {
+                return;
+            }
+            let listener = self.changed.listen();
+            if done(self) {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// Guard counting a client as active while it is served
+struct Active<'a>(&'a Lifecycle);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+        self.0.changed.notify(usize::MAX);
+    }
+}
+
+/// Read an RFC 1929 request and check it with `verifier`. Returns the
+/// username and whether it was accepted, for the caller to answer.
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    verifier: &dyn UserPassVerifier,
+) -> Result<(Vec<u8>, bool), Socks5Error> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    if len[0] != 0x01 {
+        return Err(Socks5Error::UnexpectedResponse);
+    }
+
+    let mut username = vec![0u8; len[1] as usize];
+    stream.read_exact(&mut username).await?;
+
+    stream.read_exact(&mut len[..1]).await?;
+    let mut password = vec![0u8; len[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    let valid = verifier.verify(&username, &password).await;
+    Ok((username, valid))
+}
+
+impl Socks5Server {
+    /// Dial `target` for the client and relay data between the two.
+    async fn handle_connect(
+        &self,
+        mut client: TcpStream,
+        target: TargetAddr,
+        session: &mut Session,
+    ) -> Result<(), Socks5Error> {
+        let target = match &self.resolver {
+            Some(resolver) => match resolver.resolve(target.clone()) {
+                Ok(rewritten) => {
+                    debug!("{} resolved to {}", target, rewritten);
+                    rewritten
+                }
+                Err(code) => {
+                    debug!("{} refused by the resolver: {}", target, code);
+                    reply(&mut client, code, None).await?;
+                    return Err(Socks5Error::Reply(code));
+                }
+            },
+            None => target,
+        };
+
+        // Through an upstream proxy, the address it bound is the one the
+        // target sees
+        let result = match &self.upstream {
+            Some(config) => self.forward(config, &target).await.map(|stream| {
+                let bound_addr = stream.bound_addr().clone();
+                (stream.into_inner(), Some(bound_addr))
+            }),
+            None => self.dial(&target).await.map(|stream| (stream, None)),
+        };
+
+        let (upstream, bound_addr) = match result {
+            Ok(connected) => connected,
+            Err(e) => {
+                debug!("connecting to {} failed: {}", target, e);
+                reply(&mut client, failure_code(&e), None).await?;
+                return Err(e);
+            }
+        };
+
+        match bound_addr {
+            Some(bound_addr) => reply_bound(&mut client, ReplyCode::Succeeded, bound_addr).await?,
+            None => {
+                let bound_addr = upstream.local_addr().ok();
+                reply(&mut client, ReplyCode::Succeeded, bound_addr).await?
+            }
+        }
+        self.relay(client, upstream, target, session).await
+    }
+
+    /// Connect to `target`, resolving domains locally, from the outbound
+    /// address or interface if one is set.
+    async fn dial(&self, target: &TargetAddr) -> Result<TcpStream, Socks5Error> {
+        let addrs = permitted(&self.rules, Command::Connect, target).await?;
+        if self.outbound_ip.is_none() && self.outbound_device.is_none() {
+            return Ok(TcpStream::connect(&addrs[..]).await?);
+        }
+
+        let mut last_error = None;
+        for addr in addrs {
+            let local = match self.outbound_ip {
+                Some(ip) if ip.is_ipv4() != addr.is_ipv4() => continue,
+                Some(ip) => Some(SocketAddr::new(ip, 0)),
+                None => None,
+            };
+            match connect_from(local, self.outbound_device.as_deref(), addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => e.into(),
+            None => Socks5Error::Reply(ReplyCode::AddressTypeNotSupported),
+        })
+    }
+
+    /// Socket to send datagrams to targets from: `socket` itself, unless
+    /// an outbound address or interface is set.
+    fn outbound_socket(&self, socket: &UdpSocket) -> Result<UdpSocket, Socks5Error> {
+        if self.outbound_ip.is_none() && self.outbound_device.is_none() {
+            return Ok(socket.clone());
+        }
+
+        let ip = self
+            .outbound_ip
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let outbound = std::net::UdpSocket::bind(SocketAddr::new(ip, 0))?;
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        if let Some(device) = &self.outbound_device {
+            socket2::SockRef::from(&outbound).bind_device(Some(device.as_bytes()))?;
+        }
+        Ok(UdpSocket::try_from(outbound)?)
+    }
+
+    /// Connect to `target` through the upstream proxy.
+    async fn forward(
+        &self,
+        config: &Socks5Config,
+        target: &TargetAddr,
+    ) -> Result<Socks5Stream, Socks5Error> {
+        let allowed = match target {
+            TargetAddr::Ip(addr) => self.rules.allows(Command::Connect, None, addr),
+            TargetAddr::Domain(host, port) => {
+                self.rules.allows_unresolved(Command::Connect, host, *port)
+            }
+        };
+        if !allowed {
+            debug!("{:?} to {} denied by the rules", Command::Connect, target);
+            return Err(Socks5Error::Reply(ReplyCode::NotAllowed));
+        }
+
+        match target {
+            TargetAddr::Ip(_) => config.connect_stream(target).await,
+            TargetAddr::Domain(host, port) => config.domain_stream(host, *port).await,
+        }
+    }
+
+    /// Listen for `peer` to connect on the client's behalf, then relay data
+    /// between the two. Other connections are turned away, and an
+    /// unspecified `peer` address accepts anyone.
+    async fn handle_bind(
+        &self,
+        mut client: TcpStream,
+        peer: TargetAddr,
+        session: &mut Session,
+    ) -> Result<(), Socks5Error> {
+        let expected: Vec<IpAddr> = match permitted(&self.rules, Command::Bind, &peer).await {
+            Ok(addrs) => addrs.iter().map(SocketAddr::ip).collect(),
+            Err(e) => {
+                reply(&mut client, failure_code(&e), None).await?;
+                return Err(e);
+            }
+        };
+
+        let listener = TcpListener::bind((client.local_addr()?.ip(), 0)).await?;
+        reply(
+            &mut client,
+            ReplyCode::Succeeded,
+            listener.local_addr().ok(),
+        )
+        .await?;
+
+        let accept = async {
+            loop {
+                let (stream, from) = listener.accept().await?;
+                if expected
+                    .iter()
+                    .any(|ip| ip.is_unspecified() || *ip == from.ip())
+                {
+                    return Ok(Some((stream, from)));
+                }
+                debug!("turning away {}, waiting for {:?}", from, expected);
+            }
+        };
+
+        // The client has nothing to say until the second reply, so anything
+        // it sends means it gave up
+        let closed = async {
+            let mut buf = [0u8; 1];
+            match client.read(&mut buf).await? {
+                0 => Ok(None),
+                _ => Err(Socks5Error::UnexpectedResponse),
+            }
+        };
+
+        let (upstream, from) = match future::or(closed, accept).await? {
+            Some(accepted) => accepted,
+            None => return Ok(()),
+        };
+
+        reply(&mut client, ReplyCode::Succeeded, Some(from)).await?;
+        self.relay(client, upstream, TargetAddr::Ip(from), session)
+            .await
+    }
+
+    /// Relay datagrams for the client over a fresh UDP socket until it
+    /// closes the control connection. `source` is the address the client
+    /// announced it sends from, zeros meaning unknown.
+    async fn handle_udp_associate(
+        &self,
+        mut client: TcpStream,
+        source: TargetAddr,
+    ) -> Result<(), Socks5Error> {
+        let peer = client.peer_addr()?;
+        let socket = UdpSocket::bind((client.local_addr()?.ip(), 0)).await?;
+        reply(&mut client, ReplyCode::Succeeded, socket.local_addr().ok()).await?;
+
+        let source = match source {
+            TargetAddr::Ip(addr) => addr,
+            TargetAddr::Domain(_, port) => SocketAddr::new(peer.ip(), port),
+        };
+
+        let outbound = self.outbound_socket(&socket)?;
+        let separate = self.outbound_ip.is_some() || self.outbound_device.is_some();
+
+        let relay = async {
+            let mut buf = vec![0u8; MAX_DATAGRAM];
+            let mut answer = vec![0u8; if separate { MAX_DATAGRAM } else { 0 }];
+            // Locked in with the first datagram from the client
+            let mut client_addr = None;
+            let mut targets = UdpTargets::new(self.udp_target_limit.unwrap_or(UDP_TARGET_LIMIT));
+
+            loop {
+                // With a separate outbound socket, targets answer on it
+                let (n, from, on_outbound) = if separate {
+                    future::or(
+                        async {
+                            socket
+                                .recv_from(&mut buf)
+                                .await
+                                .map(|(n, from)| (n, from, false))
+                        },
+                        async {
+                            outbound
+                                .recv_from(&mut answer)
+                                .await
+                                .map(|(n, from)| (n, from, true))
+                        },
+                    )
+                    .await?
+                } else {
+                    let (n, from) = socket.recv_from(&mut buf).await?;
+                    (n, from, false)
+                };
+
+                let from_client = !on_outbound
+                    && match client_addr {
+                        Some(addr) => from == addr,
+                        None => matches_source(from, peer, source),
+                    };
+                let is_answer = (on_outbound || !separate) && targets.contacted(from);
+                let buf = if on_outbound { &answer } else { &buf };
+
+                if from_client {
+                    client_addr = Some(from);
+                    let (target, len) = match Socks5UdpSocket::decode_header(&buf[..n]) {
+                        Ok(header) => header,
+                        Err(e) => {
+                            debug!("dropping malformed datagram from {}: {}", from, e);
+                            continue;
+                        }
+                    };
+
+                    let addr = match targets.get(&target) {
+                        Some(addr) => addr,
+                        None => {
+                            let resolved = future::or(
+                                permitted(&self.rules, Command::UdpAssociate, &target),
+                                async {
+                                    async_io::Timer::after(UDP_RESOLVE_TIMEOUT).await;
+                                    Err(Socks5Error::Timeout(Phase::Target))
+                                },
+                            );
+                            match resolved.await {
+                                Ok(addrs) => targets.insert(target.clone(), addrs[0]),
+                                Err(e) => {
+                                    debug!("dropping datagram to {}: {}", target, e);
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+
+                    if let Err(e) = outbound.send_to(&buf[len..n], addr).await {
+                        debug!("relaying datagram to {} failed: {}", target, e);
+                    }
+                } else if let (true, Some(client_addr)) = (is_answer, client_addr) {
+                    let mut datagram = Socks5UdpSocket::encode_header(&TargetAddr::Ip(from))?;
+                    datagram.extend_from_slice(&buf[..n]);
+                    socket.send_to(&datagram, client_addr).await?;
+                } else {
+                    debug!("dropping datagram from {}", from);
+                }
+            }
+        };
+
+        let control = async {
+            let mut buf = [0u8; 64];
+            while client.read(&mut buf).await? > 0 {}
+            Ok(())
+        };
+
+        future::or(control, relay).await
+    }
+}
+
+/// Targets a UDP association sent datagrams to, with the address each
+/// resolved to, so only these may answer and each is looked up once.
+/// Holds at most `limit` entries, dropping the least recently used.
+struct UdpTargets {
+    limit: usize,
+    entries: HashMap<TargetAddr, (SocketAddr, u64)>,
+    clock: u64,
+}
+
+impl UdpTargets {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Address `target` resolved to, marking it as used.
+    fn get(&mut self, target: &TargetAddr) -> Option<SocketAddr> {
+        self.clock += 1;
+        let (addr, used) = self.entries.get_mut(target)?;
+        *used = self.clock;
+        Some(*addr)
+    }
+
+    /// Remember that `target` resolved to `addr`, and return `addr`.
+    fn insert(&mut self, target: TargetAddr, addr: SocketAddr) -> SocketAddr {
+        if self.entries.len() >= self.limit {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(oldest) = oldest.map(|(target, _)| target.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(target, (addr, self.clock));
+        addr
+    }
+
+    /// Whether a datagram was sent to `addr`.
+    fn contacted(&self, addr: SocketAddr) -> bool {
+        self.entries.values().any(|(a, _)| *a == addr)
+    }
+}
+
+/// Whether a datagram from `from` may come from the client connected from
+/// `peer`, which announced `source` as its UDP address.
+fn matches_source(from: SocketAddr, peer: SocketAddr, source: SocketAddr) -> bool {
+    let ip = match source.ip().is_unspecified() {
+        true => peer.ip(),
+        false => source.ip(),
+    };
+    from.ip() == ip && (source.port() == 0 || from.port() == source.port())
+}
+
+/// Resolve `target` locally and keep the addresses `rules` allow for
+/// `command`, failing with a [`ReplyCode::NotAllowed`] error if none are.
+async fn permitted(
+    rules: &Rules,
+    command: Command,
+    target: &TargetAddr,
+) -> Result<Vec<SocketAddr>, Socks5Error> {
+    let (domain, addrs) = match target {
+        TargetAddr::Ip(addr) => (None, vec![*addr]),
+        TargetAddr::Domain(host, port) => match async_net::resolve((host.as_str(), *port)).await {
+            Ok(addrs) => (Some(host.as_str()), addrs),
+            Err(e) => {
+                debug!("resolving {} failed: {}", host, e);
+                return Err(e.into());
+            }
+        },
+    };
+
+    let addrs: Vec<_> = addrs
+        .into_iter()
+        .filter(|addr| rules.allows(command, domain, addr))
+        .collect();
+    if addrs.is_empty() {
+        debug!("{:?} to {} denied by the rules", command, target);
+        return Err(Socks5Error::Reply(ReplyCode::NotAllowed));
+    }
+    Ok(addrs)
+}
+
+/// REP code for a request failing before the server could act on it
+fn failure_code(e: &Socks5Error) -> ReplyCode {
+    match e {
+        Socks5Error::Reply(code) => *code,
+        Socks5Error::IoError(e) => reply_code(e),
+        _ => ReplyCode::GeneralFailure,
+    }
+}
+
+/// REP code telling the client why dialing its target failed
+fn reply_code(e: &io::Error) -> ReplyCode {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => ReplyCode::ConnectionRefused,
+        io::ErrorKind::HostUnreachable => ReplyCode::HostUnreachable,
+        io::ErrorKind::NetworkUnreachable => ReplyCode::NetworkUnreachable,
+        _ => ReplyCode::GeneralFailure,
+    }
+}
+
+/// Send a reply with `rep` and the bound address, `0.0.0.0:0` if none.
+async fn reply<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    rep: ReplyCode,
+    bound_addr: Option<SocketAddr>,
+) -> Result<(), Socks5Error> {
+    let bound_addr = bound_addr.unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    reply_bound(stream, rep, TargetAddr::Ip(bound_addr)).await
+}
+
+/// Send a reply carrying `bound_addr`, which may be a domain.
+async fn reply_bound<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    rep: ReplyCode,
+    bound_addr: TargetAddr,
+) -> Result<(), Socks5Error> {
+    let reply = Reply {
+        code: rep,
+        bound_addr,
+    };
+    stream.write_all(&reply.encode()?).await?;
+    Ok(())
+}
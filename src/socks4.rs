@@ -0,0 +1,110 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use async_net::TcpStream;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::Socks5Error;
+
+/// SOCKS4 and SOCKS4a client, for older proxies that don't speak SOCKS5.
+///
+/// SOCKS4 only knows IPv4 targets and has no authentication besides an
+/// unverified user id. Errors are reported with [`Socks5Error`].
+pub struct Socks4Client;
+
+impl Socks4Client {
+    /// Connect through the given SOCKS4 proxy to the given IPv4 address.
+    /// Optionally, provide a user id, which some proxies use for access
+    /// control.
+    pub async fn connect(
+        proxy_addr: &str,
+        target_addr: &SocketAddrV4,
+        user_id: Option<&str>,
+    ) -> Result<TcpStream, Socks5Error> {
+        let request = Socks4Client::build_request(*target_addr.ip(), target_addr.port(), user_id)?;
+        Socks4Client::negotiate(proxy_addr, &request).await
+    }
+
+    /// Connect through the given SOCKS4a proxy to the given host and port.
+    /// DNS resolution will be done on the proxy side.
+    /// Optionally, provide a user id, which some proxies use for access
+    /// control.
+    pub async fn connect_with_domain(
+        proxy_addr: &str,
+        domain: &str,
+        port: u16,
+        user_id: Option<&str>,
+    ) -> Result<TcpStream, Socks5Error> {
+        if domain.is_empty() || domain.contains('\0') {
+            return Err(Socks5Error::InvalidInput(
+                "domain must be non-empty without NUL",
+            ));
+        }
+
+        // The 0.0.0.x sentinel tells the proxy a domain follows the user id
+        let mut request = Socks4Client::build_request(Ipv4Addr::new(0, 0, 0, 1), port, user_id)?;
+        request.extend_from_slice(domain.as_bytes());
+        request.push(0x00);
+
+        Socks4Client::negotiate(proxy_addr, &request).await
+    }
+
+    /// Internal method building a CONNECT request up to and including the
+    /// NUL-terminated user id.
+    fn build_request(
+        ip: Ipv4Addr,
+        port: u16,
+        user_id: Option<&str>,
+    ) -> Result<Vec<u8>, Socks5Error> {
+        let user_id = user_id.unwrap_or("");
+        if user_id.contains('\0') {
+            return Err(Socks5Error::InvalidInput("user id must not contain NUL"));
+        }
+
+        let mut request = vec![0x04, 0x01];
+        request.extend_from_slice(&port.to_be_bytes());
+        request.extend_from_slice(&ip.octets());
+        request.extend_from_slice(user_id.as_bytes());
+        request.push(0x00);
+        Ok(request)
+    }
+
+    /// Internal method sending `request` to the proxy and checking the
+    /// 8-byte reply.
+    async fn negotiate(proxy_addr: &str, request: &[u8]) -> Result<TcpStream, Socks5Error> {
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        stream.write_all(request).await?;
+
+        let mut reply = [0u8; 8];
+        stream.read_exact(&mut reply).await?;
+
+        if reply[0] != 0x00 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        match reply[1] {
+            0x5a => Ok(stream),
+            0x5b => Err(Socks5Error::ConnectionFailed),
+            // The proxy couldn't reach our identd, or it disagreed with the user id
+            0x5c | 0x5d => Err(Socks5Error::AuthenticationFailed),
+            _ => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Experimental SOCKS6 client, following draft-olteanu-intarea-socks-6-11.
+//! The draft may still change, and so may this module.
+
+use std::net::{IpAddr, SocketAddr};
+
+use async_net::TcpStream;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{idna, Credentials, ReplyCode, Socks5Error, TargetAddr};
+
+const VERSION: u8 = 0x06;
+const CMD_CONNECT: u8 = 0x01;
+
+const OPTION_AUTH_ADVERTISEMENT: u16 = 2;
+const OPTION_AUTH_DATA: u16 = 4;
+
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+
+/// SOCKS6 client, for proxies implementing the draft protocol.
+///
+/// Targets and credentials are the ones the SOCKS5 client uses, and
+/// errors are reported with [`Socks5Error`]; the reply codes SOCKS6
+/// shares with SOCKS5 map to [`Socks5Error::Reply`]. Authentication
+/// takes no extra round trip, as it is sent along with the request,
+/// and so can the first bytes for the target (0-RTT data).
+pub struct Socks6Client;
+
+impl Socks6Client {
+    /// Connect through the given SOCKS6 proxy to `target`, sending
+    /// `initial_data` to the target along with the request, which saves
+    /// waiting for the reply before talking. Pass an empty slice to send
+    /// nothing early. With `credentials`, username/password
+    /// authentication is offered. Returns the stream and the address the
+    /// proxy reports having bound for the connection.
+    pub async fn connect(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        credentials: Option<&Credentials>,
+        initial_data: &[u8],
+    ) -> Result<(TcpStream, TargetAddr), Socks5Error> {
+        let mut request = Socks6Client::build_request(target, credentials, initial_data)?;
+        request.extend_from_slice(initial_data);
+
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        stream.write_all(&request).await?;
+
+        // Authentication reply: version, type, options length, options
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[0] != VERSION {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+        skip(&mut stream, u16::from_be_bytes([header[2], header[3]])).await?;
+        match header[1] {
+            0x00 => {}
+            0x01 => return Err(Socks5Error::AuthenticationFailed),
+            _ => return Err(Socks5Error::UnexpectedResponse),
+        }
+
+        // Operation reply: version, code, options length, port, padding,
+        // address type, address, options
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await?;
+        if header[0] != VERSION {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+        let port = u16::from_be_bytes([header[4], header[5]]);
+        let bound_addr = read_addr(&mut stream, header[7], port).await?;
+        skip(&mut stream, u16::from_be_bytes([header[2], header[3]])).await?;
+
+        match header[1] {
+            0x00 => Ok((stream, bound_addr)),
+            code => Err(Socks5Error::Reply(ReplyCode::from(code))),
+        }
+    }
+
+    /// Build the CONNECT request, advertising the authentication method
+    /// and the amount of initial data, and carrying the credentials.
+    fn build_request(
+        target: &TargetAddr,
+        credentials: Option<&Credentials>,
+        initial_data: &[u8],
+    ) -> Result<Vec<u8>, Socks5Error> {
+        let initial_len: u16 = initial_data
+            .len()
+            .try_into()
+            .map_err(|_| Socks5Error::InvalidInput("initial data longer than 65535 bytes"))?;
+
+        let mut options = Vec::new();
+        let mut advertisement = initial_len.to_be_bytes().to_vec();
+        if credentials.is_some() {
+            advertisement.push(METHOD_USERNAME_PASSWORD);
+        }
+        push_option(&mut options, OPTION_AUTH_ADVERTISEMENT, &advertisement);
+
+        if let Some(credentials) = credentials {
+            // Method, then the RFC 1929 request
+            let (username, password) = (credentials.username(), credentials.password());
+            let mut data = vec![METHOD_USERNAME_PASSWORD, 0x01, username.len() as u8];
+            data.extend_from_slice(username);
+            data.push(password.len() as u8);
+            data.extend_from_slice(password);
+            push_option(&mut options, OPTION_AUTH_DATA, &data);
+        }
+
+        let options_len: u16 = options
+            .len()
+            .try_into()
+            .map_err(|_| Socks5Error::InvalidInput("SOCKS6 options too long"))?;
+
+        let (port, addr) = match target {
+            TargetAddr::Ip(addr) => (addr.port(), encode_ip(addr.ip())),
+            TargetAddr::Domain(domain, port) => {
+                let domain = idna::to_ascii(domain)?;
+                if domain.is_empty() || domain.len() > 255 {
+                    return Err(Socks5Error::InvalidInput(
+                        "domain must be between 1 and 255 bytes",
+                    ));
+                }
+                let mut addr = vec![0x02, domain.len() as u8];
+                addr.extend_from_slice(domain.as_bytes());
+                // The address, after the type, is padded to 4 bytes
+                addr.resize(1 + padded(addr.len() - 1), 0);
+                (*port, addr)
+            }
+        };
+
+        let mut request = vec![VERSION, CMD_CONNECT];
+        request.extend_from_slice(&options_len.to_be_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        request.push(0x00);
+        request.extend_from_slice(&addr);
+        request.extend_from_slice(&options);
+        Ok(request)
+    }
+}
+
+/// Address type and address of `ip`
+fn encode_ip(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => [&[0x01][..], &ip.octets()].concat(),
+        IpAddr::V6(ip) => [&[0x03][..], &ip.octets()].concat(),
+    }
+}
+
+/// Append an option: kind, total length and `data` zero-padded to a
+/// multiple of 4 bytes.
+fn push_option(options: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    let len = 4 + padded(data.len());
+    options.extend_from_slice(&kind.to_be_bytes());
+    options.extend_from_slice(&(len as u16).to_be_bytes());
+    options.extend_from_slice(data);
+    options.resize(options.len() + len - 4 - data.len(), 0);
+}
+
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+/// Read an address of type `atyp` from `stream`.
+async fn read_addr(stream: &mut TcpStream, atyp: u8, port: u16) -> Result<TargetAddr, Socks5Error> {
+    match atyp {
+        0x01 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            Ok(TargetAddr::Ip(SocketAddr::new(octets.into(), port)))
+        }
+        0x03 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            Ok(TargetAddr::Ip(SocketAddr::new(octets.into(), port)))
+        }
+        0x02 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut domain = vec![0u8; padded(1 + len[0] as usize) - 1];
+            stream.read_exact(&mut domain).await?;
+            domain.truncate(len[0] as usize);
+            let domain = String::from_utf8(domain).map_err(|_| Socks5Error::UnexpectedResponse)?;
+            Ok(TargetAddr::Domain(domain, port))
+        }
+        _ => Err(Socks5Error::UnsupportedAddressType),
+    }
+}
+
+/// Read and discard `len` bytes of options.
+async fn skip(stream: &mut TcpStream, len: u16) -> Result<(), Socks5Error> {
+    let mut options = vec![0u8; len as usize];
+    stream.read_exact(&mut options).await?;
+    Ok(())
+}
@@ -0,0 +1,423 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_io::{Async, Timer};
+use async_net::TcpStream;
+use futures_lite::future;
+use futures_lite::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+use crate::{Framed, TargetAddr};
+
+/// Unique identifier of a connection attempt, used to correlate the
+/// events belonging to a single connection in logs.
+///
+/// By default this is a process-wide counter. With the `uuid` feature it
+/// is a random UUID instead, which stays unique across processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(
+    #[cfg(not(feature = "uuid"))] u64,
+    #[cfg(feature = "uuid")] uuid::Uuid,
+);
+
+impl ConnectionId {
+    /// Generate a new connection id.
+    #[cfg(not(feature = "uuid"))]
+    pub(crate) fn next() -> Self {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Generate a new connection id.
+    #[cfg(feature = "uuid")]
+    pub(crate) fn next() -> Self {
+        Self(uuid::Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Information about an established SOCKS5 connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Socks5ConnectInfo {
+    /// Identifier of the connection attempt
+    pub connection_id: ConnectionId,
+    /// IP address of the proxy the TCP connection was made to. When the
+    /// proxy hostname resolves to several addresses, this is the one that
+    /// was actually used.
+    pub proxy_connected_ip: IpAddr,
+    /// Address and port of the proxy the TCP connection was made to
+    pub proxy_addr: SocketAddr,
+    /// Target the proxy was asked to connect to
+    pub target: TargetAddr,
+    /// Number of bytes read from the proxy while negotiating (method
+    /// selection, authentication and CONNECT replies). Unusually large
+    /// values may point at a misbehaving or malicious proxy.
+    pub handshake_bytes_read: usize,
+    /// Authentication method the proxy selected, 0x00 for none or 0x02
+    /// for username/password
+    pub auth_method: u8,
+    /// BND.ADDR and BND.PORT from the proxy's reply: the address the
+    /// proxy connects to the target from, as needed for FTP active mode
+    /// or NAT traversal. Proxies may report all zeros.
+    pub bound_addr: TargetAddr,
+    /// How long each phase of establishing the connection took
+    pub timings: ConnectTimings,
+}
+
+/// Durations of the phases of a connection through a proxy, telling the
+/// latency of the proxy apart from that of the target: the proxy
+/// answers the method selection and authentication itself, while the
+/// reply to the request comes once it reached the target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ConnectTimings {
+    /// Establishing the TCP connection to the proxy
+    pub connect: Duration,
+    /// From sending the greeting to the proxy selecting a method
+    pub method_selection: Duration,
+    /// Running the selected authentication method, zero without one
+    pub authentication: Duration,
+    /// From sending the request to receiving the reply
+    pub request: Duration,
+}
+
+impl ConnectTimings {
+    /// Time from dialing the proxy until the tunnel was ready
+    pub fn total(&self) -> Duration {
+        self.connect + self.method_selection + self.authentication + self.request
+    }
+}
+
+/// Error inside the [`io::ErrorKind::TimedOut`] errors a
+/// [`Socks5Stream`] fails with once its idle timeout expired, telling
+/// them apart from other timeouts:
+///
+/// ```
+/// # use async_socks5::IdleTimeout;
+/// # fn check(e: std::io::Error) -> bool {
+/// e.get_ref().is_some_and(|e| e.is::<IdleTimeout>())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdleTimeout;
+
+impl fmt::Display for IdleTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection was idle for too long")
+    }
+}
+
+impl Error for IdleTimeout {}
+
+/// Stream tunneled through a SOCKS5 proxy.
+///
+/// Reads and writes are passed straight through to the underlying
+/// stream to the proxy, a [`TcpStream`] unless the proxy is reached
+/// over TLS, counting the bytes tunneled in each direction.
+#[derive(Debug)]
+pub struct Socks5Stream<S = TcpStream> {
+    inner: S,
+    info: Socks5ConnectInfo,
+    bytes_sent: u64,
+    bytes_received: u64,
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+    idle_timer: Option<Timer>,
+    idle_expired: bool,
+}
+
+impl<S> Socks5Stream<S> {
+    pub(crate) fn new(inner: S, info: Socks5ConnectInfo) -> Self {
+        Self {
+            inner,
+            info,
+            bytes_sent: 0,
+            bytes_received: 0,
+            idle_timeout: None,
+            last_activity: Instant::now(),
+            idle_timer: None,
+            idle_expired: false,
+        }
+    }
+
+    /// Abort the connection when no bytes were read or written for
+    /// `timeout`, as connections over Tor can die without the stream
+    /// noticing. Pending and later reads and writes then fail with
+    /// [`io::ErrorKind::TimedOut`] carrying [`IdleTimeout`]. Only applies
+    /// to reads and writes through this stream, not after
+    /// [`Socks5Stream::split`].
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self.last_activity = Instant::now();
+        self
+    }
+
+    /// Information about how the connection was established
+    pub fn info(&self) -> &Socks5ConnectInfo {
+        &self.info
+    }
+
+    /// Target the stream is tunneled to. Shorthand for `info().target`.
+    pub fn target_addr(&self) -> &TargetAddr {
+        &self.info.target
+    }
+
+    /// Address of the proxy the stream goes through. Shorthand for
+    /// `info().proxy_addr`.
+    pub fn proxy_addr(&self) -> SocketAddr {
+        self.info.proxy_addr
+    }
+
+    /// Address the proxy bound for this connection, as reported in its
+    /// reply. Shorthand for `info().bound_addr`.
+    pub fn bound_addr(&self) -> &TargetAddr {
+        &self.info.bound_addr
+    }
+
+    /// Authentication method the proxy selected. Shorthand for
+    /// `info().auth_method`.
+    pub fn auth_method(&self) -> u8 {
+        self.info.auth_method
+    }
+
+    /// Number of bytes written to the target through this stream, not
+    /// counting the handshake. Writes made through [`Socks5Stream::get_mut`]
+    /// or after [`Socks5Stream::split`] aren't counted.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Number of bytes read from the target through this stream, not
+    /// counting the handshake.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consume the wrapper and return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Wrap the stream in a [`Framed`] adaptor exchanging u32
+    /// length-prefixed frames of at most `max_frame` bytes.
+    pub fn framed(self, max_frame: usize) -> Framed<Self> {
+        Framed::new(self, max_frame)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Socks5Stream<S> {
+    /// Split the stream into owned read and write halves, e.g. to read
+    /// and write from separate tasks. Closing the write half shuts down
+    /// the sending side of the connection.
+    pub fn split(self) -> (ReadHalf<S>, WriteHalf<S>) {
+        futures_lite::io::split(self.inner)
+    }
+}
+
+impl Socks5Stream<TcpStream> {
+    /// Wait until the peer closes the connection (the read side reaches
+    /// EOF). This doesn't consume any data: EOF is only observed once all
+    /// bytes received before it have been read, so while such bytes are
+    /// pending this doesn't complete.
+    /// Useful for pool health checks and for noticing a remote close
+    /// without blocking in a read.
+    pub async fn closed(&self) -> io::Result<()> {
+        let socket = Arc::<Async<std::net::TcpStream>>::from(self.inner.clone());
+        socket.readable().await?;
+
+        let mut buf = [0u8; 1];
+        if self.inner.peek(&mut buf).await? == 0 {
+            return Ok(());
+        }
+
+        // Unread bytes keep the socket readable, and nothing signals EOF
+        // behind them
+        future::pending().await
+    }
+
+    /// Flush pending writes and shut down the write half, so the target
+    /// sees EOF while the response can still be read. This is the right
+    /// way to end a request in request/response protocols such as HTTP
+    /// with `Connection: close`: shutting down both halves would drop the
+    /// reply.
+    pub async fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.flush().await?;
+        self.inner.shutdown(Shutdown::Write)
+    }
+
+    /// Shut down the read half. Further reads return EOF while writes
+    /// keep going through.
+    pub fn shutdown_read(&self) -> io::Result<()> {
+        self.inner.shutdown(Shutdown::Read)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Socks5Stream<S> {
+    /// Account for the outcome of a read or write. While it is pending,
+    /// wake the task at the idle deadline, and abort the connection
+    /// once that has passed.
+    fn track_idle(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: &Poll<io::Result<usize>>,
+    ) -> io::Result<()> {
+        let timeout = match (self.idle_timeout, poll) {
+            (None, _) => return Ok(()),
+            (Some(_), Poll::Ready(Ok(n))) if *n > 0 => {
+                self.last_activity = Instant::now();
+                return Ok(());
+            }
+            (Some(_), Poll::Ready(_)) => return Ok(()),
+            (Some(timeout), Poll::Pending) => timeout,
+        };
+
+        let deadline = self.last_activity + timeout;
+        let timer = self.idle_timer.get_or_insert_with(|| Timer::at(deadline));
+        timer.set_at(deadline);
+        if Instant::now() < deadline && Pin::new(timer).poll(cx).is_pending() {
+            return Ok(());
+        }
+
+        // Closing lets the peer see the connection end
+        self.idle_expired = true;
+        self.idle_timer = None;
+        let _ = Pin::new(&mut self.inner).poll_close(cx);
+        Err(idle_timed_out())
+    }
+}
+
+fn idle_timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, IdleTimeout)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Socks5Stream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.idle_expired {
+            return Poll::Ready(Err(idle_timed_out()));
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.bytes_received += n as u64;
+        }
+        self.track_idle(cx, &poll)?;
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Socks5Stream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.idle_expired {
+            return Poll::Ready(Err(idle_timed_out()));
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.bytes_sent += n as u64;
+        }
+        self.track_idle(cx, &poll)?;
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Internal stream adaptor counting the bytes read through it
+pub(crate) struct Counted<'a, S> {
+    inner: &'a mut S,
+    pub(crate) read: usize,
+}
+
+impl<'a, S> Counted<'a, S> {
+    pub(crate) fn new(inner: &'a mut S) -> Self {
+        Self { inner, read: 0 }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.read += n;
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,323 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tools for testing code built on this crate without real sockets or a
+//! running proxy. Enabled with the `testing` feature.
+//!
+//! ```
+//! use async_socks5::testing::MockSocks5Server;
+//! use async_socks5::{Socks5Client, TargetAddr};
+//!
+//! smol::block_on(async {
+//!     let listener = MockSocks5Server::new().listen().await.unwrap();
+//!     let proxy = listener.local_addr().unwrap().to_string();
+//!     let server = smol::spawn(async move { listener.accept().await.unwrap() });
+//!
+//!     Socks5Client::connect_with_domain(&proxy, "example.com", 80, None)
+//!         .await
+//!         .unwrap();
+//!
+//!     let (_stream, request) = server.await;
+//!     assert_eq!(request.target, TargetAddr::Domain("example.com".into(), 80));
+//! });
+//! ```
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_io::Timer;
+use async_net::{TcpListener, TcpStream};
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{protocol, AddrType, TargetAddr};
+
+/// One end of an in-memory bidirectional stream created by [`duplex`]
+pub struct DuplexStream {
+    reader: piper::Reader,
+    writer: piper::Writer,
+}
+
+impl fmt::Debug for DuplexStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuplexStream").finish_non_exhaustive()
+    }
+}
+
+/// Create a pair of connected in-memory streams. Bytes written to one end
+/// can be read from the other, with up to `capacity` bytes buffered in
+/// each direction. Closing one end makes reads on the other return EOF.
+pub fn duplex(capacity: usize) -> (DuplexStream, DuplexStream) {
+    let (a_reader, b_writer) = piper::pipe(capacity);
+    let (b_reader, a_writer) = piper::pipe(capacity);
+
+    let a = DuplexStream {
+        reader: a_reader,
+        writer: a_writer,
+    };
+    let b = DuplexStream {
+        reader: b_reader,
+        writer: b_writer,
+    };
+
+    (a, b)
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+/// What a client asked of a [`MockSocks5Server`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockRequest {
+    /// Authentication methods offered in the greeting
+    pub methods: Vec<u8>,
+    /// Username and password, if the client authenticated
+    pub credentials: Option<(Vec<u8>, Vec<u8>)>,
+    /// Command byte of the request
+    pub command: u8,
+    /// Target address of the request
+    pub target: TargetAddr,
+}
+
+/// Scripted SOCKS5 server for tests.
+///
+/// It accepts one request per connection, answers with the configured
+/// reply, and hands the stream back so the test can act as the target.
+#[derive(Clone, Debug)]
+pub struct MockSocks5Server {
+    credentials: Option<(String, String)>,
+    reply: u8,
+    bound_addr: TargetAddr,
+    reply_delay: Option<Duration>,
+    truncate_reply: Option<usize>,
+}
+
+impl Default for MockSocks5Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockSocks5Server {
+    /// Create a server accepting unauthenticated clients and answering
+    /// every request with success and the bound address `0.0.0.0:0`.
+    pub fn new() -> Self {
+        Self {
+            credentials: None,
+            reply: 0x00,
+            bound_addr: TargetAddr::Ip(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+            reply_delay: None,
+            truncate_reply: None,
+        }
+    }
+
+    /// Require username/password authentication with these credentials.
+    pub fn require_auth(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Answer requests with the REP code `reply` instead of success.
+    pub fn reply_code(mut self, reply: u8) -> Self {
+        self.reply = reply;
+        self
+    }
+
+    /// Report `addr` as BND.ADDR/BND.PORT in replies.
+    pub fn bound_addr(mut self, addr: TargetAddr) -> Self {
+        self.bound_addr = addr;
+        self
+    }
+
+    /// Wait `delay` before answering requests, as a slow proxy would.
+    pub fn reply_delay(mut self, delay: Duration) -> Self {
+        self.reply_delay = Some(delay);
+        self
+    }
+
+    /// Only send the first `len` bytes of replies, as a proxy hanging
+    /// up mid-reply would. Drop the stream returned with the request to
+    /// close the connection.
+    pub fn truncate_reply(mut self, len: usize) -> Self {
+        self.truncate_reply = Some(len);
+        self
+    }
+
+    /// Run the server side of the protocol over `stream` and return the
+    /// client's request. Afterwards the stream carries tunneled data.
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+    ) -> io::Result<MockRequest> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x05 {
+            return Err(invalid_data("not a SOCKS5 greeting"));
+        }
+
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).await?;
+
+        let method = if self.credentials.is_some() {
+            0x02
+        } else {
+            0x00
+        };
+        if !methods.contains(&method) {
+            stream.write_all(&[0x05, 0xff]).await?;
+            return Err(invalid_data("no acceptable authentication methods"));
+        }
+        stream.write_all(&[0x05, method]).await?;
+
+        let credentials = match &self.credentials {
+            Some(expected) => Some(self.authenticate(stream, expected).await?),
+            None => None,
+        };
+
+        let mut request = [0u8; 3];
+        stream.read_exact(&mut request).await?;
+
+        let mut addr = vec![0u8; 1];
+        stream.read_exact(&mut addr).await?;
+        let rest = match AddrType::try_from(addr[0]) {
+            Ok(AddrType::IPv4) => 4 + 2,
+            Ok(AddrType::IPv6) => 16 + 2,
+            Ok(AddrType::DomainName) => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await?;
+                addr.push(len[0]);
+                len[0] as usize + 2
+            }
+            Err(_) => return Err(invalid_data("unsupported address type")),
+        };
+
+        let start = addr.len();
+        addr.resize(start + rest, 0);
+        stream.read_exact(&mut addr[start..]).await?;
+
+        let (target, _) =
+            protocol::decode_addr(&addr).map_err(|_| invalid_data("malformed request address"))?;
+
+        let reply = protocol::encode_request(self.reply, &self.bound_addr)
+            .map_err(|_| invalid_data("malformed bound address"))?;
+        if let Some(delay) = self.reply_delay {
+            Timer::after(delay).await;
+        }
+        let len = self
+            .truncate_reply
+            .map_or(reply.len(), |len| len.min(reply.len()));
+        stream.write_all(&reply[..len]).await?;
+
+        Ok(MockRequest {
+            methods,
+            credentials,
+            command: request[1],
+            target,
+        })
+    }
+
+    /// Check RFC 1929 credentials against `expected`.
+    async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        expected: &(String, String),
+    ) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut username = vec![0u8; len[1] as usize];
+        stream.read_exact(&mut username).await?;
+
+        stream.read_exact(&mut len[..1]).await?;
+        let mut password = vec![0u8; len[0] as usize];
+        stream.read_exact(&mut password).await?;
+
+        if username != expected.0.as_bytes() || password != expected.1.as_bytes() {
+            stream.write_all(&[0x01, 0x01]).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "authentication failed",
+            ));
+        }
+
+        stream.write_all(&[0x01, 0x00]).await?;
+        Ok((username, password))
+    }
+
+    /// Listen for clients on an ephemeral localhost port.
+    pub async fn listen(self) -> io::Result<MockListener> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        Ok(MockListener {
+            listener,
+            server: self,
+        })
+    }
+}
+
+/// [`MockSocks5Server`] listening on a localhost TCP port
+#[derive(Debug)]
+pub struct MockListener {
+    listener: TcpListener,
+    server: MockSocks5Server,
+}
+
+impl MockListener {
+    /// Address to use as the proxy address in tests
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept a client and serve its request. Returns the stream, now
+    /// carrying tunneled data, and the request.
+    pub async fn accept(&self) -> io::Result<(TcpStream, MockRequest)> {
+        let (mut stream, _) = self.listener.accept().await?;
+        let request = self.server.serve(&mut stream).await?;
+        Ok((stream, request))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
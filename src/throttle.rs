@@ -0,0 +1,200 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use futures_lite::io::{AsyncRead, AsyncWrite};
+
+/// Stream wrapper limiting how many bytes per second are read and
+/// written, with a separate token bucket for each direction.
+///
+/// Each bucket holds up to a second's worth of bytes, so a connection
+/// that was quiet can burst that much before being slowed down. Wraps
+/// connections returned by the client as well as anything else, and
+/// [`Socks5Server::upload_limit`](crate::Socks5Server::upload_limit) and
+/// [`Socks5Server::download_limit`](crate::Socks5Server::download_limit)
+/// apply it to relayed clients.
+///
+/// ```no_run
+/// use async_socks5::{Socks5Client, Throttled};
+///
+/// # smol::block_on(async {
+/// let stream = Socks5Client::connect_with_domain("127.0.0.1:1080", "example.com", 80, None).await?;
+/// // 1 MiB/s down, 256 KiB/s up
+/// let stream = Throttled::new(stream)
+///     .read_limit(1024 * 1024)
+///     .write_limit(256 * 1024);
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Throttled<S> {
+    inner: S,
+    read: Option<Bucket>,
+    write: Option<Bucket>,
+}
+
+impl<S> Throttled<S> {
+    /// Wrap `inner` without any limit yet.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            read: None,
+            write: None,
+        }
+    }
+
+    /// Read at most `bytes_per_second` from the stream.
+    pub fn read_limit(mut self, bytes_per_second: u64) -> Self {
+        self.read = Some(Bucket::new(bytes_per_second));
+        self
+    }
+
+    /// Write at most `bytes_per_second` to the stream.
+    pub fn write_limit(mut self, bytes_per_second: u64) -> Self {
+        self.write = Some(Bucket::new(bytes_per_second));
+        self
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consume the wrapper and return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+    timer: Option<Timer>,
+}
+
+impl Bucket {
+    fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second.max(1) as f64;
+        Self {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+            timer: None,
+        }
+    }
+
+    /// Number of bytes that may be transferred now, at most `wanted`,
+    /// or `Pending` until the bucket has refilled a little.
+    fn poll_allowance(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(timer) = &mut self.timer {
+                if Pin::new(timer).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.timer = None;
+            }
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+            self.refilled = now;
+
+            if self.tokens >= 1.0 {
+                return Poll::Ready((self.tokens as usize).min(wanted));
+            }
+            let wait = (1.0 - self.tokens) / self.rate;
+            self.timer = Some(Timer::after(Duration::from_secs_f64(wait)));
+        }
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.tokens -= n as f64;
+    }
+}
+
+/// Run `io` on at most the allowance of `bucket` bytes from `len`, then
+/// take what was transferred from the bucket.
+fn throttle(
+    bucket: &mut Option<Bucket>,
+    cx: &mut Context<'_>,
+    len: usize,
+    io: impl FnOnce(&mut Context<'_>, usize) -> Poll<io::Result<usize>>,
+) -> Poll<io::Result<usize>> {
+    let Some(bucket) = bucket else {
+        return io(cx, len);
+    };
+    if len == 0 {
+        return io(cx, 0);
+    }
+
+    let allowed = futures_lite::ready!(bucket.poll_allowance(cx, len));
+    let poll = io(cx, allowed);
+    if let Poll::Ready(Ok(n)) = poll {
+        bucket.consume(n);
+    }
+    poll
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        throttle(&mut this.read, cx, buf.len(), |cx, allowed| {
+            Pin::new(inner).poll_read(cx, &mut buf[..allowed])
+        })
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        throttle(&mut this.write, cx, buf.len(), |cx, allowed| {
+            Pin::new(inner).poll_write(cx, &buf[..allowed])
+        })
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,141 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_lite::future::FutureExt;
+use futures_lite::io::{AsyncRead, AsyncWrite};
+
+use crate::{Phase, Socks5Error};
+
+/// Boxed future returned by [`Timer::sleep`]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Time source used to enforce timeouts.
+///
+/// The default, [`AsyncIoTimer`], uses the `async-io` reactor timer.
+/// Tests can provide their own implementation to control when timeouts
+/// fire without really sleeping.
+pub trait Timer: fmt::Debug + Send + Sync {
+    /// Return a future that completes after `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// [`Timer`] backed by [`async_io::Timer`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncIoTimer;
+
+impl Timer for AsyncIoTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+}
+
+/// Run `future`, failing with [`Socks5Error::Timeout`] in `phase` if it
+/// does not complete within `duration` as measured by `timer`.
+pub(crate) async fn timeout<T, F>(
+    timer: &dyn Timer,
+    duration: Duration,
+    phase: Phase,
+    future: F,
+) -> Result<T, Socks5Error>
+where
+    F: Future<Output = Result<T, Socks5Error>>,
+{
+    let sleep = timer.sleep(duration);
+    future
+        .or(async move {
+            sleep.await;
+            Err(Socks5Error::Timeout(phase))
+        })
+        .await
+}
+
+/// Stream failing reads with [`io::ErrorKind::TimedOut`] when no data
+/// arrives for `duration`. Every read that makes progress restarts the
+/// clock.
+pub(crate) struct ReadTimeout<'a, S> {
+    inner: S,
+    timer: &'a dyn Timer,
+    duration: Duration,
+    sleep: Option<Sleep>,
+}
+
+impl<'a, S> ReadTimeout<'a, S> {
+    pub(crate) fn new(inner: S, timer: &'a dyn Timer, duration: Duration) -> Self {
+        Self {
+            inner,
+            timer,
+            duration,
+            sleep: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ReadTimeout<'_, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Poll::Ready(result) = Pin::new(&mut this.inner).poll_read(cx, buf) {
+            this.sleep = None;
+            return Poll::Ready(result);
+        }
+
+        let sleep = this
+            .sleep
+            .get_or_insert_with(|| this.timer.sleep(this.duration));
+        match sleep.poll(cx) {
+            Poll::Ready(()) => {
+                this.sleep = None;
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "read timed out",
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ReadTimeout<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,150 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_net::TcpStream;
+use futures_rustls::client::TlsStream;
+use futures_rustls::rustls::crypto::ring;
+use futures_rustls::rustls::pki_types::ServerName;
+use futures_rustls::rustls::{ClientConfig, RootCertStore};
+use futures_rustls::TlsConnector;
+
+use crate::{Socks5Client, Socks5Error, Socks5Stream, TargetAddr};
+
+/// Build a rustls client configuration trusting the webpki root store.
+fn default_client_config() -> Result<Arc<ClientConfig>, Socks5Error> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+/// Parse `name` into a rustls server name.
+fn server_name(name: &str) -> Result<ServerName<'static>, Socks5Error> {
+    let name = ServerName::try_from(name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(name)
+}
+
+impl Socks5Client {
+    /// Connect to a SOCKS5 proxy that wraps the protocol itself in TLS,
+    /// then perform the SOCKS5 handshake over the encrypted channel.
+    /// The proxy certificate is verified against `proxy_sni` using the
+    /// webpki root store.
+    /// Returns the TLS stream to the proxy, tunneled to `target`.
+    pub async fn connect_proxy_tls(
+        proxy_host: &str,
+        proxy_port: u16,
+        proxy_sni: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TlsStream<TcpStream>, Socks5Error> {
+        let tls_config = default_client_config()?;
+        Socks5Client::connect_proxy_tls_with(
+            tls_config,
+            proxy_host,
+            proxy_port,
+            proxy_sni,
+            target,
+            credentials,
+        )
+        .await
+    }
+
+    /// Like [`Socks5Client::connect_proxy_tls`], but with the given rustls
+    /// configuration, e.g. to trust a private CA in front of the proxy or
+    /// to present a client certificate. The rustls version is the one
+    /// re-exported as [`crate::rustls`].
+    pub async fn connect_proxy_tls_with(
+        tls_config: Arc<ClientConfig>,
+        proxy_host: &str,
+        proxy_port: u16,
+        proxy_sni: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TlsStream<TcpStream>, Socks5Error> {
+        let server_name = server_name(proxy_sni)?;
+
+        let connector = TlsConnector::from(tls_config);
+        let stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+        let stream = connector.connect(server_name, stream).await?;
+        Socks5Client::handshake_over(stream, target, credentials).await
+    }
+
+    /// Like [`Socks5Client::connect_proxy_tls_with`], but returns a
+    /// [`Socks5Stream`] over the TLS stream, carrying the connection
+    /// info and byte counters. Its connect timing includes the TLS
+    /// handshake with the proxy.
+    pub async fn connect_proxy_tls_stream(
+        tls_config: Arc<ClientConfig>,
+        proxy_host: &str,
+        proxy_port: u16,
+        proxy_sni: &str,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5Stream<TlsStream<TcpStream>>, Socks5Error> {
+        let server_name = server_name(proxy_sni)?;
+
+        let connector = TlsConnector::from(tls_config);
+        let started = Instant::now();
+        let stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+        let proxy_addr = stream.peer_addr()?;
+        let stream = connector.connect(server_name, stream).await?;
+        let connect = started.elapsed();
+        Socks5Client::negotiate_stream(stream, proxy_addr, connect, target, credentials).await
+    }
+
+    /// Connect through the given SOCKS5 proxy to `target` and run a TLS
+    /// handshake with the target over the tunnel. The certificate is
+    /// verified against `sni`, or against the target's domain if no SNI
+    /// is given; IP targets require an explicit SNI.
+    ///
+    /// A separate SNI allows connecting to an IP while presenting the
+    /// expected hostname, or domain fronting where the tunnel goes to a
+    /// front host and the SNI names the one behind it. Note that fronting
+    /// only works with providers that still route on the HTTP `Host`
+    /// header, and that the certificate must be valid for the SNI.
+    pub async fn connect_tls(
+        proxy_addr: &str,
+        target: &TargetAddr,
+        sni: Option<&str>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TlsStream<Socks5Stream>, Socks5Error> {
+        let server_name = match (sni, target) {
+            (Some(sni), _) => server_name(sni)?,
+            (None, TargetAddr::Domain(domain, _)) => server_name(domain)?,
+            (None, TargetAddr::Ip(_)) => {
+                return Err(Socks5Error::InvalidInput("IP targets need an explicit SNI"))
+            }
+        };
+
+        let connector = TlsConnector::from(default_client_config()?);
+        let stream = Socks5Client::connect_stream(proxy_addr, target, credentials).await?;
+        Ok(connector.connect(server_name, stream).await?)
+    }
+}
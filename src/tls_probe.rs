@@ -0,0 +1,148 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::timer::{self, AsyncIoTimer};
+use crate::{Phase, Socks5Client, Socks5Error, TargetAddr};
+
+/// TLS 1.2 cipher suites offered in the probe, covering what practically
+/// every HTTPS server accepts.
+const CIPHER_SUITES: [u16; 12] = [
+    0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+];
+
+/// x25519, secp256r1, secp384r1
+const SUPPORTED_GROUPS: [u16; 3] = [0x001d, 0x0017, 0x0018];
+
+const SIGNATURE_ALGORITHMS: [u16; 9] = [
+    0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601, 0x0201,
+];
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Append an extension with the given type and body.
+fn push_extension(buf: &mut Vec<u8>, ext_type: u16, body: &[u8]) {
+    push_u16(buf, ext_type);
+    push_u16(buf, body.len() as u16);
+    buf.extend_from_slice(body);
+}
+
+/// Random bytes for the ClientHello. They carry no security weight since
+/// the handshake is never completed.
+fn client_random() -> [u8; 32] {
+    let mut random = [0u8; 32];
+    for chunk in random.chunks_mut(8) {
+        let n = RandomState::new().build_hasher().finish();
+        chunk.copy_from_slice(&n.to_be_bytes());
+    }
+    random
+}
+
+/// Build a minimal TLS 1.2 ClientHello record for `host`. SNI is only
+/// included for hostnames, as IP literals aren't allowed there.
+fn client_hello(host: &str) -> Vec<u8> {
+    let mut extensions = vec![];
+
+    if host.parse::<IpAddr>().is_err() {
+        let mut sni = vec![];
+        push_u16(&mut sni, host.len() as u16 + 3);
+        sni.push(0x00); // host_name
+        push_u16(&mut sni, host.len() as u16);
+        sni.extend_from_slice(host.as_bytes());
+        push_extension(&mut extensions, 0x0000, &sni);
+    }
+
+    let mut groups = vec![];
+    push_u16(&mut groups, SUPPORTED_GROUPS.len() as u16 * 2);
+    SUPPORTED_GROUPS
+        .iter()
+        .for_each(|g| push_u16(&mut groups, *g));
+    push_extension(&mut extensions, 0x000a, &groups);
+
+    // Uncompressed points only
+    push_extension(&mut extensions, 0x000b, &[0x01, 0x00]);
+
+    let mut sigalgs = vec![];
+    push_u16(&mut sigalgs, SIGNATURE_ALGORITHMS.len() as u16 * 2);
+    SIGNATURE_ALGORITHMS
+        .iter()
+        .for_each(|s| push_u16(&mut sigalgs, *s));
+    push_extension(&mut extensions, 0x000d, &sigalgs);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&client_random());
+    body.push(0x00); // Empty session id
+    push_u16(&mut body, CIPHER_SUITES.len() as u16 * 2);
+    CIPHER_SUITES.iter().for_each(|c| push_u16(&mut body, *c));
+    body.extend_from_slice(&[0x01, 0x00]); // Null compression only
+    push_u16(&mut body, extensions.len() as u16);
+    body.extend_from_slice(&extensions);
+
+    let mut handshake = vec![0x01]; // ClientHello
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    push_u16(&mut record, handshake.len() as u16);
+    record.extend_from_slice(&handshake);
+    record
+}
+
+impl Socks5Client {
+    /// Check whether an HTTPS endpoint is alive through the proxy without
+    /// a TLS stack. A raw ClientHello is sent to `host:port` and the
+    /// target counts as alive if it answers with a ServerHello. An alert
+    /// or a closed tunnel yields `false`; SOCKS5 and I/O failures are
+    /// returned as errors, and a target that doesn't answer within
+    /// `timeout` fails with [`Socks5Error::Timeout`] with [`Phase::Target`].
+    /// The handshake is abandoned right after.
+    pub async fn tls_alive(
+        proxy_addr: &str,
+        host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+        timeout: Duration,
+    ) -> Result<bool, Socks5Error> {
+        let target = TargetAddr::Domain(host.to_string(), port);
+        let mut stream = Socks5Client::connect_stream(proxy_addr, &target, credentials).await?;
+
+        stream.write_all(&client_hello(host)).await?;
+
+        // Record header followed by the handshake message type
+        let mut response = [0u8; 6];
+        let read = timer::timeout(&AsyncIoTimer, timeout, Phase::Target, async {
+            Ok(stream.read_exact(&mut response).await)
+        });
+        match read.await? {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(response[0] == 0x16 && response[1] == 0x03 && response[5] == 0x02)
+    }
+}
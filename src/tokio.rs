@@ -0,0 +1,66 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Connecting through a SOCKS5 proxy with tokio's networking types.
+//! Enabled with the `tokio` feature.
+//!
+//! The functions here mirror the [`Socks5Client`] ones, performing the
+//! same negotiation, but take and return tokio streams so no other
+//! runtime is needed.
+
+use ::tokio::io::{AsyncRead, AsyncWrite};
+use ::tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use crate::{Socks5Client, Socks5Error, TargetAddr};
+
+/// Connect through the given SOCKS5 proxy to the given [`TargetAddr`].
+/// Optionally, provide credentials in the form of username and password.
+/// Returns a tokio [`TcpStream`] on success.
+pub async fn connect(
+    proxy_addr: &str,
+    target: &TargetAddr,
+    credentials: Option<(&str, &str)>,
+) -> Result<TcpStream, Socks5Error> {
+    let stream = TcpStream::connect(proxy_addr).await?;
+    handshake_over(stream, target, credentials).await
+}
+
+/// Connect through the given SOCKS5 proxy to the given host and port.
+/// DNS resolution will be done on the SOCKS5 server-side.
+/// Optionally, provide credentials in the form of username and password.
+pub async fn connect_with_domain(
+    proxy_addr: &str,
+    domain: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<TcpStream, Socks5Error> {
+    let target = TargetAddr::Domain(domain.to_string(), port);
+    connect(proxy_addr, &target, credentials).await
+}
+
+/// Perform the full SOCKS5 negotiation for `target` over an existing
+/// tokio stream to the proxy. See [`Socks5Client::handshake_over`].
+pub async fn handshake_over<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    target: &TargetAddr,
+    credentials: Option<(&str, &str)>,
+) -> Result<S, Socks5Error> {
+    Socks5Client::negotiate(&mut (&mut stream).compat(), target, &credentials).await?;
+    Ok(stream)
+}
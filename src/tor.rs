@@ -0,0 +1,240 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use async_net::TcpStream;
+
+use crate::{protocol, Socks5Client, Socks5Error, TargetAddr};
+
+/// Tor's RESOLVE command, see `socks-extensions.txt` in the Tor spec
+const CMD_RESOLVE: u8 = 0xf0;
+
+/// Tor's RESOLVE_PTR command
+const CMD_RESOLVE_PTR: u8 = 0xf1;
+
+/// Length of a v3 onion service address, without the `.onion` suffix
+const ONION_V3_LEN: usize = 56;
+
+/// Username Tor isolates circuits by, sent through the username and
+/// password authentication. Streams opened with the same token may share
+/// a circuit, streams opened with different tokens never do.
+///
+/// ```no_run
+/// use async_socks5::{IsolationToken, Socks5Client};
+///
+/// # smol::block_on(async {
+/// let token = IsolationToken::new();
+/// let config = Socks5Client::tor().isolation(&token);
+/// let stream = config.connect_with_domain("example.com", 80).await?;
+/// # Ok::<(), async_socks5::Socks5Error>(())
+/// # });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IsolationToken(String);
+
+impl IsolationToken {
+    /// Generate a token for a new session, distinct from every other
+    /// token of this process.
+    pub fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let mut hasher = RandomState::new().build_hasher();
+        NEXT.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+        SystemTime::now().hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+        Self(format!("isolation-{:016x}", hasher.finish()))
+    }
+
+    /// Token for the session named by `key`, the same for every call
+    /// with that key. Sessions sharing a key share circuits.
+    pub fn from_key(key: &str) -> Self {
+        Self(format!("isolation-{}", key))
+    }
+
+    /// Username and password to authenticate with for this token
+    pub fn credentials(&self) -> (&str, &str) {
+        (&self.0, &self.0)
+    }
+}
+
+impl Default for IsolationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extended REP codes Tor sends for onion service failures, see
+/// `socks-extensions.txt` in the Tor spec. Only enabled with
+/// [`crate::Socks5Config::tor_errors`], as other proxies may use the
+/// same codes for something else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TorError {
+    /// 0xf0: the onion service descriptor can not be found
+    DescriptorNotFound,
+    /// 0xf1: the onion service descriptor is invalid
+    DescriptorInvalid,
+    /// 0xf2: introduction to the onion service failed
+    IntroductionFailed,
+    /// 0xf3: the rendezvous with the onion service failed
+    RendezvousFailed,
+    /// 0xf4: the onion service requires client authorization
+    MissingClientAuth,
+    /// 0xf5: the client authorization was rejected
+    WrongClientAuth,
+    /// 0xf6: the onion address is invalid
+    InvalidAddress,
+    /// 0xf7: introduction to the onion service timed out
+    IntroductionTimedOut,
+}
+
+impl TorError {
+    /// Decode an extended REP code, if it is one.
+    pub fn from_reply(rep: u8) -> Option<Self> {
+        match rep {
+            0xf0 => Some(Self::DescriptorNotFound),
+            0xf1 => Some(Self::DescriptorInvalid),
+            0xf2 => Some(Self::IntroductionFailed),
+            0xf3 => Some(Self::RendezvousFailed),
+            0xf4 => Some(Self::MissingClientAuth),
+            0xf5 => Some(Self::WrongClientAuth),
+            0xf6 => Some(Self::InvalidAddress),
+            0xf7 => Some(Self::IntroductionTimedOut),
+            _ => None,
+        }
+    }
+}
+
+impl From<TorError> for u8 {
+    fn from(err: TorError) -> Self {
+        match err {
+            TorError::DescriptorNotFound => 0xf0,
+            TorError::DescriptorInvalid => 0xf1,
+            TorError::IntroductionFailed => 0xf2,
+            TorError::RendezvousFailed => 0xf3,
+            TorError::MissingClientAuth => 0xf4,
+            TorError::WrongClientAuth => 0xf5,
+            TorError::InvalidAddress => 0xf6,
+            TorError::IntroductionTimedOut => 0xf7,
+        }
+    }
+}
+
+impl fmt::Display for TorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DescriptorNotFound => write!(f, "onion service descriptor not found"),
+            Self::DescriptorInvalid => write!(f, "onion service descriptor is invalid"),
+            Self::IntroductionFailed => write!(f, "onion service introduction failed"),
+            Self::RendezvousFailed => write!(f, "onion service rendezvous failed"),
+            Self::MissingClientAuth => write!(f, "onion service requires client authorization"),
+            Self::WrongClientAuth => write!(f, "onion service client authorization rejected"),
+            Self::InvalidAddress => write!(f, "invalid onion service address"),
+            Self::IntroductionTimedOut => write!(f, "onion service introduction timed out"),
+        }
+    }
+}
+
+impl Socks5Client {
+    /// Resolve `domain` through the given Tor SOCKS proxy using Tor's
+    /// RESOLVE extension, without opening a stream or leaking the query
+    /// to the local resolver. The proxy may answer with an IPv4 or an
+    /// IPv6 address.
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn resolve(
+        proxy_addr: &str,
+        domain: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<IpAddr, Socks5Error> {
+        let target = TargetAddr::Domain(domain.to_string(), 0);
+        match Socks5Client::tor_request(proxy_addr, CMD_RESOLVE, &target, credentials).await? {
+            TargetAddr::Ip(addr) => Ok(addr.ip()),
+            TargetAddr::Domain(..) => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+
+    /// Reverse resolve `ip` through the given Tor SOCKS proxy using Tor's
+    /// RESOLVE_PTR extension, without leaking the query to the local
+    /// resolver. Returns the hostname the proxy found.
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn resolve_ptr(
+        proxy_addr: &str,
+        ip: IpAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<String, Socks5Error> {
+        let target = TargetAddr::Ip(SocketAddr::new(ip, 0));
+        match Socks5Client::tor_request(proxy_addr, CMD_RESOLVE_PTR, &target, credentials).await? {
+            TargetAddr::Domain(host, _) => Ok(host),
+            TargetAddr::Ip(..) => Err(Socks5Error::UnexpectedResponse),
+        }
+    }
+
+    /// Connect through the given Tor SOCKS proxy to `port` on the onion
+    /// service `onion`. The address is checked before anything is sent:
+    /// it must end in `.onion`, possibly with subdomains in front, and
+    /// name a v3 service, i.e. 56 base32 characters. Malformed addresses
+    /// fail with [`Socks5Error::InvalidInput`].
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_to_onion(
+        proxy_addr: &str,
+        onion: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream, Socks5Error> {
+        check_onion(onion)?;
+        Socks5Client::connect_with_domain(proxy_addr, onion, port, credentials).await
+    }
+
+    /// Internal method sending a Tor extension request and returning the
+    /// BND.ADDR of the reply, which carries the answer.
+    async fn tor_request(
+        proxy_addr: &str,
+        cmd: u8,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TargetAddr, Socks5Error> {
+        let request = protocol::encode_request(cmd, target)?;
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        Socks5Client::negotiate_request(&mut stream, &request, &credentials).await
+    }
+}
+
+/// Check that `onion` is a well-formed v3 onion service hostname.
+fn check_onion(onion: &str) -> Result<(), Socks5Error> {
+    let lower = onion.to_ascii_lowercase();
+    let service = match lower.strip_suffix(".onion") {
+        Some(name) => name.rsplit('.').next().unwrap_or(name),
+        None => return Err(Socks5Error::InvalidInput("not an .onion address")),
+    };
+
+    let is_base32 = |c: char| c.is_ascii_lowercase() || ('2'..='7').contains(&c);
+    if service.len() != ONION_V3_LEN || !service.chars().all(is_base32) {
+        return Err(Socks5Error::InvalidInput("not a v3 onion service address"));
+    }
+
+    if onion.len() > 255 || onion.split('.').any(str::is_empty) {
+        return Err(Socks5Error::InvalidInput("malformed onion address"));
+    }
+
+    Ok(())
+}
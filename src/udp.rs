@@ -0,0 +1,354 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_net::{TcpStream, UdpSocket};
+use futures_lite::io::AsyncWriteExt;
+
+use crate::protocol::{Request, UdpHeader};
+use crate::trace::debug;
+use crate::{Command, Socks5Client, Socks5Error, TargetAddr};
+
+/// Largest possible UDP payload
+pub(crate) const MAX_DATAGRAM: usize = 65535;
+
+/// How long an incomplete sequence of fragments is kept, the minimum
+/// RFC 1928 allows
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// FRAG bit marking the last fragment of a sequence
+const END_OF_SEQUENCE: u8 = 0x80;
+
+/// UDP socket relaying datagrams through a SOCKS5 proxy (UDP ASSOCIATE).
+///
+/// The association lasts as long as this socket lives, since the proxy
+/// tears it down once the TCP control connection is closed.
+#[derive(Debug)]
+pub struct Socks5UdpSocket {
+    socket: UdpSocket,
+    relay_addr: SocketAddr,
+    label: Option<String>,
+    fragment_size: Option<usize>,
+    reassembly: Mutex<Option<Reassembly>>,
+    _control: TcpStream,
+}
+
+/// Fragments of one datagram received so far
+#[derive(Debug)]
+struct Reassembly {
+    target: TargetAddr,
+    position: u8,
+    payload: Vec<u8>,
+    started: Instant,
+}
+
+impl Socks5Client {
+    /// Ask the given SOCKS5 proxy to relay UDP datagrams for us.
+    /// The local UDP socket is bound to `local_addr` if given (useful when
+    /// a firewall only allows fixed source ports), or to an ephemeral port
+    /// on the interface used to reach the proxy otherwise. Binding happens
+    /// before the association is requested, so an unusable address fails
+    /// without involving the proxy.
+    /// Optionally, provide credentials in the form of username and password.
+    /// Returns a [`Socks5UdpSocket`] sending and receiving through the
+    /// relay address reported by the proxy.
+    pub async fn udp_associate(
+        proxy_addr: &str,
+        local_addr: Option<SocketAddr>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5UdpSocket, Socks5Error> {
+        Socks5Client::udp_associate_from(proxy_addr, local_addr, None, credentials).await
+    }
+
+    /// Like [`Socks5Client::udp_associate`], but tell the proxy which
+    /// address we will send datagrams from. Per RFC 1928 this goes into the
+    /// request's DST.ADDR/DST.PORT, and some strict proxies only relay
+    /// datagrams coming from it. With `None`, all zeros are sent, meaning
+    /// the source is not known in advance.
+    pub async fn udp_associate_from(
+        proxy_addr: &str,
+        local_addr: Option<SocketAddr>,
+        source_addr: Option<SocketAddr>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Socks5UdpSocket, Socks5Error> {
+        let mut control = TcpStream::connect(proxy_addr).await?;
+        let socket = Socks5UdpSocket::bind(&control, local_addr).await?;
+        Socks5Client::handshake(&mut control, &credentials).await?;
+        Socks5UdpSocket::associate(control, socket, source_addr).await
+    }
+}
+
+impl Socks5UdpSocket {
+    /// Bind the local UDP socket for an association over `control`.
+    pub(crate) async fn bind(
+        control: &TcpStream,
+        local_addr: Option<SocketAddr>,
+    ) -> Result<UdpSocket, Socks5Error> {
+        let socket = match local_addr {
+            Some(addr) => UdpSocket::bind(addr).await?,
+            None => UdpSocket::bind((control.local_addr()?.ip(), 0)).await?,
+        };
+        Ok(socket)
+    }
+
+    /// Send the UDP ASSOCIATE request over an already negotiated
+    /// `control` connection and wrap `socket` around the relay.
+    pub(crate) async fn associate(
+        mut control: TcpStream,
+        socket: UdpSocket,
+        source_addr: Option<SocketAddr>,
+    ) -> Result<Socks5UdpSocket, Socks5Error> {
+        control
+            .write_all(&Self::associate_frame(source_addr)?)
+            .await?;
+        let relay = Socks5Client::read_reply(&mut control).await?;
+        Self::relay_through(control, socket, relay).await
+    }
+
+    /// Encode the UDP ASSOCIATE request announcing `source_addr`.
+    pub(crate) fn associate_frame(source_addr: Option<SocketAddr>) -> Result<Vec<u8>, Socks5Error> {
+        let source = source_addr.unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+        let request = Request {
+            command: Command::UdpAssociate,
+            target: TargetAddr::Ip(source),
+        };
+        request.encode()
+    }
+
+    /// Wrap `socket` around the `relay` the proxy reported in its reply
+    /// to the UDP ASSOCIATE request sent over `control`.
+    pub(crate) async fn relay_through(
+        control: TcpStream,
+        socket: UdpSocket,
+        relay: TargetAddr,
+    ) -> Result<Socks5UdpSocket, Socks5Error> {
+        let mut relay_addr = match relay {
+            TargetAddr::Ip(addr) => addr,
+            TargetAddr::Domain(..) => return Err(Socks5Error::UnsupportedAddressType),
+        };
+
+        // Proxies commonly report an unspecified relay address, meaning
+        // the address we reached the proxy at.
+        if relay_addr.ip().is_unspecified() {
+            relay_addr.set_ip(control.peer_addr()?.ip());
+        }
+
+        // Let the kernel discard datagrams that don't come from the relay
+        socket.connect(relay_addr).await?;
+
+        Ok(Socks5UdpSocket {
+            socket,
+            relay_addr,
+            label: None,
+            fragment_size: None,
+            reassembly: Mutex::new(None),
+            _control: control,
+        })
+    }
+
+    /// Attach a label used in diagnostics for this association.
+    pub(crate) fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Split payloads longer than `max_payload` bytes into a sequence of
+    /// fragments when sending, and reassemble fragmented datagrams from
+    /// the relay. Off by default, as many relays support neither and
+    /// drop fragments; without it, received fragments are dropped too.
+    ///
+    /// A sequence is discarded when a fragment arrives out of order or
+    /// from another address, or when it isn't complete within 5 seconds.
+    pub fn with_fragmentation(mut self, max_payload: usize) -> Self {
+        self.fragment_size = Some(max_payload.max(1));
+        self
+    }
+
+    /// Label of the [`Socks5Config`](crate::Socks5Config) this association
+    /// was created from, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Address of the proxy's UDP relay
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+
+    /// Local address of the underlying UDP socket
+    pub fn local_addr(&self) -> Result<SocketAddr, Socks5Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Build the SOCKS5 UDP request header (RSV, FRAG, ATYP, DST.ADDR,
+    /// DST.PORT) for a datagram sent to `target`. Domain targets are
+    /// encoded with ATYP 0x03 and resolved by the proxy.
+    pub fn encode_header(target: &TargetAddr) -> Result<Vec<u8>, Socks5Error> {
+        let header = UdpHeader {
+            frag: 0,
+            target: target.clone(),
+        };
+        header.encode()
+    }
+
+    /// Parse the SOCKS5 UDP request header at the start of `datagram`.
+    /// Returns the address it carries and the header length, so the
+    /// payload is `&datagram[len..]`.
+    pub fn decode_header(datagram: &[u8]) -> Result<(TargetAddr, usize), Socks5Error> {
+        let (header, len) = UdpHeader::decode(datagram)?;
+
+        // Fragments are only reassembled by `recv_from`
+        if header.frag != 0 {
+            return Err(Socks5Error::UnexpectedResponse);
+        }
+
+        Ok((header.target, len))
+    }
+
+    /// Send `buf` to `target` through the relay.
+    /// Returns the number of payload bytes sent.
+    /// Payloads longer than the size given to
+    /// [`Socks5UdpSocket::with_fragmentation`] are sent as fragments.
+    pub async fn send_to(&self, buf: &[u8], target: &TargetAddr) -> Result<usize, Socks5Error> {
+        let max_payload = match self.fragment_size {
+            Some(max_payload) if buf.len() > max_payload => max_payload,
+            _ => {
+                let mut datagram = Socks5UdpSocket::encode_header(target)?;
+                datagram.extend_from_slice(buf);
+                self.socket.send(&datagram).await?;
+                return Ok(buf.len());
+            }
+        };
+
+        let count = buf.len().div_ceil(max_payload);
+        if count > 127 {
+            return Err(Socks5Error::InvalidInput(
+                "datagram needs more than 127 fragments",
+            ));
+        }
+
+        for (i, chunk) in buf.chunks(max_payload).enumerate() {
+            let mut frag = i as u8 + 1;
+            if i + 1 == count {
+                frag |= END_OF_SEQUENCE;
+            }
+            let header = UdpHeader {
+                frag,
+                target: target.clone(),
+            };
+            let mut datagram = header.encode()?;
+            datagram.extend_from_slice(chunk);
+            self.socket.send(&datagram).await?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Receive a datagram from the relay into `buf`.
+    /// Returns the number of payload bytes read and the address it was
+    /// sent from. The socket is connected to the relay, so only its
+    /// datagrams arrive; those carrying a malformed header are dropped.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, TargetAddr), Socks5Error> {
+        let mut datagram = vec![0u8; MAX_DATAGRAM];
+
+        loop {
+            let n = self.socket.recv(&mut datagram).await?;
+            let (header, header_len) = match UdpHeader::decode(&datagram[..n]) {
+                Ok(header) => header,
+                Err(e) => {
+                    debug!("[{}] dropping malformed datagram: {}", self.log_label(), e);
+                    continue;
+                }
+            };
+
+            let payload = &datagram[header_len..n];
+            let (addr, payload) = match header.frag {
+                0 => (header.target, payload),
+                _ if self.fragment_size.is_none() => {
+                    debug!("[{}] dropping fragment", self.log_label());
+                    continue;
+                }
+                frag => match self.reassemble(frag, header.target, payload) {
+                    Some((addr, whole)) => {
+                        datagram = whole;
+                        (addr, &datagram[..])
+                    }
+                    None => continue,
+                },
+            };
+
+            let len = payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&payload[..len]);
+            return Ok((len, addr));
+        }
+    }
+
+    /// Add a fragment to the reassembly queue, returning the datagram
+    /// once `frag` completes it.
+    fn reassemble(
+        &self,
+        frag: u8,
+        target: TargetAddr,
+        payload: &[u8],
+    ) -> Option<(TargetAddr, Vec<u8>)> {
+        let position = frag & !END_OF_SEQUENCE;
+        let mut queue = self
+            .reassembly
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(pending) = queue.as_ref() {
+            if pending.started.elapsed() > REASSEMBLY_TIMEOUT
+                || position != pending.position + 1
+                || pending.target != target
+                || pending.payload.len() + payload.len() > MAX_DATAGRAM
+            {
+                debug!("[{}] dropping incomplete datagram", self.log_label());
+                *queue = None;
+            }
+        }
+
+        match queue.as_mut() {
+            Some(pending) => {
+                pending.position = position;
+                pending.payload.extend_from_slice(payload);
+            }
+            None if position == 1 => {
+                *queue = Some(Reassembly {
+                    target,
+                    position,
+                    payload: payload.to_vec(),
+                    started: Instant::now(),
+                })
+            }
+            // The start of the sequence was lost
+            None => return None,
+        }
+
+        if frag & END_OF_SEQUENCE == 0 {
+            return None;
+        }
+        queue.take().map(|done| (done.target, done.payload))
+    }
+
+    fn log_label(&self) -> &str {
+        self.label.as_deref().unwrap_or("-")
+    }
+}
@@ -0,0 +1,129 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_lite::Stream;
+use futures_sink::Sink;
+
+use crate::udp::MAX_DATAGRAM;
+use crate::{Socks5Error, Socks5UdpSocket, TargetAddr};
+
+type Pending<T> = Pin<Box<dyn Future<Output = Result<T, Socks5Error>> + Send>>;
+
+/// [`Socks5UdpSocket`] as a [`Stream`] of received datagrams and a
+/// [`Sink`] of datagrams to send, each paired with the remote address.
+///
+/// The stream never ends; errors are yielded as items and the next
+/// poll receives again. Created with [`Socks5UdpSocket::framed`].
+pub struct Socks5UdpFramed {
+    socket: Arc<Socks5UdpSocket>,
+    recv: Option<Pending<(Bytes, TargetAddr)>>,
+    send: Option<Pending<usize>>,
+}
+
+impl Socks5UdpSocket {
+    /// Wrap this socket in a [`Stream`] and [`Sink`] of
+    /// `(Bytes, TargetAddr)` datagrams.
+    pub fn framed(self) -> Socks5UdpFramed {
+        Socks5UdpFramed {
+            socket: Arc::new(self),
+            recv: None,
+            send: None,
+        }
+    }
+}
+
+impl Socks5UdpFramed {
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &Socks5UdpSocket {
+        &self.socket
+    }
+}
+
+impl fmt::Debug for Socks5UdpFramed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5UdpFramed")
+            .field("socket", &self.socket)
+            .field("receiving", &self.recv.is_some())
+            .field("sending", &self.send.is_some())
+            .finish()
+    }
+}
+
+impl Stream for Socks5UdpFramed {
+    type Item = Result<(Bytes, TargetAddr), Socks5Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let socket = self.socket.clone();
+        let recv = self.recv.get_or_insert_with(|| {
+            Box::pin(async move {
+                let mut buf = vec![0u8; MAX_DATAGRAM];
+                let (n, from) = socket.recv_from(&mut buf).await?;
+                buf.truncate(n);
+                Ok((Bytes::from(buf), from))
+            })
+        });
+
+        let received = futures_lite::ready!(recv.as_mut().poll(cx));
+        self.recv = None;
+        Poll::Ready(Some(received))
+    }
+}
+
+impl Sink<(Bytes, TargetAddr)> for Socks5UdpFramed {
+    type Error = Socks5Error;
+
+    /// Wait for the previous datagram to be sent.
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Socks5Error>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        (payload, target): (Bytes, TargetAddr),
+    ) -> Result<(), Socks5Error> {
+        // Fail early rather than on the next poll
+        Socks5UdpSocket::encode_header(&target)?;
+
+        let socket = self.socket.clone();
+        self.send = Some(Box::pin(
+            async move { socket.send_to(&payload, &target).await },
+        ));
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Socks5Error>> {
+        let sent = match self.send.as_mut() {
+            Some(send) => futures_lite::ready!(send.as_mut().poll(cx)),
+            None => return Poll::Ready(Ok(())),
+        };
+        self.send = None;
+        Poll::Ready(sent.map(|_| ()))
+    }
+
+    /// Flush; the association itself ends when this is dropped.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Socks5Error>> {
+        self.poll_flush(cx)
+    }
+}
@@ -0,0 +1,40 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::Path;
+
+use async_net::unix::UnixStream;
+
+use crate::trace::debug;
+use crate::{Socks5Client, Socks5Error, TargetAddr};
+
+impl Socks5Client {
+    /// Connect through a SOCKS5 proxy listening on the unix socket at
+    /// `path`, such as Tor's `SocksPort unix:/run/tor/socks`, to the
+    /// given target.
+    /// Optionally, provide credentials in the form of username and password.
+    pub async fn connect_unix(
+        path: impl AsRef<Path>,
+        target: &TargetAddr,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<UnixStream, Socks5Error> {
+        debug!("connecting to proxy at {}", path.as_ref().display());
+        let stream = UnixStream::connect(path).await?;
+        Socks5Client::handshake_over(stream, target, credentials).await
+    }
+}
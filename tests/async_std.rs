@@ -0,0 +1,48 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "async-std")]
+
+mod common;
+
+use async_std::io::ReadExt;
+use smol::io::AsyncWriteExt;
+
+#[test]
+fn async_std_connect_with_domain() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            stream.write_all(b"hello").await.unwrap();
+            request
+        })
+        .await;
+
+        let mut stream =
+            async_socks5::async_std::connect_with_domain(&proxy, "example.com", 80, None)
+                .await
+                .unwrap();
+
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(&server.await[5..16], b"example.com");
+    });
+}
@@ -0,0 +1,503 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_socks5::{
+    AuthFuture, AuthMethod, AuthStream, Credentials, Phase, Socks5Client, Socks5Config,
+    Socks5Error, TargetAddr,
+};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn credentials_not_sent_when_server_selects_no_auth() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let methods = common::read_greeting(&mut stream).await;
+            assert!(methods.contains(&0x02));
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+            // The very next frame must be the CONNECT request, not an
+            // RFC 1929 username/password frame (which starts with 0x01).
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            let mut trailing = vec![];
+            stream.read_to_end(&mut trailing).await.unwrap();
+            (request, trailing)
+        })
+        .await;
+
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let stream = Socks5Client::connect(&proxy, &target, Some(("user", "secret")))
+            .await
+            .unwrap();
+        drop(stream);
+
+        let (request, trailing) = server.await;
+        assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0x00, 0x50]);
+        assert!(trailing.is_empty());
+    });
+}
+
+#[test]
+fn no_acceptable_methods_without_credentials() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00]);
+            stream.write_all(&[0x05, 0xff]).await.unwrap();
+        })
+        .await;
+
+        let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::CredentialsRequired));
+    });
+}
+
+#[test]
+fn no_acceptable_methods_with_credentials() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0xff]).await.unwrap();
+        })
+        .await;
+
+        let creds = Some(("user", "secret"));
+        let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, creds)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::NoAcceptableAuthMethods));
+    });
+}
+
+#[test]
+fn user_pass_selected_without_credentials() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let err = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::CredentialsRequired));
+    });
+}
+
+#[test]
+fn circuit_opens_after_auth_failures() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 3];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x01]).await.unwrap();
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .credentials("user", "bad")
+            .circuit_breaker(1, Duration::from_secs(3600));
+
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert!(matches!(err, Err(Socks5Error::AuthenticationFailed)));
+        server.await;
+
+        // The proxy is gone now, so this only succeeds in failing fast
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert!(matches!(err, Err(Socks5Error::CircuitOpen)));
+    });
+}
+
+#[test]
+fn auth_retried_with_fresh_credentials() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+            let mut passwords = vec![];
+            for status in [0x01, 0x00] {
+                let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 5];
+                stream.read_exact(&mut auth).await.unwrap();
+                passwords.push(auth[7..].to_vec());
+                stream.write_all(&[0x01, status]).await.unwrap();
+            }
+
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            passwords
+        })
+        .await;
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let config = Socks5Config::new(&proxy)
+            .credentials_fn(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                ("user".into(), format!("pass{}", n))
+            })
+            .max_auth_attempts(2);
+
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(server.await, [b"pass0".to_vec(), b"pass1".to_vec()]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    });
+}
+
+#[test]
+fn auth_retry_errors_after_a_rejection() {
+    smol::block_on(async {
+        // Closing after the first rejection is an authentication failure,
+        // stalling is a timeout like any other
+        for close in [true, false] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+                let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 4];
+                stream.read_exact(&mut auth).await.unwrap();
+                stream.write_all(&[0x01, 0x01]).await.unwrap();
+                if close {
+                    return;
+                }
+                smol::future::pending::<()>().await;
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .credentials_fn(|| ("user".into(), "pass".into()))
+                .max_auth_attempts(2)
+                .read_timeout(Duration::from_millis(100));
+
+            let err = config
+                .connect_with_domain("example.com", 80)
+                .await
+                .unwrap_err();
+            match close {
+                true => assert_eq!(err, Socks5Error::AuthenticationFailed),
+                false => assert_eq!(err, Socks5Error::Timeout(Phase::Authentication)),
+            }
+        }
+    });
+}
+
+#[test]
+fn selected_method_must_be_offered() {
+    smol::block_on(async {
+        for (offered, selected) in [(vec![0x02], 0x00), (vec![0x00, 0x02], 0x01)] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                assert_eq!(common::read_greeting(&mut stream).await, offered);
+                stream.write_all(&[0x05, selected]).await.unwrap();
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .credentials("user", "secret")
+                .require_auth(selected == 0x00);
+
+            let err = config.connect_with_domain("example.com", 80).await;
+            assert!(matches!(err, Err(Socks5Error::UnexpectedResponse)));
+        }
+    });
+}
+
+#[test]
+fn version_bytes_checked() {
+    smol::block_on(async {
+        // A bogus method selection version, then a bogus auth version
+        for (selection, auth) in [([0x04, 0x00], None), ([0x05, 0x02], Some([0x05, 0x00]))] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&selection).await.unwrap();
+                if let Some(auth) = auth {
+                    let mut request = vec![0u8; 1 + 1 + 4 + 1 + 6];
+                    stream.read_exact(&mut request).await.unwrap();
+                    stream.write_all(&auth).await.unwrap();
+                }
+            })
+            .await;
+
+            let creds = Some(("user", "secret"));
+            let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, creds)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Socks5Error::UnexpectedResponse));
+        }
+    });
+}
+
+#[test]
+fn custom_offered_methods() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let methods = common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            methods
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).offered_methods(vec![0x80, 0x02, 0x00]);
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(server.await, [0x80, 0x02, 0x00]);
+    });
+}
+
+#[test]
+fn custom_offered_methods_validation() {
+    let target = TargetAddr::Domain("example.com".into(), 80);
+    let invalid = [
+        vec![],
+        vec![0x00; 256],
+        vec![0x00, 0x01, 0x00],
+        vec![0x01, 0x02],
+    ];
+
+    for methods in invalid {
+        let config = Socks5Config::new("127.0.0.1:9").offered_methods(methods);
+        assert!(matches!(
+            config.dry_run(&target),
+            Err(Socks5Error::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn invalid_fields_rejected_before_sending() {
+    smol::block_on(async {
+        let long = "x".repeat(300);
+
+        let err = Socks5Client::connect_with_domain("127.0.0.1:9", &long, 80, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+
+        let invalid = [
+            (long.as_str(), "pass"),
+            ("user", long.as_str()),
+            ("", "pass"),
+            ("user", ""),
+            ("", ""),
+        ];
+        for creds in invalid {
+            let (proxy, server) = common::serve_once(|mut stream| async move {
+                let mut received = vec![];
+                stream.read_to_end(&mut received).await.unwrap();
+                received
+            })
+            .await;
+
+            let target = TargetAddr::Domain("example.com".into(), 80);
+            let err = Socks5Client::connect_stream(&proxy, &target, Some(creds))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Socks5Error::InvalidInput(_)));
+            assert!(server.await.is_empty());
+        }
+    });
+}
+
+#[test]
+fn byte_credentials_checked_and_sent() {
+    let long = vec![b'x'; 256];
+    assert!(Credentials::new("", "pass").is_err());
+    assert!(Credentials::from_bytes(&b"user"[..], long).is_err());
+
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 3];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            auth
+        })
+        .await;
+
+        let credentials = Credentials::from_bytes(&b"user"[..], &[0xff, 0x00, 0xfe][..]).unwrap();
+        let config = Socks5Config::new(&proxy).with_credentials(credentials);
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(server.await, b"\x01\x04user\x03\xff\x00\xfe");
+    });
+}
+
+#[test]
+fn provider_queried_only_when_needed() {
+    smol::block_on(async {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let config_for = |proxy: &str| {
+            let counter = counter.clone();
+            Socks5Config::new(proxy).credentials_provider(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                async move { Credentials::new("user", format!("tok{}", n)) }
+            })
+        };
+
+        // No authentication selected, nothing fetched
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+        let config = config_for(&proxy);
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 4];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            auth
+        })
+        .await;
+        let config = config_for(&proxy);
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(server.await, b"\x01\x04user\x04tok0");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    });
+}
+
+/// Vendor method sending a fixed token and expecting a zero status byte
+#[derive(Debug)]
+struct Token(&'static [u8]);
+
+impl AuthMethod for Token {
+    fn method_byte(&self) -> u8 {
+        0x80
+    }
+
+    fn authenticate<'a>(&'a self, stream: &'a mut dyn AuthStream) -> AuthFuture<'a> {
+        Box::pin(async move {
+            stream.write_all(self.0).await?;
+            let mut status = [0u8; 1];
+            stream.read_exact(&mut status).await?;
+            match status[0] {
+                0x00 => Ok(()),
+                _ => Err(Socks5Error::AuthenticationFailed),
+            }
+        })
+    }
+}
+
+#[test]
+fn custom_auth_method_dispatched() {
+    smol::block_on(async {
+        for status in [0x00, 0x01] {
+            let (proxy, server) = common::serve_once(move |mut stream| async move {
+                let methods = common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x80]).await.unwrap();
+                let mut token = [0u8; 5];
+                stream.read_exact(&mut token).await.unwrap();
+                stream.write_all(&[status]).await.unwrap();
+                if status == 0x00 {
+                    common::read_request(&mut stream).await;
+                    common::reply_ok(&mut stream).await;
+                }
+                (methods, token)
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .require_auth(true)
+                .auth_method(Token(b"token"));
+            let result = config.connect_with_domain("example.com", 80).await;
+
+            let (methods, token) = server.await;
+            assert_eq!(methods, [0x80]);
+            assert_eq!(&token, b"token");
+            match status {
+                0x00 => assert!(result.is_ok()),
+                _ => assert!(matches!(result, Err(Socks5Error::AuthenticationFailed))),
+            }
+        }
+    });
+}
+
+#[test]
+fn no_acceptable_methods_with_other_methods_offered() {
+    smol::block_on(async {
+        for offered in [vec![0x80], vec![0x00, 0x80]] {
+            let (proxy, _server) = common::serve_once(|mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0xff]).await.unwrap();
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .require_auth(offered == [0x80])
+                .auth_method(Token(b"token"));
+            let err = config.connect_with_domain("example.com", 80).await;
+            assert!(matches!(err, Err(Socks5Error::NoAcceptableAuthMethods)));
+        }
+    });
+}
+
+#[test]
+fn probe_validates_credentials_without_a_request() {
+    smol::block_on(async {
+        for status in [0x00, 0x01] {
+            let (proxy, server) = common::serve_once(move |mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+                let mut auth = [0u8; 1 + 1 + 4 + 1 + 6];
+                stream.read_exact(&mut auth).await.unwrap();
+                stream.write_all(&[0x01, status]).await.unwrap();
+
+                // The client hangs up instead of sending a request
+                let mut rest = vec![];
+                stream.read_to_end(&mut rest).await.unwrap();
+                rest
+            })
+            .await;
+
+            let result = Socks5Client::probe(&proxy, Some(("user", "secret"))).await;
+            match status {
+                0x00 => {
+                    let report = result.unwrap();
+                    assert_eq!(report.auth_method, 0x02);
+                    assert_eq!(report.proxy_addr.to_string(), proxy);
+                }
+                _ => assert_eq!(result.unwrap_err(), Socks5Error::AuthenticationFailed),
+            }
+            assert_eq!(server.await, b"");
+        }
+    });
+}
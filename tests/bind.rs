@@ -0,0 +1,78 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use async_socks5::{ReplyCode, Socks5Client, Socks5Error, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn bind_reports_both_replies() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+
+            // Listening on all interfaces, port 2121
+            let first = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0x08, 0x49];
+            stream.write_all(&first).await.unwrap();
+
+            let mut second = vec![0x05, 0x00, 0x00, 0x01, 192, 0, 2, 7, 0x00, 0x14];
+            second.extend_from_slice(b"hello");
+            stream.write_all(&second).await.unwrap();
+            request
+        })
+        .await;
+
+        let peer = TargetAddr::Ip("192.0.2.7:20".parse().unwrap());
+        let listener = Socks5Client::bind(&proxy, &peer, None).await.unwrap();
+        assert_eq!(
+            *listener.bind_addr(),
+            TargetAddr::Ip("127.0.0.1:2121".parse().unwrap())
+        );
+
+        let (mut stream, from) = listener.accept().await.unwrap();
+        assert_eq!(from, peer);
+
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"hello");
+
+        let request = server.await;
+        assert_eq!(request[..4], [0x05, 0x02, 0x00, 0x01]);
+    });
+}
+
+#[test]
+fn bind_second_reply_failure() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            let failed = [0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+            stream.write_all(&failed).await.unwrap();
+        })
+        .await;
+
+        let peer = TargetAddr::Ip("192.0.2.7:20".parse().unwrap());
+        let listener = Socks5Client::bind(&proxy, &peer, None).await.unwrap();
+        let err = listener.accept().await.unwrap_err();
+        assert!(matches!(err, Socks5Error::Reply(ReplyCode::GeneralFailure)));
+    });
+}
@@ -0,0 +1,79 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+
+use async_socks5::{Socks5Client, Socks5Error};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn connect_through_two_hops() {
+    smol::block_on(async {
+        // The first hop forwards the stream, so the second hop's
+        // negotiation arrives on the very same connection.
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00]);
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            let first = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 6];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+            let second = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            (first, auth, second)
+        })
+        .await;
+
+        let chain = [
+            (proxy.as_str(), None),
+            ("hop.example:1080", Some(("user", "secret"))),
+        ];
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        Socks5Client::connect_chain(&chain, &target).await.unwrap();
+
+        let (first, auth, second) = server.await;
+        assert_eq!(&first[..5], [0x05, 0x01, 0x00, 0x03, 11]);
+        assert_eq!(&first[5..16], b"hop.example");
+        assert_eq!(&first[16..], [0x04, 0x38]);
+        assert_eq!(&auth[2..6], b"user");
+        assert_eq!(second, [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0x00, 0x50]);
+    });
+}
+
+#[test]
+fn invalid_chains_rejected() {
+    smol::block_on(async {
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+
+        let err = Socks5Client::connect_chain(&[], &target).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+
+        let chain = [("127.0.0.1:9", None), ("no-port", None)];
+        let err = Socks5Client::connect_chain(&chain, &target)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    });
+}
@@ -0,0 +1,92 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Scripted in-process SOCKS5 proxy used by the integration tests.
+
+#![allow(dead_code)]
+
+use std::future::Future;
+
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, TcpStream};
+use smol::Task;
+
+/// Bind a listener on localhost and run `handler` on the first accepted
+/// connection. Returns the proxy address and the handler's task.
+pub async fn serve_once<F, Fut, T>(handler: F) -> (String, Task<T>)
+where
+    F: FnOnce(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let task = smol::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        handler(stream).await
+    });
+
+    (addr, task)
+}
+
+/// Read a client greeting and return the offered methods.
+pub async fn read_greeting(stream: &mut TcpStream) -> Vec<u8> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    assert_eq!(header[0], 0x05);
+
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await.unwrap();
+    methods
+}
+
+/// Read a full SOCKS5 request (header, address and port) and return its bytes.
+pub async fn read_request(stream: &mut TcpStream) -> Vec<u8> {
+    let mut request = vec![0u8; 4];
+    stream.read_exact(&mut request).await.unwrap();
+
+    let addr_len = match request[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await.unwrap();
+            request.push(len[0]);
+            len[0] as usize
+        }
+        atyp => panic!("unexpected ATYP {:#04x}", atyp),
+    };
+
+    let mut rest = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut rest).await.unwrap();
+    request.extend_from_slice(&rest);
+    request
+}
+
+/// Send a successful reply with an IPv4 bound address.
+pub async fn reply_ok(stream: &mut TcpStream) {
+    let reply = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90];
+    stream.write_all(&reply).await.unwrap();
+}
+
+/// Accept a no-auth greeting.
+pub async fn accept_no_auth(stream: &mut TcpStream) {
+    read_greeting(stream).await;
+    stream.write_all(&[0x05, 0x00]).await.unwrap();
+}
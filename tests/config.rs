@@ -0,0 +1,541 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_socks5::{
+    Credentials, IpVersion, Phase, ReplyCode, Resolution, Socks5Config, Socks5Error, TargetAddr,
+};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use socket2::SockRef;
+
+#[test]
+fn proxy_ip_version_filters_candidates() {
+    smol::block_on(async {
+        let config = Socks5Config::new("127.0.0.1:9050").proxy_ip_version(IpVersion::V6);
+        let err = config
+            .connect_with_domain("example.com", 80)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::NoMatchingProxyAddress));
+    });
+}
+
+#[test]
+fn dry_run_validates_without_connecting() {
+    let target = TargetAddr::Domain("example.com".into(), 80);
+    assert!(Socks5Config::new("localhost:9050").dry_run(&target).is_ok());
+    assert!(Socks5Config::new("[::1]:9050").dry_run(&target).is_ok());
+
+    let long = "x".repeat(256);
+    let invalid = [
+        (Socks5Config::new("localhost"), target.clone()),
+        (Socks5Config::new("localhost:socks"), target.clone()),
+        (Socks5Config::new("::1:9050"), target.clone()),
+        (
+            Socks5Config::new("localhost:9050").credentials(&long, "pass"),
+            target.clone(),
+        ),
+        (
+            Socks5Config::new("localhost:9050").require_auth(true),
+            target.clone(),
+        ),
+        (
+            Socks5Config::new("localhost:9050"),
+            TargetAddr::Domain(long.clone(), 80),
+        ),
+        (
+            Socks5Config::new("localhost:9050").strict_hostnames(true),
+            TargetAddr::Domain("exa\nmple.com".into(), 80),
+        ),
+    ];
+
+    for (config, target) in invalid {
+        assert!(matches!(
+            config.dry_run(&target),
+            Err(Socks5Error::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn ipv6_loopback_proxy() {
+    smol::block_on(async {
+        let listener = smol::net::TcpListener::bind("[::1]:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = smol::spawn(async move {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x00]).await.unwrap();
+                common::read_request(&mut stream).await;
+                common::reply_ok(&mut stream).await;
+            }
+        });
+
+        let target = TargetAddr::Ip("10.0.0.1:80".parse().unwrap());
+        Socks5Config::new(proxy).connect(&target).await.unwrap();
+        Socks5Config::new(format!("[::1]:{}", proxy.port()))
+            .connect(&target)
+            .await
+            .unwrap();
+        server.await;
+    });
+}
+
+#[test]
+fn retries_transient_failures_only() {
+    smol::block_on(async {
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = smol::spawn(async move {
+            // Host unreachable, TTL expired and general failure are
+            // retried, not allowed isn't
+            for rep in [0x04, 0x06, 0x00, 0x01, 0x00, 0x02] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x00]).await.unwrap();
+                common::read_request(&mut stream).await;
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+            }
+        });
+
+        let target = TargetAddr::Ip("10.0.0.1:80".parse().unwrap());
+        let config = Socks5Config::new(proxy)
+            .retry(3, Duration::from_millis(1))
+            .retry_jitter(true);
+        config.connect(&target).await.unwrap();
+        config.connect(&target).await.unwrap();
+
+        let err = config.connect(&target).await.unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::NotAllowed));
+        server.await;
+    });
+}
+
+#[test]
+fn socket_options_applied_to_returned_stream() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .nodelay(true)
+            .keepalive(Duration::from_secs(60))
+            .keepalive_interval(Duration::from_secs(5))
+            .ttl(42)
+            .strict_socket_options(true);
+        let stream = config.connect_with_domain("example.com", 80).await.unwrap();
+
+        assert!(stream.nodelay().unwrap());
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(60)
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            socket.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(stream.ttl().unwrap(), 42);
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn bind_device_applies_before_connect() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).bind_device("lo");
+        let stream = config.connect_with_domain("example.com", 80).await.unwrap();
+        let device = SockRef::from(&stream).device().unwrap();
+        assert_eq!(device.as_deref(), Some(&b"lo"[..]));
+
+        let config = Socks5Config::new(&proxy).bind_device("no-such-if0");
+        let err = config
+            .connect_with_domain("example.com", 80)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::IoError(_)), "{:?}", err);
+    });
+}
+
+#[test]
+fn local_addr_binds_proxy_connection() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let peer = stream.peer_addr().unwrap();
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            peer
+        })
+        .await;
+
+        // Find a free port to bind to
+        let local = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let config = Socks5Config::new(&proxy).local_addr(local);
+        let stream = config.connect_with_domain("example.com", 80).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap(), local);
+        assert_eq!(server.await, local);
+
+        // Binding to an address that isn't ours fails outright
+        let config = Socks5Config::new(&proxy).local_addr("192.0.2.1:0".parse().unwrap());
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert!(matches!(err, Err(Socks5Error::IoError(_))));
+    });
+}
+
+#[test]
+fn connect_any_takes_the_first_answer() {
+    smol::block_on(async {
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = smol::spawn(async move {
+            let mut silent = vec![];
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                common::accept_no_auth(&mut stream).await;
+                let request = common::read_request(&mut stream).await;
+                match request[7] {
+                    // Never answers, so the next attempt starts
+                    1 => silent.push(stream),
+                    2 => {
+                        let reply = [0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                        stream.write_all(&reply).await.unwrap();
+                    }
+                    _ => {
+                        common::reply_ok(&mut stream).await;
+                        stream.write_all(&request[7..8]).await.unwrap();
+                        return;
+                    }
+                }
+            }
+        });
+
+        let targets: Vec<SocketAddr> = ["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let config = Socks5Config::new(proxy).happy_eyeballs(Duration::from_millis(20));
+        let mut stream = config.connect_any(&targets).await.unwrap();
+
+        let mut last_octet = [0u8; 1];
+        stream.read_exact(&mut last_octet).await.unwrap();
+        assert_eq!(last_octet, [3]);
+        server.await;
+
+        let err = config.connect_any(&[]).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    });
+}
+
+#[test]
+fn resolution_policies() {
+    smol::block_on(async {
+        let config = Socks5Config::new("127.0.0.1:9").resolution(Resolution::Remote);
+        let err = config
+            .connect_resolved("example.com", 80)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::LocalResolutionDisabled);
+
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = smol::spawn(async move {
+            let mut atyps = vec![];
+            for rep in [0x08, 0x00] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                common::accept_no_auth(&mut stream).await;
+                atyps.push(common::read_request(&mut stream).await[3]);
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+            }
+            atyps
+        });
+
+        let config = Socks5Config::new(proxy).resolution(Resolution::RemotePreferred);
+        let target = TargetAddr::Domain("localhost".into(), 80);
+        config.connect(&target).await.unwrap();
+        assert_eq!(server.await, [0x03, 0x01]);
+    });
+}
+
+#[test]
+fn pipelined_greeting_and_request() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            // Nothing is answered until the request arrived too
+            let greeting = common::read_greeting(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            common::reply_ok(&mut stream).await;
+            (greeting, request)
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .pipelining(true)
+            .timeout(Duration::from_secs(5));
+        config.connect_with_domain("example.com", 80).await.unwrap();
+
+        let (greeting, request) = server.await;
+        assert_eq!(greeting, [0x00]);
+        assert_eq!(request[3], 0x03);
+
+        // Credentials are always sent, even to a proxy that would let us
+        // in anonymously
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let greeting = common::read_greeting(&mut stream).await;
+            let method = match greeting.contains(&0x02) {
+                true => 0x02,
+                false => 0x00,
+            };
+            stream.write_all(&[0x05, method]).await.unwrap();
+            let mut auth = [0u8; 1 + 1 + 4 + 1 + 4];
+            if method == 0x02 {
+                stream.read_exact(&mut auth).await.unwrap();
+                stream.write_all(&[0x01, 0x00]).await.unwrap();
+            }
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            (greeting, auth, request)
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .pipelining(true)
+            .credentials("user", "pass");
+        config.connect_with_domain("example.com", 80).await.unwrap();
+
+        let (greeting, auth, request) = server.await;
+        assert_eq!(greeting, [0x00, 0x02]);
+        assert_eq!(auth, *b"\x01\x04user\x04pass");
+        assert_eq!(request[3], 0x03);
+
+        // A proxy wanting authentication fails the connection
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0xff]).await.unwrap();
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).pipelining(true);
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert_eq!(err.unwrap_err(), Socks5Error::CredentialsRequired);
+    });
+}
+
+#[test]
+fn connect_many_limits_concurrency() {
+    smol::block_on(async {
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let (active, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+        let counters = (active.clone(), peak.clone());
+        let _server = smol::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (active, peak) = counters.clone();
+                smol::spawn(async move {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+
+                    common::accept_no_auth(&mut stream).await;
+                    let request = common::read_request(&mut stream).await;
+                    smol::Timer::after(Duration::from_millis(20)).await;
+
+                    // Refuse targets on odd ports
+                    let rep = request[request.len() - 1] % 2 * 0x05;
+                    let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    stream.write_all(&reply).await.unwrap();
+                })
+                .detach();
+            }
+        });
+
+        let targets: Vec<TargetAddr> = (0..6)
+            .map(|port| TargetAddr::Ip(SocketAddr::from(([10, 0, 0, 1], port))))
+            .collect();
+        let results = Socks5Config::new(&proxy).connect_many(&targets, 2).await;
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        for (port, result) in results.iter().enumerate() {
+            match port % 2 {
+                0 => assert!(result.is_ok(), "{:?}", result),
+                _ => assert_eq!(
+                    *result.as_ref().unwrap_err(),
+                    Socks5Error::Reply(ReplyCode::ConnectionRefused)
+                ),
+            }
+        }
+    });
+}
+
+#[test]
+fn domain_fallback_retries_with_the_hostname() {
+    smol::block_on(async {
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = smol::spawn(async move {
+            let mut requests = vec![];
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                common::accept_no_auth(&mut stream).await;
+                let request = common::read_request(&mut stream).await;
+                // Like an IPv4-only proxy asked for an IPv6 address
+                let rep = match request[3] {
+                    0x03 => 0x00,
+                    _ => 0x08,
+                };
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let config = Socks5Config::new(proxy).domain_fallback(true);
+        config.connect_resolved("localhost", 80).await.unwrap();
+
+        let requests = server.await;
+        // The locally resolved address, whichever family it was
+        assert!(matches!(requests[0][3], 0x01 | 0x04));
+        assert_eq!(requests[1][3..5], [0x03, 9]);
+        assert_eq!(&requests[1][5..14], b"localhost");
+    });
+}
+
+#[test]
+fn domain_fallback_not_taken_for_ip_literals() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            assert_eq!(common::read_request(&mut stream).await[3], 0x04);
+            let reply = [0x05, 0x08, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).domain_fallback(true);
+        let err = config.connect_resolved("::1", 80).await.unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::AddressTypeNotSupported));
+    });
+}
+
+#[test]
+fn connect_phase_timeout_abandons_bound_connect() {
+    smol::block_on(async {
+        // A listener whose accept queue is full drops further SYNs, so
+        // connecting to it hangs
+        let listener =
+            socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        listener
+            .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+            .unwrap();
+        listener.listen(0).unwrap();
+        let proxy = listener.local_addr().unwrap().as_socket().unwrap();
+        let mut queued = vec![];
+        while let Ok(stream) =
+            std::net::TcpStream::connect_timeout(&proxy, Duration::from_millis(100))
+        {
+            queued.push(stream);
+        }
+
+        let local = "127.0.0.1:0".parse().unwrap();
+        let config = Socks5Config::new(proxy)
+            .local_addr(local)
+            .phase_timeout(Phase::Connect, Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        let err = config
+            .connect_with_domain("example.com", 80)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Timeout(Phase::Connect));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    });
+}
+
+#[test]
+fn connect_stream_reports_info_and_counts_bytes() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = [0u8; 11];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+            common::read_request(&mut stream).await;
+            let reply = [0x05, 0x00, 0x00, 0x01, 10, 0, 0, 7, 0x1f, 0x90];
+            stream.write_all(&reply).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 3];
+            stream.read_exact(&mut buf).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Config::new(&proxy)
+            .with_credentials(Credentials::new("user", "pass").unwrap())
+            .resolution(Resolution::Remote)
+            .connect_stream(&target)
+            .await
+            .unwrap()
+            .idle_timeout(Duration::from_secs(5));
+
+        assert_eq!(stream.proxy_addr(), proxy.parse::<SocketAddr>().unwrap());
+        assert_eq!(stream.target_addr(), &target);
+        assert_eq!(stream.auth_method(), 0x02);
+        assert_eq!(
+            stream.bound_addr(),
+            &TargetAddr::Ip("10.0.0.7:8080".parse().unwrap())
+        );
+        // Method selection, authentication and the reply
+        assert_eq!(stream.info().handshake_bytes_read, 2 + 2 + 10);
+
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(b"hey").await.unwrap();
+        assert_eq!((stream.bytes_sent(), stream.bytes_received()), (3, 5));
+    });
+}
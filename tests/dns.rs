@@ -0,0 +1,177 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use async_socks5::{Phase, Socks5Client, Socks5Error, Socks5Resolver, Socks5Server};
+use smol::net::UdpSocket;
+
+/// Start a proxy serving UDP ASSOCIATE and return its address.
+async fn start_proxy() -> String {
+    let listener = Socks5Server::new().bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    smol::spawn(async move {
+        while let Some(client) = listener.accept().await.unwrap() {
+            smol::spawn(client).detach();
+        }
+    })
+    .detach();
+    addr
+}
+
+/// Answer each query with `answers(qtype)` records, named by a pointer
+/// to the question, or with `rcode` if nonzero.
+async fn start_dns(rcode: u8, answers: fn(u16) -> Vec<(u16, Vec<u8>)>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    smol::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (n, client) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..n];
+            let qtype = u16::from_be_bytes([query[n - 4], query[n - 3]]);
+            let records = answers(qtype);
+
+            let mut reply = query.to_vec();
+            reply[2] |= 0x80;
+            reply[3] = 0x80 | rcode;
+            reply[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+            for (rtype, data) in records {
+                reply.extend_from_slice(&[0xc0, 12]);
+                reply.extend_from_slice(&rtype.to_be_bytes());
+                reply.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+                reply.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                reply.extend_from_slice(&data);
+            }
+            socket.send_to(&reply, client).await.unwrap();
+        }
+    })
+    .detach();
+    addr
+}
+
+#[test]
+fn resolves_a_and_aaaa_through_relay() {
+    smol::block_on(async {
+        let dns = start_dns(0, |qtype| match qtype {
+            // A CNAME first, which the resolver skips
+            1 => vec![(5, vec![0xc0, 12]), (1, vec![192, 0, 2, 1])],
+            28 => vec![(
+                28,
+                "2001:db8::1"
+                    .parse::<std::net::Ipv6Addr>()
+                    .unwrap()
+                    .octets()
+                    .to_vec(),
+            )],
+            _ => vec![],
+        })
+        .await;
+        let proxy = start_proxy().await;
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let resolver = Socks5Resolver::new(socket, dns);
+
+        let addrs = resolver.lookup_ip("example.org.").await.unwrap();
+        let expected: Vec<IpAddr> =
+            vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        assert_eq!(addrs, expected);
+    });
+}
+
+#[test]
+fn nxdomain_and_silence() {
+    smol::block_on(async {
+        let dns = start_dns(3, |_| vec![]).await;
+        let proxy = start_proxy().await;
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let resolver = Socks5Resolver::new(socket, dns);
+        let err = resolver.lookup_ipv4("missing.example").await.unwrap_err();
+        assert_eq!(err, Socks5Error::Dns(3));
+
+        let err = resolver.lookup_ipv4("bad..name").await.unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+
+        // Nobody answers on this port
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = Socks5Resolver::new(resolver.into_inner(), silent.local_addr().unwrap())
+            .timeout(Duration::from_millis(200));
+        let err = resolver.lookup_ipv6("example.org").await.unwrap_err();
+        assert_eq!(err, Socks5Error::Timeout(Phase::Target));
+    });
+}
+
+/// Ignore the first `ignored` queries, then answer with one A record and
+/// the given extra flags in the third header byte.
+async fn start_lossy_dns(ignored: usize, flags: u8) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    smol::spawn(async move {
+        let mut buf = [0u8; 512];
+        for _ in 0..ignored {
+            socket.recv_from(&mut buf).await.unwrap();
+        }
+        loop {
+            let (n, client) = socket.recv_from(&mut buf).await.unwrap();
+            let mut reply = buf[..n].to_vec();
+            reply[2] |= 0x80 | flags;
+            reply[3] = 0x80;
+            reply[6..8].copy_from_slice(&[0, 1]);
+            reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+            socket.send_to(&reply, client).await.unwrap();
+        }
+    })
+    .detach();
+    addr
+}
+
+#[test]
+fn lost_queries_are_sent_again() {
+    smol::block_on(async {
+        let dns = start_lossy_dns(2, 0).await;
+        let proxy = start_proxy().await;
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let resolver = Socks5Resolver::new(socket, dns).timeout(Duration::from_millis(900));
+        let addrs = resolver.lookup_ipv4("example.org").await.unwrap();
+        assert_eq!(addrs, ["192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap()]);
+    });
+}
+
+#[test]
+fn truncated_answer_fails() {
+    smol::block_on(async {
+        let dns = start_lossy_dns(0, 0x02).await;
+        let proxy = start_proxy().await;
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let resolver = Socks5Resolver::new(socket, dns);
+        let err = resolver.lookup_ipv4("example.org").await.unwrap_err();
+        assert_eq!(err, Socks5Error::DnsTruncated);
+    });
+}
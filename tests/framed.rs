@@ -0,0 +1,81 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::ErrorKind;
+
+use async_socks5::Framed;
+use smol::io::Cursor;
+
+fn decode(bytes: Vec<u8>, max_frame: usize) -> Framed<Cursor<Vec<u8>>> {
+    Framed::new(Cursor::new(bytes), max_frame)
+}
+
+#[test]
+fn encode_boundary_frames() {
+    smol::block_on(async {
+        let mut framed = Framed::new(Cursor::new(vec![]), 4);
+        framed.send(b"").await.unwrap();
+        framed.send(b"abcd").await.unwrap();
+
+        let err = framed.send(b"abcde").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let expected = [0, 0, 0, 0, 0, 0, 0, 4, b'a', b'b', b'c', b'd'];
+        assert_eq!(framed.into_inner().into_inner(), expected);
+    });
+}
+
+#[test]
+fn decode_boundary_frames() {
+    smol::block_on(async {
+        let mut framed = decode(vec![0, 0, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4], 4);
+        assert_eq!(framed.recv().await.unwrap(), Some(vec![]));
+        assert_eq!(framed.recv().await.unwrap(), Some(vec![1, 2, 3, 4]));
+        assert_eq!(framed.recv().await.unwrap(), None);
+
+        let mut framed = decode(vec![0, 0, 0, 5, 1, 2, 3, 4, 5], 4);
+        let err = framed.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    });
+}
+
+#[test]
+fn decode_truncated_frames() {
+    smol::block_on(async {
+        let mut framed = decode(vec![0, 0], 16);
+        let err = framed.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let mut framed = decode(vec![0, 0, 0, 3, 1, 2], 16);
+        let err = framed.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    });
+}
+
+#[test]
+fn roundtrip_max_frame() {
+    smol::block_on(async {
+        let frame: Vec<u8> = (0..=u8::MAX).cycle().take(65536).collect();
+
+        let mut framed = Framed::new(Cursor::new(vec![]), frame.len());
+        framed.send(&frame).await.unwrap();
+
+        let mut framed = decode(framed.into_inner().into_inner(), frame.len());
+        assert_eq!(framed.recv().await.unwrap(), Some(frame));
+    });
+}
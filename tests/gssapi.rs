@@ -0,0 +1,179 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "gssapi")]
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_socks5::{GssapiAuth, GssapiContext, Phase, ProtectionLevel, Socks5Config, Socks5Error};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, TcpStream};
+
+/// Context taking two round trips, with wrapping that only adds a tag
+#[derive(Debug, Default)]
+struct FakeContext {
+    steps: u8,
+}
+
+impl GssapiContext for FakeContext {
+    fn step(&mut self, input: Option<&[u8]>) -> Result<Option<Vec<u8>>, Socks5Error> {
+        self.steps += 1;
+        match (self.steps, input) {
+            (1, None) => Ok(Some(b"hello".to_vec())),
+            (2, Some(b"again")) => Ok(Some(b"there".to_vec())),
+            (3, Some(b"done")) => Ok(None),
+            _ => Err(Socks5Error::AuthenticationFailed),
+        }
+    }
+
+    fn is_established(&self) -> bool {
+        self.steps >= 3
+    }
+
+    fn wrap(&mut self, data: &[u8], _encrypt: bool) -> Result<Vec<u8>, Socks5Error> {
+        Ok([b"w:", data].concat())
+    }
+
+    fn unwrap(&mut self, token: &[u8]) -> Result<Vec<u8>, Socks5Error> {
+        token
+            .strip_prefix(b"w:")
+            .map(<[u8]>::to_vec)
+            .ok_or(Socks5Error::UnexpectedResponse)
+    }
+}
+
+async fn read_message(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await.unwrap();
+    assert_eq!(header[0], 0x01);
+    let mut token = vec![0u8; u16::from_be_bytes([header[2], header[3]]) as usize];
+    stream.read_exact(&mut token).await.unwrap();
+    (header[1], token)
+}
+
+async fn write_message(stream: &mut TcpStream, mtyp: u8, token: &[u8]) {
+    let mut message = vec![0x01, mtyp];
+    message.extend_from_slice(&(token.len() as u16).to_be_bytes());
+    message.extend_from_slice(token);
+    stream.write_all(&message).await.unwrap();
+}
+
+#[test]
+fn gssapi_exchange_and_protection_level() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x01]);
+            stream.write_all(&[0x05, 0x01]).await.unwrap();
+
+            assert_eq!(read_message(&mut stream).await, (0x01, b"hello".to_vec()));
+            write_message(&mut stream, 0x01, b"again").await;
+            assert_eq!(read_message(&mut stream).await, (0x01, b"there".to_vec()));
+            write_message(&mut stream, 0x01, b"done").await;
+
+            // Ask for confidentiality, settle for integrity
+            assert_eq!(read_message(&mut stream).await, (0x02, b"w:\x02".to_vec()));
+            write_message(&mut stream, 0x02, b"w:\x01").await;
+
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let auth = Arc::new(GssapiAuth::new(
+            || Box::new(FakeContext::default()),
+            ProtectionLevel::Confidentiality,
+        ));
+        let config = Socks5Config::new(&proxy).auth_method(auth.clone());
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        server.await;
+
+        assert_eq!(auth.negotiated_level(), Some(ProtectionLevel::Integrity));
+        let mut session = auth.take_session().unwrap();
+        assert_eq!(session.level, ProtectionLevel::Integrity);
+        assert_eq!(session.context.wrap(b"data", false).unwrap(), b"w:data");
+    });
+}
+
+#[test]
+fn gssapi_abort_fails_authentication() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x01]).await.unwrap();
+            read_message(&mut stream).await;
+            stream.write_all(&[0x01, 0xff]).await.unwrap();
+        })
+        .await;
+
+        let auth = GssapiAuth::new(
+            || Box::new(FakeContext::default()),
+            ProtectionLevel::Integrity,
+        );
+        let config = Socks5Config::new(&proxy).auth_method(auth);
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert!(matches!(err, Err(Socks5Error::AuthenticationFailed)));
+    });
+}
+
+#[test]
+fn gssapi_retries_after_cancelled_attempt() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let server = smol::spawn(async move {
+            // The first proxy never answers the context token
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x01]).await.unwrap();
+            read_message(&mut stream).await;
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x01]).await.unwrap();
+            read_message(&mut stream).await;
+            write_message(&mut stream, 0x01, b"again").await;
+            read_message(&mut stream).await;
+            write_message(&mut stream, 0x01, b"done").await;
+            read_message(&mut stream).await;
+            write_message(&mut stream, 0x02, b"w:\x01").await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        });
+
+        let auth = Arc::new(GssapiAuth::new(
+            || Box::new(FakeContext::default()),
+            ProtectionLevel::Integrity,
+        ));
+        let config = Socks5Config::new(&proxy)
+            .auth_method(auth.clone())
+            .phase_timeout(Phase::Authentication, Duration::from_millis(200));
+
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert!(matches!(
+            err,
+            Err(Socks5Error::Timeout(Phase::Authentication))
+        ));
+
+        config.connect_with_domain("example.com", 80).await.unwrap();
+        server.await;
+        assert_eq!(auth.negotiated_level(), Some(ProtectionLevel::Integrity));
+    });
+}
@@ -0,0 +1,98 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_socks5::{ReplyCode, Socks5Config, Socks5Error, Socks5Metrics};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<String>>);
+
+impl Socks5Metrics for Recorder {
+    fn proxy_connected(&self, _proxy: SocketAddr, _latency: Duration) {
+        self.0.lock().unwrap().push("connected".into());
+    }
+
+    fn authenticated(&self, method: u8, success: bool) {
+        let event = format!("auth {:#04x} {}", method, success);
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn handshake_completed(&self, _duration: Duration) {
+        self.0.lock().unwrap().push("handshake".into());
+    }
+
+    fn reply(&self, code: ReplyCode) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("reply {}", u8::from(code)));
+    }
+
+    fn failed(&self, error: &Socks5Error) {
+        self.0.lock().unwrap().push(format!("failed: {}", error));
+    }
+}
+
+#[test]
+fn connection_stages_recorded() {
+    smol::block_on(async {
+        let recorder = Arc::new(Recorder::default());
+
+        for rep in [0x00, 0x02] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+                let mut auth = [0u8; 1 + 1 + 4 + 1 + 4];
+                stream.read_exact(&mut auth).await.unwrap();
+                stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+                common::read_request(&mut stream).await;
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .credentials("user", "pass")
+                .metrics(recorder.clone());
+            let _ = config.connect_with_domain("example.com", 80).await;
+        }
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                "connected",
+                "auth 0x02 true",
+                "handshake",
+                "reply 0",
+                "connected",
+                "auth 0x02 true",
+                "handshake",
+                "reply 2",
+                "failed: proxy replied: connection not allowed by ruleset",
+            ]
+        );
+    });
+}
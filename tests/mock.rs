@@ -0,0 +1,171 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "testing")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_socks5::testing::{duplex, MockListener, MockSocks5Server};
+use async_socks5::{Phase, ReplyCode, Socks5Client, Socks5Config, Socks5Error, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Start `server` and return the proxy address along with the listener.
+async fn listen(server: MockSocks5Server) -> (String, MockListener) {
+    let listener = server.listen().await.unwrap();
+    (listener.local_addr().unwrap().to_string(), listener)
+}
+
+#[test]
+fn no_auth_connect() {
+    smol::block_on(async {
+        let (proxy, listener) = listen(MockSocks5Server::new()).await;
+        let server = smol::spawn(async move {
+            let (mut stream, request) = listener.accept().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            request
+        });
+
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let mut stream = Socks5Client::connect(&proxy, &target, None).await.unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"hello");
+
+        let request = server.await;
+        assert_eq!(request.methods, [0x00]);
+        assert_eq!(request.credentials, None);
+        assert_eq!(request.command, 0x01);
+        assert_eq!(request.target, TargetAddr::Ip(target));
+    });
+}
+
+#[test]
+fn user_pass_connect() {
+    smol::block_on(async {
+        for password in ["secret", "wrong"] {
+            let (proxy, listener) =
+                listen(MockSocks5Server::new().require_auth("user", "secret")).await;
+            let server = smol::spawn(async move { listener.accept().await.map(|(_, r)| r) });
+
+            let target = TargetAddr::Domain("example.com".into(), 443);
+            let result =
+                Socks5Client::connect_stream(&proxy, &target, Some(("user", password))).await;
+
+            match password {
+                "secret" => {
+                    assert_eq!(result.unwrap().auth_method(), 0x02);
+                    let request = server.await.unwrap();
+                    assert_eq!(
+                        request.credentials,
+                        Some((b"user".to_vec(), b"secret".to_vec()))
+                    );
+                    assert_eq!(request.target, target);
+                }
+                _ => {
+                    assert!(matches!(result, Err(Socks5Error::AuthenticationFailed)));
+                    assert!(server.await.is_err());
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn ipv6_target_and_bound_addr() {
+    smol::block_on(async {
+        let bound: SocketAddr = "[2001:db8::2]:1080".parse().unwrap();
+        let mock = MockSocks5Server::new().bound_addr(TargetAddr::Ip(bound));
+        let (proxy, listener) = listen(mock).await;
+        let server = smol::spawn(async move { listener.accept().await.unwrap().1 });
+
+        let target = TargetAddr::Ip("[2001:db8::1]:443".parse().unwrap());
+        let stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        assert_eq!(*stream.bound_addr(), TargetAddr::Ip(bound));
+        assert_eq!(server.await.target, target);
+    });
+}
+
+#[test]
+fn failure_reply_code() {
+    smol::block_on(async {
+        let (proxy, listener) = listen(MockSocks5Server::new().reply_code(0x05)).await;
+        let _server = smol::spawn(async move { listener.accept().await });
+
+        let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+    });
+}
+
+#[test]
+fn handshake_over_in_memory_pipe() {
+    smol::block_on(async {
+        let (client, mut proxy) = duplex(1024);
+        let server = smol::spawn(async move {
+            let request = MockSocks5Server::new().serve(&mut proxy).await.unwrap();
+            proxy.write_all(b"hello").await.unwrap();
+            request
+        });
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::handshake_over(client, &target, None)
+            .await
+            .unwrap();
+
+        let mut data = [0u8; 5];
+        stream.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"hello");
+        assert_eq!(server.await.target, target);
+    });
+}
+
+#[test]
+fn slow_and_truncated_replies() {
+    smol::block_on(async {
+        let server = MockSocks5Server::new().reply_delay(Duration::from_secs(5));
+        let (proxy, listener) = listen(server).await;
+        let _server = smol::spawn(async move { listener.accept().await });
+
+        let config =
+            Socks5Config::new(&proxy).phase_timeout(Phase::Reply, Duration::from_millis(50));
+        let err = config
+            .connect_with_domain("example.com", 80)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Timeout(Phase::Reply));
+
+        let (proxy, listener) = listen(MockSocks5Server::new().truncate_reply(6)).await;
+        let _server = smol::spawn(async move {
+            // Hang up right after the partial reply
+            listener.accept().await.map(|(_, request)| request)
+        });
+
+        let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Socks5Error::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof),
+            "{:?}",
+            err
+        );
+    });
+}
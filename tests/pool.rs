@@ -0,0 +1,139 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_socks5::{Socks5Config, Socks5Pool, Socks5Server, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpListener;
+use smol::Task;
+
+/// Run a proxy serving every client, counting the connections it got.
+async fn proxy() -> (String, Arc<AtomicUsize>, Task<()>) {
+    let listener = Socks5Server::new().bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let counter = accepted.clone();
+    let task = smol::spawn(async move {
+        while let Some(client) = listener.accept().await.unwrap() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            smol::spawn(client).detach();
+        }
+    });
+
+    (addr, accepted, task)
+}
+
+/// Run a target answering every connection with `hi`.
+async fn target() -> (TargetAddr, Task<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().into();
+    let task = smol::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"hi").await.unwrap();
+        }
+    });
+    (addr, task)
+}
+
+#[test]
+fn pooled_connections_used_first() {
+    smol::block_on(async {
+        let (proxy, accepted, _proxy) = proxy().await;
+        let (target, _target) = target().await;
+
+        let pool = Socks5Pool::new(Socks5Config::new(&proxy)).max_idle(2);
+        pool.fill().await.unwrap();
+        assert_eq!(pool.idle_count(), 2);
+
+        for expected in [2, 2, 3] {
+            let mut stream = pool.connect(&target).await.unwrap();
+            let mut buf = [0u8; 2];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hi");
+            assert_eq!(accepted.load(Ordering::SeqCst), expected);
+        }
+        assert_eq!(pool.idle_count(), 0);
+    });
+}
+
+#[test]
+fn expired_connections_replaced() {
+    smol::block_on(async {
+        let (proxy, accepted, _proxy) = proxy().await;
+        let (target, _target) = target().await;
+
+        let pool = Socks5Pool::new(Socks5Config::new(&proxy))
+            .max_idle(1)
+            .idle_timeout(Duration::ZERO);
+        pool.fill().await.unwrap();
+        pool.connect(&target).await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    });
+}
+
+#[test]
+fn closed_connections_replaced() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let server = smol::spawn(async move {
+            // The first connection is closed right after the handshake
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::accept_no_auth(&mut stream).await;
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        });
+
+        let pool = Socks5Pool::new(Socks5Config::new(&proxy)).max_idle(1);
+        pool.fill().await.unwrap();
+        smol::Timer::after(Duration::from_millis(50)).await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        pool.connect(&target).await.unwrap();
+        assert_eq!(server.await[3], 0x03);
+    });
+}
+
+#[test]
+fn pooled_stream_counts_bytes() {
+    smol::block_on(async {
+        let (proxy, _accepted, _proxy) = proxy().await;
+        let (target, _target) = target().await;
+
+        let pool = Socks5Pool::new(Socks5Config::new(&proxy)).max_idle(1);
+        pool.fill().await.unwrap();
+
+        let mut stream = pool.connect_stream(&target).await.unwrap();
+        assert_eq!(stream.target_addr(), &target);
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(stream.bytes_received(), 2);
+    });
+}
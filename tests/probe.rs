@@ -0,0 +1,106 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use async_socks5::{ReplyCode, Socks5Client, Socks5Error};
+use smol::io::AsyncWriteExt;
+
+/// Probe for UDP ASSOCIATE support against a mock sending `reply`.
+async fn probe_udp(reply: &[u8]) -> Result<bool, Socks5Error> {
+    let reply = reply.to_vec();
+    let (proxy, server) = common::serve_once(|mut stream| async move {
+        common::accept_no_auth(&mut stream).await;
+        let request = common::read_request(&mut stream).await;
+        stream.write_all(&reply).await.unwrap();
+        request
+    })
+    .await;
+
+    let result = Socks5Client::supports_command(&proxy, 0x03, None).await;
+    let request = server.await;
+    assert_eq!(request[..4], [0x05, 0x03, 0x00, 0x01]);
+    result
+}
+
+#[test]
+fn supports_command_interprets_replies() {
+    smol::block_on(async {
+        let ok = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x13, 0x88];
+        assert!(probe_udp(&ok).await.unwrap());
+
+        let unsupported = [0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        assert!(!probe_udp(&unsupported).await.unwrap());
+
+        let refused = [0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            probe_udp(&refused).await.unwrap_err(),
+            Socks5Error::Reply(ReplyCode::NotAllowed)
+        );
+    });
+}
+
+#[test]
+fn supports_command_rejects_other_protocols() {
+    smol::block_on(async {
+        // A SOCKS4 style reply whose second byte happens to be zero
+        let socks4 = [0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x13, 0x88];
+        assert_eq!(
+            probe_udp(&socks4).await.unwrap_err(),
+            Socks5Error::UnexpectedResponse
+        );
+
+        // The reply is cut short inside BND.ADDR
+        let truncated = [0x05, 0x00, 0x00, 0x01, 127, 0];
+        assert!(matches!(
+            probe_udp(&truncated).await.unwrap_err(),
+            Socks5Error::IoError(_)
+        ));
+    });
+}
+
+/// Ask a mock selecting `method` whether it requires authentication.
+async fn probe_auth(method: u8) -> Result<bool, Socks5Error> {
+    let (proxy, server) = common::serve_once(move |mut stream| async move {
+        let offered = common::read_greeting(&mut stream).await;
+        stream.write_all(&[0x05, method]).await.unwrap();
+        offered
+    })
+    .await;
+
+    let result = Socks5Client::requires_auth(&proxy).await;
+    assert_eq!(server.await, [0x00, 0x02]);
+    result
+}
+
+#[test]
+fn requires_auth_interprets_selection() {
+    smol::block_on(async {
+        assert!(!probe_auth(0x00).await.unwrap());
+        assert!(probe_auth(0x02).await.unwrap());
+        assert_eq!(
+            probe_auth(0xff).await.unwrap_err(),
+            Socks5Error::NoAcceptableAuthMethods
+        );
+        // GSS-API was never offered
+        assert_eq!(
+            probe_auth(0x01).await.unwrap_err(),
+            Socks5Error::UnexpectedResponse
+        );
+    });
+}
@@ -0,0 +1,164 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use async_socks5::protocol::{
+    ClientHandshake, Greeting, MethodSelection, Reply, Request, UdpHeader,
+};
+use async_socks5::{Command, Credentials, ReplyCode, Socks5Error, TargetAddr};
+
+#[test]
+fn messages_round_trip_and_wait_for_more_bytes() {
+    let greeting = Greeting {
+        methods: vec![0x00, 0x02],
+    };
+    let request = Request {
+        command: Command::Bind,
+        target: TargetAddr::Domain("example.com".into(), 21),
+    };
+    let reply = Reply {
+        code: ReplyCode::HostUnreachable,
+        bound_addr: "[::1]:1080".parse().unwrap(),
+    };
+
+    let bytes = greeting.encode().unwrap();
+    for end in 0..bytes.len() {
+        assert_eq!(Greeting::decode(&bytes[..end]).unwrap(), None);
+    }
+    assert_eq!(
+        Greeting::decode(&bytes).unwrap(),
+        Some((greeting, bytes.len()))
+    );
+
+    let bytes = request.encode().unwrap();
+    for end in 0..bytes.len() {
+        assert_eq!(Request::decode(&bytes[..end]).unwrap(), None);
+    }
+    assert_eq!(
+        Request::decode(&bytes).unwrap(),
+        Some((request, bytes.len()))
+    );
+
+    let mut bytes = reply.encode().unwrap();
+    let len = bytes.len();
+    bytes.extend_from_slice(b"tunnel");
+    assert_eq!(Reply::decode(&bytes).unwrap(), Some((reply, len)));
+
+    assert_eq!(
+        MethodSelection::decode(&[0x05, 0xff]).unwrap(),
+        Some((MethodSelection { method: 0xff }, 2))
+    );
+
+    let header = UdpHeader {
+        frag: 1,
+        target: "10.0.0.1:53".parse().unwrap(),
+    };
+    let mut datagram = header.encode().unwrap();
+    datagram.extend_from_slice(b"query");
+    let (decoded, len) = UdpHeader::decode(&datagram).unwrap();
+    assert_eq!((decoded, &datagram[len..]), (header, &b"query"[..]));
+}
+
+#[test]
+fn malformed_messages_rejected() {
+    assert_eq!(
+        Greeting::decode(&[0x04, 0x01, 0x00]),
+        Err(Socks5Error::UnexpectedResponse)
+    );
+    assert_eq!(
+        Greeting { methods: vec![] }.encode(),
+        Err(Socks5Error::InvalidInput(
+            "greeting must offer 1 to 255 methods"
+        ))
+    );
+    // Nonzero RSV, unknown ATYP and an empty domain
+    assert_eq!(
+        Reply::decode(&[0x05, 0x00, 0x01, 0x01]),
+        Err(Socks5Error::UnexpectedResponse)
+    );
+    assert_eq!(
+        Request::decode(&[0x05, 0x01, 0x00, 0x02]),
+        Err(Socks5Error::UnsupportedAddressType)
+    );
+    assert_eq!(
+        Request::decode(&[0x05, 0x01, 0x00, 0x03, 0x00]),
+        Err(Socks5Error::UnexpectedResponse)
+    );
+    assert_eq!(
+        UdpHeader::decode(&[0x00, 0x00, 0x00, 0x01, 127, 0, 0]),
+        Err(Socks5Error::UnexpectedResponse)
+    );
+}
+
+#[test]
+fn client_handshake_with_authentication() {
+    let target = TargetAddr::Domain("example.com".into(), 443);
+    let credentials = Credentials::new("user", "pass").unwrap();
+    let mut handshake = ClientHandshake::new(&Request::connect(target), Some(credentials)).unwrap();
+
+    assert_eq!(handshake.outgoing(), [0x05, 0x02, 0x00, 0x02]);
+    assert_eq!(handshake.outgoing(), []);
+    assert_eq!(handshake.received(&[0x05]).unwrap(), 0);
+    assert_eq!(handshake.received(&[0x05, 0x02]).unwrap(), 2);
+    assert_eq!(handshake.outgoing(), b"\x01\x04user\x04pass");
+
+    assert_eq!(handshake.received(&[0x01, 0x00]).unwrap(), 2);
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, 11];
+    request.extend_from_slice(b"example.com\x01\xbb");
+    assert_eq!(handshake.outgoing(), request);
+    assert_eq!(handshake.bound_addr(), None);
+
+    let reply = [0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        handshake.received(&reply),
+        Err(Socks5Error::Reply(ReplyCode::ConnectionRefused))
+    );
+
+    // A method that wasn't offered is a protocol violation
+    let target = TargetAddr::Domain("example.com".into(), 443);
+    let mut handshake = ClientHandshake::new(&Request::connect(target), None).unwrap();
+    handshake.outgoing();
+    assert_eq!(
+        handshake.received(&[0x05, 0x01]),
+        Err(Socks5Error::UnexpectedResponse)
+    );
+}
+
+#[test]
+fn international_domains_sent_as_punycode() {
+    let cases = [
+        ("bücher.example", "xn--bcher-kva.example"),
+        ("MÜNCHEN.de", "xn--mnchen-3ya.de"),
+        ("例え.テスト", "xn--r8jz45g.xn--zckzah"),
+        ("plain.example", "plain.example"),
+    ];
+
+    for (domain, ascii) in cases {
+        let request = Request::connect(TargetAddr::Domain(domain.into(), 80));
+        let bytes = request.encode().unwrap();
+        assert_eq!(bytes[4] as usize, ascii.len());
+        assert_eq!(&bytes[5..5 + ascii.len()], ascii.as_bytes(), "{}", domain);
+    }
+
+    // 120 labels of one character each, too long once encoded
+    let long = vec!["ü"; 120].join(".");
+    let request = Request::connect(TargetAddr::Domain(long, 80));
+    assert!(matches!(
+        request.encode(),
+        Err(Socks5Error::InvalidInput(_))
+    ));
+}
@@ -0,0 +1,109 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use async_socks5::{Proxy, Socks5Error, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpStream;
+
+/// Read an HTTP request head.
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[test]
+fn http_connect_with_basic_auth() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let head = read_head(&mut stream).await;
+            let response = "HTTP/1.1 200 Connection established\r\nVia: test\r\n\r\nhello";
+            stream.write_all(response.as_bytes()).await.unwrap();
+            head
+        })
+        .await;
+
+        let proxy = Proxy::from_url(&format!("http://user:pass@{}", proxy)).unwrap();
+        let target = TargetAddr::Domain("example.com".into(), 443);
+        let mut stream = proxy.connect(&target).await.unwrap();
+
+        let mut banner = [0u8; 5];
+        stream.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"hello");
+        assert_eq!(
+            server.await,
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\
+             Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        );
+    });
+}
+
+#[test]
+fn http_connect_failures() {
+    smol::block_on(async {
+        let cases = [
+            ("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n", None),
+            (
+                "HTTP/1.0 403 Forbidden\r\n\r\n",
+                Some(Socks5Error::HttpStatus(403)),
+            ),
+            (
+                "SSH-2.0-OpenSSH\r\n\r\n",
+                Some(Socks5Error::UnexpectedResponse),
+            ),
+        ];
+
+        for (response, expected) in cases {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                read_head(&mut stream).await;
+                stream.write_all(response.as_bytes()).await.unwrap();
+            })
+            .await;
+
+            let proxy = Proxy::from_url(&format!("http://{}", proxy)).unwrap();
+            let target: TargetAddr = "[2001:db8::1]:443".parse().unwrap();
+            let err = proxy.connect(&target).await.unwrap_err();
+            assert_eq!(err, expected.unwrap_or(Socks5Error::CredentialsRequired));
+        }
+    });
+}
+
+#[test]
+fn socks5_urls_give_socks5_proxies() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        })
+        .await;
+
+        let proxy = Proxy::from_url(&format!("socks5h://{}", proxy)).unwrap();
+        assert!(matches!(proxy, Proxy::Socks5 { .. }));
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        proxy.connect(&target).await.unwrap();
+        assert_eq!(server.await[3], 0x03);
+    });
+}
@@ -0,0 +1,102 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::time::Duration;
+
+use async_socks5::{
+    ProxyPool, ReplyCode, Selection, Socks5Config, Socks5Error, Socks5Server, TargetAddr,
+};
+use smol::net::TcpListener;
+
+/// Start a proxy serving any number of clients and return its address.
+async fn start_proxy(server: Socks5Server) -> String {
+    let listener = server.bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    smol::spawn(async move {
+        while let Some(client) = listener.accept().await.unwrap() {
+            smol::spawn(client).detach();
+        }
+    })
+    .detach();
+    addr
+}
+
+/// Address nothing listens on
+async fn dead_proxy() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[test]
+fn fails_over_and_quarantines() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = TargetAddr::Ip(target.local_addr().unwrap());
+        smol::spawn(async move {
+            loop {
+                let _ = target.accept().await.unwrap();
+            }
+        })
+        .detach();
+
+        let server = Socks5Server::new().auth(|user, pass| user == b"bob" && pass == b"secret");
+        let live = start_proxy(server).await;
+        let pool = ProxyPool::new()
+            .proxy(Socks5Config::new(dead_proxy().await))
+            .proxy(Socks5Config::new(&live).credentials("bob", "secret"))
+            .quarantine(1, Duration::from_secs(60));
+        assert_eq!(pool.len(), 2);
+
+        // Whichever proxy goes first, connections succeed, and the dead
+        // one ends up quarantined
+        for _ in 0..3 {
+            pool.connect(&target_addr).await.unwrap();
+        }
+        assert_eq!(pool.available(), 1);
+    });
+}
+
+#[test]
+fn quarantined_pool_and_target_errors() {
+    smol::block_on(async {
+        let pool = ProxyPool::new()
+            .proxy(Socks5Config::new(dead_proxy().await))
+            .selection(Selection::LeastFailures)
+            .quarantine(1, Duration::from_secs(60));
+        let target = TargetAddr::Ip("127.0.0.1:9".parse().unwrap());
+
+        let err = pool.connect(&target).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::IoError(_)));
+        assert_eq!(pool.available(), 0);
+        assert_eq!(
+            pool.connect(&target).await.unwrap_err(),
+            Socks5Error::CircuitOpen
+        );
+
+        // The proxy answering that the target is unreachable is not held
+        // against it
+        let live = start_proxy(Socks5Server::new()).await;
+        let closed = dead_proxy().await.parse().unwrap();
+        let pool = ProxyPool::new()
+            .proxy(Socks5Config::new(&live))
+            .quarantine(1, Duration::from_secs(60));
+        let err = pool.connect(&TargetAddr::Ip(closed)).await.unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+        assert_eq!(pool.available(), 1);
+    });
+}
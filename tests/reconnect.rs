@@ -0,0 +1,106 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::io;
+use std::time::Duration;
+
+use async_socks5::{ReconnectingStream, Resolution, Socks5Config, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpListener;
+use socket2::SockRef;
+
+#[test]
+fn reconnects_and_replays_handshake() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let server = smol::spawn(async move {
+            // The first tunnel is reset, the second one answers the
+            // replayed handshake
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::accept_no_auth(&mut stream).await;
+            let first = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            SockRef::from(&stream)
+                .set_linger(Some(Duration::ZERO))
+                .unwrap();
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            common::accept_no_auth(&mut stream).await;
+            let second = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            let mut hello = [0u8; 5];
+            stream.read_exact(&mut hello).await.unwrap();
+            assert_eq!(&hello, b"hello");
+            stream.write_all(b"welcome").await.unwrap();
+            assert_eq!(first, second);
+            stream
+        });
+
+        let target = TargetAddr::Domain("chat.example".into(), 6667);
+        let config = Socks5Config::new(&proxy).resolution(Resolution::Remote);
+        let mut stream = ReconnectingStream::connect(config, target)
+            .await
+            .unwrap()
+            .backoff(Duration::from_millis(10))
+            .on_reconnect(|mut stream| async move {
+                stream.write_all(b"hello").await?;
+                Ok(stream)
+            });
+
+        let mut buf = [0u8; 7];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"welcome");
+        assert_eq!(stream.reconnects(), 1);
+        let _control = server.await;
+    });
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            SockRef::from(&stream)
+                .set_linger(Some(Duration::ZERO))
+                .unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Ip("192.0.2.1:80".parse().unwrap());
+        let mut stream = ReconnectingStream::connect(Socks5Config::new(&proxy), target)
+            .await
+            .unwrap()
+            .max_attempts(2)
+            .backoff(Duration::from_millis(1));
+        server.await;
+
+        // Nothing listens any more, so both attempts are refused
+        let mut buf = [0u8; 1];
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(stream.reconnects(), 0);
+    });
+}
@@ -0,0 +1,76 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::ErrorKind;
+
+use async_socks5::{relay, relay_with_buffer_size, MAX_RELAY_BUFFER_SIZE};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, TcpStream};
+
+/// Return both ends of a loopback TCP connection.
+async fn tcp_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap());
+    let (client, accepted) = smol::future::zip(client, listener.accept()).await;
+    (client.unwrap(), accepted.unwrap().0)
+}
+
+#[test]
+fn relay_rejects_bad_buffer_sizes() {
+    smol::block_on(async {
+        for size in [0, MAX_RELAY_BUFFER_SIZE + 1] {
+            let (a, _) = tcp_pair().await;
+            let (b, _) = tcp_pair().await;
+            let err = relay_with_buffer_size(a, b, size).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    });
+}
+
+#[test]
+fn relay_copies_both_directions() {
+    smol::block_on(async {
+        for size in [None, Some(1)] {
+            let (mut left, a) = tcp_pair().await;
+            let (b, mut right) = tcp_pair().await;
+
+            let relay = smol::spawn(async move {
+                match size {
+                    Some(size) => relay_with_buffer_size(a, b, size).await,
+                    None => relay(a, b).await,
+                }
+            });
+
+            left.write_all(b"request").await.unwrap();
+            left.close().await.unwrap();
+
+            let mut received = vec![];
+            right.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"request");
+
+            right.write_all(b"longer response").await.unwrap();
+            right.close().await.unwrap();
+
+            let mut received = vec![];
+            left.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"longer response");
+
+            assert_eq!(relay.await.unwrap(), (7, 15));
+        }
+    });
+}
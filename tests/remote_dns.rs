@@ -0,0 +1,72 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use async_socks5::{Socks5Config, Socks5Error, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpListener;
+
+#[test]
+fn forced_remote_dns_sends_domain() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+
+        let server = smol::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0u8; 2];
+            stream.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+            let mut header = [0u8; 5];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut rest = vec![0u8; header[4] as usize + 2];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            (header, rest)
+        });
+
+        let config = Socks5Config::new(&proxy).force_remote_dns(true);
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        config.connect(&target).await.unwrap();
+
+        let (header, rest) = server.await;
+        assert_eq!(header[3], 0x03);
+        assert_eq!(&rest[..header[4] as usize], b"example.com");
+        assert_eq!(rest[header[4] as usize..], [0x00, 0x50]);
+    });
+}
+
+#[test]
+fn forced_remote_dns_refuses_local_lookups() {
+    smol::block_on(async {
+        // Nothing needs to listen, the lookup is refused before dialing
+        let config = Socks5Config::new("127.0.0.1:9").force_remote_dns(true);
+        let err = config
+            .connect_resolved("example.com", 80)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::LocalResolutionDisabled));
+    });
+}
@@ -0,0 +1,554 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+use async_socks5::{
+    AddrType, CommandOptions, ConnectRequest, Credentials, Phase, ReplyCode, Socks5Client,
+    Socks5Config, Socks5Error, Socks5Stream, TargetAddr,
+};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn connect_request_ipv4() {
+    let target = TargetAddr::Ip("192.168.1.2:8080".parse().unwrap());
+    assert_eq!(
+        Socks5Client::build_connect_request(&target).unwrap(),
+        [0x05, 0x01, 0x00, 0x01, 192, 168, 1, 2, 0x1f, 0x90]
+    );
+}
+
+#[test]
+fn connect_request_ipv6() {
+    let target = TargetAddr::Ip("[2001:db8::1]:443".parse().unwrap());
+    let mut expected = vec![0x05, 0x01, 0x00, 0x04, 0x20, 0x01, 0x0d, 0xb8];
+    expected.extend_from_slice(&[0; 11]);
+    expected.extend_from_slice(&[0x01, 0x01, 0xbb]);
+    assert_eq!(
+        Socks5Client::build_connect_request(&target).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn connect_request_domain() {
+    let target = TargetAddr::Domain("icanhazip.com".into(), 80);
+    let mut expected = vec![0x05, 0x01, 0x00, 0x03, 13];
+    expected.extend_from_slice(b"icanhazip.com");
+    expected.extend_from_slice(&[0x00, 0x50]);
+    assert_eq!(
+        Socks5Client::build_connect_request(&target).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn connect_request_max_domain() {
+    let target = TargetAddr::Domain("a".repeat(255), 443);
+    let request = Socks5Client::build_connect_request(&target).unwrap();
+    assert_eq!(request.len(), 3 + 1 + 1 + 255 + 2);
+    assert_eq!(request.capacity(), request.len());
+
+    for domain in [String::new(), "a".repeat(256)] {
+        let target = TargetAddr::Domain(domain, 443);
+        let err = Socks5Client::build_connect_request(&target).unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    }
+}
+
+#[test]
+fn target_addr_conversions() {
+    let ip: std::net::SocketAddr = "10.0.0.1:80".parse().unwrap();
+    assert_eq!(TargetAddr::from(ip), TargetAddr::Ip(ip));
+    assert_eq!(TargetAddr::from(("10.0.0.1", 80)), TargetAddr::Ip(ip));
+    assert_eq!(
+        TargetAddr::from(("example.com", 80)),
+        TargetAddr::Domain("example.com".into(), 80)
+    );
+
+    assert_eq!(
+        "10.0.0.1:80".parse::<TargetAddr>().unwrap(),
+        TargetAddr::Ip(ip)
+    );
+    assert_eq!(
+        "[::1]:443".parse::<TargetAddr>().unwrap(),
+        TargetAddr::Ip("[::1]:443".parse().unwrap())
+    );
+    assert_eq!(
+        "example.com:80".parse::<TargetAddr>().unwrap(),
+        TargetAddr::Domain("example.com".into(), 80)
+    );
+
+    for invalid in [
+        "example.com",
+        ":80",
+        "example.com:http",
+        "::1:80",
+        "host:70000",
+    ] {
+        assert!(matches!(
+            invalid.parse::<TargetAddr>(),
+            Err(Socks5Error::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn connect_target_from_host_and_port() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        })
+        .await;
+
+        Socks5Client::connect_target(&proxy, ("example.com", 80), None)
+            .await
+            .unwrap();
+
+        let request = server.await;
+        assert_eq!(&request[3..5], [0x03, 11]);
+        assert_eq!(&request[5..16], b"example.com");
+    });
+}
+
+#[test]
+fn connect_raw_host() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        })
+        .await;
+
+        let host = [0xde, 0xad, 0x00, 0xbe, 0xef];
+        Socks5Client::connect_with_raw_host(&proxy, &host, 7, None)
+            .await
+            .unwrap();
+
+        let request = server.await;
+        assert_eq!(
+            request[3..],
+            [0x03, 5, 0xde, 0xad, 0x00, 0xbe, 0xef, 0x00, 0x07]
+        );
+    });
+}
+
+#[test]
+fn domain_and_raw_host_send_the_same_request() {
+    smol::block_on(async {
+        let mut requests = vec![];
+        for raw in [false, true] {
+            let (proxy, server) = common::serve_once(|mut stream| async move {
+                assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+                let mut auth = [0u8; 13];
+                stream.read_exact(&mut auth).await.unwrap();
+                stream.write_all(&[0x01, 0x00]).await.unwrap();
+                let request = common::read_request(&mut stream).await;
+                common::reply_ok(&mut stream).await;
+                request
+            })
+            .await;
+
+            let credentials = Some(("user", "secret"));
+            let connected = match raw {
+                false => {
+                    Socks5Client::connect_with_domain(&proxy, "example.com", 80, credentials).await
+                }
+                true => {
+                    Socks5Client::connect_with_raw_host(&proxy, b"example.com", 80, credentials)
+                        .await
+                }
+            };
+            connected.unwrap();
+            requests.push(server.await);
+        }
+
+        assert_eq!(requests[0], requests[1]);
+    });
+}
+
+#[test]
+fn connect_raw_host_validation() {
+    smol::block_on(async {
+        for host in [vec![], vec![b'a'; 256]] {
+            let err = Socks5Client::connect_with_raw_host("127.0.0.1:9", &host, 80, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Socks5Error::InvalidInput(_)));
+        }
+
+        let config = Socks5Config::new("127.0.0.1:9").strict_hostnames(true);
+        let err = config.connect_with_raw_host(b"a\0b", 80).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    });
+}
+
+#[test]
+fn ipv6_target_through_ipv4_proxy() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        })
+        .await;
+
+        // The ATYP follows the target, not the family the proxy is reached over
+        let target = "[2001:db8::1]:443".parse().unwrap();
+        Socks5Client::connect(&proxy, &target, None).await.unwrap();
+
+        let request = server.await;
+        assert_eq!(request[3], 0x04);
+        assert_eq!(
+            request[4..20],
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets()
+        );
+        assert_eq!(request[20..], [0x01, 0xbb]);
+    });
+}
+
+#[test]
+fn execute_connect_request() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+            let mut auth = [0u8; 13];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            (auth, request)
+        })
+        .await;
+
+        let mut request = ConnectRequest::new(&proxy, TargetAddr::Domain("example.com".into(), 80));
+        request.credentials = Some(Credentials::new("user", "secret").unwrap());
+        request.timeout = Some(Duration::from_secs(5));
+
+        // Owned credentials let the request move into a task
+        smol::spawn(async move { Socks5Client::execute(&request).await })
+            .await
+            .unwrap();
+
+        let (auth, request) = server.await;
+        assert_eq!(auth, *b"\x01\x04user\x06secret");
+        assert_eq!(request[3..5], [0x03, 11]);
+    });
+}
+
+#[test]
+fn execute_stream_reports_the_target() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let request = ConnectRequest::new(&proxy, target.clone());
+        let stream = Socks5Client::execute_stream(&request).await.unwrap();
+        assert_eq!(stream.target_addr(), &target);
+        assert_eq!(stream.auth_method(), 0x00);
+    });
+}
+
+#[test]
+fn connect_options_resolve_remotely_by_default() {
+    let request = ConnectRequest::new(
+        "127.0.0.1:1080",
+        TargetAddr::Domain("example.com".into(), 80),
+    );
+    assert!(request.options.force_remote_dns);
+}
+
+#[test]
+fn reply_with_any_bound_addr_type() {
+    smol::block_on(async {
+        let mut ipv6 = vec![0x05, 0x00, 0x00, 0x04];
+        ipv6.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6.extend_from_slice(&[0x1f, 0x90]);
+
+        let mut domain = vec![0x05, 0x00, 0x00, 0x03, 9];
+        domain.extend_from_slice(b"relay.lan");
+        domain.extend_from_slice(&[0x1f, 0x90]);
+
+        for (reply, configured) in [
+            (ipv6.clone(), false),
+            (domain.clone(), false),
+            (ipv6, true),
+            (domain, true),
+        ] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+
+                let mut reply = reply;
+                reply.extend_from_slice(b"tunnel");
+                stream.write_all(&reply).await.unwrap();
+            })
+            .await;
+
+            let target = TargetAddr::Domain("example.com".into(), 80);
+            let mut stream = match configured {
+                false => Socks5Client::connect_stream(&proxy, &target, None)
+                    .await
+                    .map(Socks5Stream::into_inner),
+                true => {
+                    let config = Socks5Config::new(&proxy).force_remote_dns(true);
+                    config.connect(&target).await
+                }
+            }
+            .unwrap();
+
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"tunnel");
+        }
+    });
+}
+
+#[test]
+fn connect_with_domain_full_returns_bound_addr() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let mut reply = vec![0x05, 0x00, 0x00, 0x04];
+            reply.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+            reply.extend_from_slice(&[0x1f, 0x90]);
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let (_stream, bound_addr) =
+            Socks5Client::connect_with_domain_full(&proxy, "example.com", 80, None)
+                .await
+                .unwrap();
+        assert_eq!(
+            bound_addr,
+            TargetAddr::Ip("[2001:db8::2]:8080".parse().unwrap())
+        );
+        assert_eq!(bound_addr.addr_type(), AddrType::IPv6);
+    });
+}
+
+#[test]
+fn reply_codes_are_distinguished() {
+    smol::block_on(async {
+        for rep in 0x01..=0x09u8 {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+            })
+            .await;
+
+            let target = TargetAddr::Domain("example.com".into(), 80);
+            let err = Socks5Client::connect_stream(&proxy, &target, None).await;
+            match err {
+                Err(Socks5Error::Reply(code)) => assert_eq!(u8::from(code), rep),
+                _ => panic!("expected a reply error for {:#04x}", rep),
+            }
+        }
+    });
+
+    assert_eq!(ReplyCode::from(0x04), ReplyCode::HostUnreachable);
+    assert_eq!(ReplyCode::from(0x05), ReplyCode::ConnectionRefused);
+    assert_eq!(ReplyCode::from(0x09), ReplyCode::Other(0x09));
+    assert_eq!(ReplyCode::HostUnreachable.to_string(), "host unreachable");
+}
+
+#[test]
+fn addr_type_bytes() {
+    for (atyp, byte) in [
+        (AddrType::IPv4, 0x01),
+        (AddrType::DomainName, 0x03),
+        (AddrType::IPv6, 0x04),
+    ] {
+        assert_eq!(u8::from(atyp), byte);
+        assert_eq!(AddrType::try_from(byte), Ok(atyp));
+    }
+
+    assert_eq!(
+        AddrType::try_from(0x02),
+        Err(Socks5Error::UnsupportedAddressType)
+    );
+}
+
+#[test]
+fn unknown_reply_addr_type_rejected() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let reply = [0x05, 0x00, 0x00, 0x02, 0, 0, 0, 0, 0, 0];
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let err = Socks5Client::connect_with_domain(&proxy, "example.com", 80, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::UnsupportedAddressType);
+    });
+}
+
+#[test]
+fn malformed_reply_headers_rejected() {
+    smol::block_on(async {
+        let replies = [
+            [0x04, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0],
+            [0x05, 0x00, 0x01, 0x01, 0, 0, 0, 0, 0, 0],
+        ];
+
+        for reply in replies {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                stream.write_all(&reply).await.unwrap();
+            })
+            .await;
+
+            let target = TargetAddr::Domain("example.com".into(), 80);
+            let err = Socks5Client::connect_stream(&proxy, &target, None).await;
+            assert!(matches!(err, Err(Socks5Error::UnexpectedResponse)));
+        }
+    });
+}
+
+#[test]
+fn io_errors_keep_their_source() {
+    smol::block_on(async {
+        // Nothing listens on the discard port of localhost
+        let err = Socks5Client::connect_with_domain("127.0.0.1:9", "example.com", 80, None)
+            .await
+            .unwrap_err();
+
+        let source = std::error::Error::source(&err).unwrap();
+        let io = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::ConnectionRefused);
+        assert!(io.raw_os_error().is_some());
+        assert_eq!(err.to_string(), io.to_string());
+    });
+}
+
+#[test]
+fn errors_compare_and_hash() {
+    use std::collections::HashSet;
+    use std::io;
+
+    assert_eq!(
+        Socks5Error::AuthenticationFailed,
+        Socks5Error::AuthenticationFailed
+    );
+    assert_ne!(
+        Socks5Error::AuthenticationFailed,
+        Socks5Error::Timeout(Phase::Total)
+    );
+    assert_ne!(
+        Socks5Error::Timeout(Phase::Reply),
+        Socks5Error::Timeout(Phase::Total)
+    );
+    assert_ne!(
+        Socks5Error::Reply(ReplyCode::HostUnreachable),
+        Socks5Error::Reply(ReplyCode::ConnectionRefused)
+    );
+
+    // I/O errors are equal when their kinds are
+    let refused = || Socks5Error::from(io::Error::new(io::ErrorKind::ConnectionRefused, "first"));
+    let other = Socks5Error::from(io::Error::new(io::ErrorKind::ConnectionRefused, "second"));
+    assert_eq!(refused(), other);
+
+    let seen: HashSet<_> = [refused(), other, Socks5Error::Timeout(Phase::Total)]
+        .into_iter()
+        .collect();
+    assert_eq!(seen.len(), 2);
+}
+
+#[test]
+fn send_command_returns_raw_reply() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let methods = common::read_greeting(&mut stream).await;
+            assert_eq!(methods, [0x00, 0x02]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            let mut auth = [0u8; 9];
+            stream.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x02me\x04pass");
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+            let request = common::read_request(&mut stream).await;
+            // A vendor reply code, followed by a bound address
+            let reply = [0x05, 0x80, 0x00, 0x01, 10, 0, 0, 1, 0x00, 0x50];
+            stream.write_all(&reply).await.unwrap();
+
+            // Then an unknown command on a negotiated stream, rejected
+            // without a bound address
+            let second = common::read_request(&mut stream).await;
+            stream.write_all(&[0x05, 0x07, 0x00, 0x01]).await.unwrap();
+            (request, second)
+        })
+        .await;
+
+        let mut stream = smol::net::TcpStream::connect(&proxy).await.unwrap();
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut options = CommandOptions::default();
+        options.credentials = Some(Credentials::new("me", "pass").unwrap());
+
+        let reply = Socks5Client::send_command(&mut stream, 0xf3, &target, &options)
+            .await
+            .unwrap();
+        assert_eq!(reply.code, 0x80);
+        assert!(!reply.is_success());
+        assert_eq!(
+            reply.bound_addr,
+            TargetAddr::Ip("10.0.0.1:80".parse().unwrap())
+        );
+
+        options.negotiated = true;
+        let reply = Socks5Client::send_command(&mut stream, 0x42, &target, &options)
+            .await
+            .unwrap();
+        assert_eq!(reply.reply_code(), ReplyCode::CommandNotSupported);
+
+        let (request, second) = server.await;
+        assert_eq!(request[1], 0xf3);
+        assert_eq!(
+            &request[3..],
+            &Socks5Client::build_connect_request(&target).unwrap()[3..]
+        );
+        assert_eq!(second[1], 0x42);
+    });
+}
@@ -0,0 +1,59 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::SocketAddr;
+
+use async_socks5::{Action, Command, Rule, Rules};
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn first_matching_rule_decides() {
+    let rules = Rules::new()
+        .allow(Rule::network("10.1.0.0".parse().unwrap(), 16))
+        .deny(Rule::network("10.0.0.0".parse().unwrap(), 8))
+        .deny(Rule::any().ports(25..=25));
+
+    let connect = Command::Connect;
+    assert!(rules.allows(connect, None, &addr("10.1.2.3:80")));
+    assert!(!rules.allows(connect, None, &addr("10.2.0.1:80")));
+    assert!(!rules.allows(connect, None, &addr("192.0.2.1:25")));
+    assert!(rules.allows(connect, None, &addr("192.0.2.1:26")));
+
+    // IPv4-mapped addresses are no way around IPv4 rules
+    assert!(!rules.allows(connect, None, &addr("[::ffff:10.2.0.1]:80")));
+}
+
+#[test]
+fn domain_suffixes_and_commands() {
+    let rules = Rules::new()
+        .allow(Rule::domain_suffix("example.com").command(Command::Connect))
+        .default_action(Action::Deny);
+
+    let ip = addr("192.0.2.1:443");
+    for domain in ["example.com", "www.Example.COM", "a.b.example.com."] {
+        assert!(rules.allows(Command::Connect, Some(domain), &ip));
+        assert!(!rules.allows(Command::Bind, Some(domain), &ip));
+    }
+    for domain in ["badexample.com", "example.com.evil", "com"] {
+        assert!(!rules.allows(Command::Connect, Some(domain), &ip));
+    }
+    assert!(!rules.allows(Command::Connect, None, &ip));
+}
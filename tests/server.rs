@@ -0,0 +1,679 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_socks5::{
+    ClientSummary, ConnectionId, InMemoryVerifier, ReplyCode, Rule, Rules, ServerObserver,
+    Socks5Client, Socks5Config, Socks5Error, Socks5Server, Socks5UdpSocket, TargetAddr,
+    UserPassVerifier, VerifyFuture,
+};
+use smol::future::FutureExt;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, UdpSocket};
+
+/// Start `server` on an ephemeral port, serving one client.
+async fn start(server: Socks5Server) -> (String, smol::Task<Result<(), Socks5Error>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let task = smol::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        server.serve(stream).await
+    });
+
+    (addr, task)
+}
+
+#[test]
+fn relays_to_target_after_auth() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let echo = smol::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let server = Socks5Server::new().auth(|user, pass| user == b"user" && pass == b"secret");
+        let (proxy, task) = start(server).await;
+
+        let mut stream = Socks5Client::connect(&proxy, &target_addr, Some(("user", "secret")))
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        echo.await;
+        drop(stream);
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn wrong_credentials_rejected() {
+    smol::block_on(async {
+        let server = Socks5Server::new().auth(|_, pass| pass == b"secret");
+        let (proxy, task) = start(server).await;
+
+        let target = "127.0.0.1:9".parse().unwrap();
+        let err = Socks5Client::connect(&proxy, &target, Some(("user", "wrong")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::AuthenticationFailed));
+        assert!(matches!(task.await, Err(Socks5Error::AuthenticationFailed)));
+    });
+}
+
+#[test]
+fn denied_targets_not_dialed() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+
+        let rules = Rules::new().deny(Rule::any().ports(target_addr.port()..=target_addr.port()));
+        let (proxy, task) = start(Socks5Server::new().rules(rules)).await;
+
+        let err = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::NotAllowed));
+        assert_eq!(task.await, Err(Socks5Error::Reply(ReplyCode::NotAllowed)));
+
+        // Nothing connected to the target in the meantime
+        let pending = smol::future::poll_once(target.accept()).await;
+        assert!(pending.is_none());
+    });
+}
+
+/// Verifier looking users up asynchronously, like a database would
+struct SlowLookup(InMemoryVerifier);
+
+impl UserPassVerifier for SlowLookup {
+    fn verify<'a>(&'a self, username: &'a [u8], password: &'a [u8]) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            smol::Timer::after(Duration::from_millis(10)).await;
+            self.0.verify(username, password).await
+        })
+    }
+}
+
+#[test]
+fn verifier_backs_authentication() {
+    smol::block_on(async {
+        let users = InMemoryVerifier::new()
+            .user("alice", "one")
+            .user("bob", "two");
+
+        for (creds, accepted) in [
+            (("alice", "one"), true),
+            (("bob", "two"), true),
+            (("alice", "two"), false),
+            (("carol", "one"), false),
+        ] {
+            let server = Socks5Server::new().verifier(SlowLookup(users.clone()));
+            let (proxy, task) = start(server).await;
+
+            // Unreachable target: authentication is all that matters here
+            let target = "127.0.0.1:9".parse().unwrap();
+            let err = Socks5Client::connect(&proxy, &target, Some(creds))
+                .await
+                .unwrap_err();
+            let _ = task.await;
+            assert_eq!(err == Socks5Error::AuthenticationFailed, !accepted);
+        }
+    });
+}
+
+#[test]
+fn dial_failure_reported_to_client() {
+    smol::block_on(async {
+        // Grab a free port and close it again, so nothing listens there
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = closed.local_addr().unwrap();
+        drop(closed);
+
+        let (proxy, _task) = start(Socks5Server::new()).await;
+        let err = Socks5Client::connect(&proxy, &target, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+    });
+}
+
+#[test]
+fn bound_server_serves_each_client() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let echo = smol::spawn(async move {
+            loop {
+                let (stream, _) = target.accept().await.unwrap();
+                smol::spawn(async move {
+                    let (reader, mut writer) = smol::io::split(stream);
+                    smol::io::copy(reader, &mut writer).await.unwrap();
+                })
+                .detach();
+            }
+        });
+
+        let listener = Socks5Server::new().bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let _accept = smol::spawn(async move {
+            while let Some(client) = listener.accept().await.unwrap() {
+                smol::spawn(client).detach();
+            }
+        });
+
+        // Both tunnels stay open at the same time
+        let mut first = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap();
+        let mut second = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap();
+
+        for (stream, msg) in [(&mut second, b"two!"), (&mut first, b"one!")] {
+            stream.write_all(msg).await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, msg);
+        }
+        drop(echo);
+    });
+}
+
+#[test]
+fn udp_associate_relays_for_the_client_only() {
+    smol::block_on(async {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+
+        let (proxy, task) = start(Socks5Server::new()).await;
+
+        // Announce the client's port, so the relay can tell it apart
+        let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = local.local_addr().unwrap();
+        drop(local);
+        let socket =
+            Socks5Client::udp_associate_from(&proxy, Some(local_addr), Some(local_addr), None)
+                .await
+                .unwrap();
+
+        // Datagrams from other ports are not relayed
+        let intruder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut datagram = Socks5UdpSocket::encode_header(&echo_addr.into()).unwrap();
+        datagram.extend_from_slice(b"evil");
+        intruder
+            .send_to(&datagram, socket.relay_addr())
+            .await
+            .unwrap();
+
+        socket.send_to(b"ping", &echo_addr.into()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        echo.send_to(b"pong", from).await.unwrap();
+
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, TargetAddr::Ip(echo_addr));
+
+        // Closing the control connection ends the association
+        drop(socket);
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn bind_accepts_the_expected_peer() {
+    smol::block_on(async {
+        let (proxy, task) = start(Socks5Server::new()).await;
+        let peer = TargetAddr::Ip("127.0.0.1:0".parse().unwrap());
+        let listener = Socks5Client::bind(&proxy, &peer, None).await.unwrap();
+
+        let bind_addr = listener.bind_addr().to_string();
+        let mut inbound = smol::net::TcpStream::connect(bind_addr).await.unwrap();
+        let (mut stream, from) = listener.accept().await.unwrap();
+        assert_eq!(from, TargetAddr::Ip(inbound.local_addr().unwrap()));
+
+        inbound.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        drop((stream, inbound));
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn bind_turns_away_other_peers() {
+    smol::block_on(async {
+        let (proxy, task) = start(Socks5Server::new()).await;
+        let peer = TargetAddr::Ip("127.0.0.2:0".parse().unwrap());
+        let listener = Socks5Client::bind(&proxy, &peer, None).await.unwrap();
+
+        // Connecting from 127.0.0.1 gets the connection closed
+        let bind_addr = listener.bind_addr().to_string();
+        let mut intruder = smol::net::TcpStream::connect(bind_addr).await.unwrap();
+        let mut buf = vec![];
+        intruder.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+
+        // The client giving up ends the BIND
+        drop(listener);
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn upstream_leg_reuses_the_client() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let echo = smol::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let back = Socks5Server::new().auth(|user, pass| user == b"front" && pass == b"secret");
+        let (back_addr, _back) = start(back).await;
+        let upstream = Socks5Config::new(&back_addr).credentials("front", "secret");
+        let (proxy, _front) = start(Socks5Server::new().upstream(upstream)).await;
+
+        let mut stream = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        echo.await;
+
+        // Failures of the upstream leg reach the client as they were
+        let (back_addr, _back) = start(Socks5Server::new()).await;
+        let upstream = Socks5Config::new(&back_addr);
+        let (proxy, front) = start(Socks5Server::new().upstream(upstream)).await;
+
+        let err = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+        assert_eq!(
+            front.await,
+            Err(Socks5Error::Reply(ReplyCode::ConnectionRefused))
+        );
+    });
+}
+
+#[test]
+fn upstream_bound_addr_reaches_the_client() {
+    smol::block_on(async {
+        let (back_addr, _back) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let mut reply = vec![0x05, 0x00, 0x00, 0x03, 9];
+            reply.extend_from_slice(b"relay.lan");
+            reply.extend_from_slice(&[0x1f, 0x90]);
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+        let upstream = Socks5Config::new(&back_addr);
+        let (proxy, _front) = start(Socks5Server::new().upstream(upstream)).await;
+
+        let target = TargetAddr::Ip("127.0.0.1:9".parse().unwrap());
+        let stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.bound_addr(),
+            &TargetAddr::Domain("relay.lan".into(), 8080)
+        );
+    });
+}
+
+#[test]
+fn shutdown_drains_then_aborts() {
+    smol::block_on(async {
+        // A target that keeps its connection open without ever answering
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let _idle = smol::spawn(async move {
+            let (_stream, _) = target.accept().await.unwrap();
+            smol::future::pending::<()>().await;
+        });
+
+        let server = Socks5Server::new();
+        let listener = server.clone().bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let accept = smol::spawn(async move {
+            let mut clients = vec![];
+            while let Some(client) = listener.accept().await.unwrap() {
+                clients.push(smol::spawn(client));
+            }
+            clients
+        });
+
+        let mut stream = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap();
+
+        server.shutdown();
+        let clients = accept.await;
+        assert_eq!(clients.len(), 1);
+        assert!(Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .is_err());
+
+        server.join(Some(Duration::from_millis(50))).await;
+        for client in clients {
+            let err = client.await.unwrap_err();
+            assert!(
+                matches!(err, Socks5Error::IoError(e) if e.kind() == std::io::ErrorKind::ConnectionAborted)
+            );
+        }
+
+        let mut buf = vec![];
+        let _ = stream.read_to_end(&mut buf).await;
+        assert!(buf.is_empty());
+    });
+}
+
+#[test]
+fn resolver_rewrites_or_refuses_targets() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let echo = smol::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            stream.write_all(b"hi").await.unwrap();
+        });
+
+        let resolver = move |target: TargetAddr| match target {
+            TargetAddr::Domain(host, _) if host == "service.internal" => Ok(target_addr.into()),
+            TargetAddr::Domain(..) => Err(ReplyCode::HostUnreachable),
+            target => Ok(target),
+        };
+        let server = Socks5Server::new().resolver(resolver);
+
+        let (proxy, _task) = start(server.clone()).await;
+        let mut stream = Socks5Client::connect_with_domain(&proxy, "service.internal", 1, None)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+        echo.await;
+
+        let (proxy, task) = start(server).await;
+        let err = Socks5Client::connect_with_domain(&proxy, "elsewhere.example", 80, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::HostUnreachable));
+        assert_eq!(
+            task.await,
+            Err(Socks5Error::Reply(ReplyCode::HostUnreachable))
+        );
+    });
+}
+
+/// Observer writing events down as strings
+#[derive(Default)]
+struct Audit(Mutex<Vec<String>>);
+
+impl ServerObserver for Audit {
+    fn authenticated(&self, _: ConnectionId, username: &[u8], success: bool) {
+        let user = String::from_utf8_lossy(username);
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("auth {} {}", user, success));
+    }
+
+    fn connected(&self, _: ConnectionId, target: &TargetAddr) {
+        self.0.lock().unwrap().push(format!("connected {}", target));
+    }
+
+    fn relayed(&self, _: ConnectionId, sent: u64, received: u64) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("relayed {} {}", sent, received));
+    }
+
+    fn closed(&self, _: ConnectionId, summary: &ClientSummary) {
+        assert_eq!(summary.username.as_deref(), Some(&b"user"[..]));
+        let failed = summary.error.is_some();
+        let line = format!(
+            "closed {} {} {}",
+            summary.bytes_sent, summary.bytes_received, failed
+        );
+        self.0.lock().unwrap().push(line);
+    }
+}
+
+#[test]
+fn observer_sees_each_stage() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let echo = smol::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(b"pong!").await.unwrap();
+        });
+
+        let audit = Arc::new(Audit::default());
+        let server = Socks5Server::new()
+            .auth(|_, pass| pass == b"secret")
+            .observer(audit.clone());
+
+        let (proxy, task) = start(server.clone()).await;
+        let mut stream = Socks5Client::connect(&proxy, &target_addr, Some(("user", "secret")))
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        echo.await;
+        drop(stream);
+        task.await.unwrap();
+
+        let (proxy, task) = start(server).await;
+        let creds = Some(("user", "wrong"));
+        assert!(Socks5Client::connect(&proxy, &target_addr, creds)
+            .await
+            .is_err());
+        assert!(task.await.is_err());
+
+        assert_eq!(
+            *audit.0.lock().unwrap(),
+            [
+                "auth user true".to_string(),
+                format!("connected {}", target_addr),
+                "relayed 4 5".into(),
+                "closed 4 5 false".into(),
+                "auth user false".into(),
+                "closed 0 0 true".into(),
+            ]
+        );
+    });
+}
+
+#[test]
+fn failed_auth_rate_limited_per_source() {
+    smol::block_on(async {
+        let window = Duration::from_millis(300);
+        let server = Socks5Server::new()
+            .auth(|_, pass| pass == b"secret")
+            .auth_rate_limit(2, window);
+        let target = "127.0.0.1:9".parse().unwrap();
+
+        for _ in 0..2 {
+            let (proxy, _task) = start(server.clone()).await;
+            let err = Socks5Client::connect(&proxy, &target, Some(("user", "wrong")))
+                .await
+                .unwrap_err();
+            assert_eq!(err, Socks5Error::AuthenticationFailed);
+        }
+
+        // Even the right password is refused now
+        let (proxy, task) = start(server.clone()).await;
+        let err = Socks5Client::connect(&proxy, &target, Some(("user", "secret")))
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::NoAcceptableAuthMethods);
+        assert_eq!(task.await, Err(Socks5Error::AuthenticationFailed));
+
+        // Until the failures leave the window
+        smol::Timer::after(window).await;
+        let (proxy, _task) = start(server).await;
+        let err = Socks5Client::connect(&proxy, &target, Some(("user", "secret")))
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+    });
+}
+
+#[test]
+fn outbound_addr_used_for_targets() {
+    smol::block_on(async {
+        let server = Socks5Server::new().outbound_addr("127.0.0.1".parse().unwrap());
+
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let (proxy, task) = start(server.clone()).await;
+        let accept = smol::spawn(async move { target.accept().await.unwrap().1 });
+
+        let stream = Socks5Client::connect_stream(&proxy, &target_addr.into(), None)
+            .await
+            .unwrap();
+        let from = accept.await;
+        assert_eq!(*stream.bound_addr(), TargetAddr::Ip(from));
+        drop(stream);
+        task.await.unwrap();
+
+        // Datagrams leave from a socket of their own, answers still arrive
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        let (proxy, task) = start(server).await;
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+
+        socket.send_to(b"ping", &echo_addr.into()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_ne!(from, socket.relay_addr());
+        echo.send_to(b"pong", from).await.unwrap();
+
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, TargetAddr::Ip(echo_addr));
+
+        drop(socket);
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn udp_relay_drops_datagrams_from_strangers() {
+    smol::block_on(async {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+
+        let (proxy, task) = start(Socks5Server::new()).await;
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+
+        socket.send_to(b"ping", &echo_addr.into()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        // Someone the client never sent to writes to the relay port
+        let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        stranger
+            .send_to(b"spoofed", socket.relay_addr())
+            .await
+            .unwrap();
+        echo.send_to(b"pong", from).await.unwrap();
+
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, TargetAddr::Ip(echo_addr));
+
+        let nothing = async {
+            socket.recv_from(&mut buf).await.unwrap();
+            false
+        }
+        .or(async {
+            smol::Timer::after(Duration::from_millis(200)).await;
+            true
+        });
+        assert!(nothing.await);
+
+        drop(socket);
+        task.await.unwrap();
+    });
+}
+
+#[test]
+fn udp_relay_forgets_least_recently_used_targets() {
+    smol::block_on(async {
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let first_addr = first.local_addr().unwrap();
+        let second_addr = second.local_addr().unwrap();
+
+        let (proxy, task) = start(Socks5Server::new().udp_target_limit(1)).await;
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        socket.send_to(b"one", &first_addr.into()).await.unwrap();
+        let (_, relay) = first.recv_from(&mut buf).await.unwrap();
+        socket.send_to(b"two", &second_addr.into()).await.unwrap();
+        second.recv_from(&mut buf).await.unwrap();
+
+        // The first target was forgotten, so its answer is dropped
+        first.send_to(b"late", relay).await.unwrap();
+        second.send_to(b"pong", relay).await.unwrap();
+
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, TargetAddr::Ip(second_addr));
+
+        drop(socket);
+        task.await.unwrap();
+    });
+}
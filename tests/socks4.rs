@@ -0,0 +1,109 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use async_socks5::{Socks4Client, Socks5Error};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpStream;
+
+/// Read a SOCKS4 request up to the user id, plus the domain if the
+/// address is a SOCKS4a sentinel.
+async fn read_socks4_request(stream: &mut TcpStream) -> Vec<u8> {
+    let mut request = vec![0u8; 8];
+    stream.read_exact(&mut request).await.unwrap();
+
+    let fields = match request[4..7] == [0, 0, 0] && request[7] != 0 {
+        true => 2,
+        false => 1,
+    };
+
+    for _ in 0..fields {
+        loop {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            request.push(byte[0]);
+            if byte[0] == 0x00 {
+                break;
+            }
+        }
+    }
+
+    request
+}
+
+fn serve(status: u8) -> impl FnOnce(TcpStream) -> smol::future::Boxed<Vec<u8>> {
+    move |mut stream| {
+        Box::pin(async move {
+            let request = read_socks4_request(&mut stream).await;
+            stream
+                .write_all(&[0x00, status, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            request
+        })
+    }
+}
+
+#[test]
+fn socks4_connect() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(serve(0x5a)).await;
+
+        let target = "192.0.2.1:80".parse().unwrap();
+        Socks4Client::connect(&proxy, &target, Some("alice"))
+            .await
+            .unwrap();
+
+        let mut expected = vec![0x04, 0x01, 0x00, 0x50, 192, 0, 2, 1];
+        expected.extend_from_slice(b"alice\0");
+        assert_eq!(server.await, expected);
+    });
+}
+
+#[test]
+fn socks4a_connect_with_domain() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(serve(0x5a)).await;
+
+        Socks4Client::connect_with_domain(&proxy, "example.com", 443, None)
+            .await
+            .unwrap();
+
+        let mut expected = vec![0x04, 0x01, 0x01, 0xbb, 0, 0, 0, 1, 0x00];
+        expected.extend_from_slice(b"example.com\0");
+        assert_eq!(server.await, expected);
+    });
+}
+
+#[test]
+fn socks4_rejections() {
+    smol::block_on(async {
+        for status in [0x5b, 0x5d] {
+            let (proxy, _server) = common::serve_once(serve(status)).await;
+
+            let target = "192.0.2.1:80".parse().unwrap();
+            let err = Socks4Client::connect(&proxy, &target, None).await;
+
+            match status {
+                0x5b => assert!(matches!(err, Err(Socks5Error::ConnectionFailed))),
+                _ => assert!(matches!(err, Err(Socks5Error::AuthenticationFailed))),
+            }
+        }
+    });
+}
@@ -0,0 +1,100 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "socks6")]
+
+mod common;
+
+use async_socks5::{Credentials, ReplyCode, Socks5Error, Socks6Client, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn connect_with_auth_and_initial_data() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let mut request = vec![0u8; 52];
+            stream.read_exact(&mut request).await.unwrap();
+
+            // Authentication succeeded, then the operation reply with a
+            // padded domain and an option to skip
+            stream.write_all(&[0x06, 0x00, 0x00, 0x00]).await.unwrap();
+            let mut reply = vec![0x06, 0x00, 0x00, 0x04, 0x1f, 0x90, 0x00, 0x02];
+            reply.extend_from_slice(&[5, b'b', b'o', b'u', b'n', b'd', 0, 0]);
+            reply.extend_from_slice(&[0x00, 0x09, 0x00, 0x04]);
+            stream.write_all(&reply).await.unwrap();
+            stream.write_all(b"pong").await.unwrap();
+            (request, stream)
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 443);
+        let credentials = Credentials::new("user", "secret").unwrap();
+        let (mut stream, bound) =
+            Socks6Client::connect(&proxy, &target, Some(&credentials), b"ping")
+                .await
+                .unwrap();
+        assert_eq!(bound, TargetAddr::Domain("bound".into(), 8080));
+        let mut pong = [0u8; 4];
+        stream.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+
+        let (request, _stream) = server.await;
+        let mut expected = vec![0x06, 0x01, 0x00, 0x1c, 0x01, 0xbb, 0x00, 0x02, 11];
+        expected.extend_from_slice(b"example.com");
+        // Method advertisement: 4 bytes of initial data, username/password
+        expected.extend_from_slice(&[0x00, 0x02, 0x00, 0x08, 0x00, 0x04, 0x02, 0x00]);
+        // Authentication data
+        expected.extend_from_slice(&[0x00, 0x04, 0x00, 0x14, 0x02, 0x01, 4]);
+        expected.extend_from_slice(b"user");
+        expected.push(6);
+        expected.extend_from_slice(b"secret");
+        expected.extend_from_slice(&[0x00, 0x00]);
+        expected.extend_from_slice(b"ping");
+        assert_eq!(request, expected);
+    });
+}
+
+#[test]
+fn rejected_authentication_and_reply_codes() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            let mut request = vec![0u8; 20];
+            stream.read_exact(&mut request).await.unwrap();
+            stream.write_all(&[0x06, 0x01, 0x00, 0x00]).await.unwrap();
+        })
+        .await;
+        let target = TargetAddr::Ip("192.0.2.1:80".parse().unwrap());
+        let err = Socks6Client::connect(&proxy, &target, None, b"")
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::AuthenticationFailed);
+
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            let mut request = vec![0u8; 20];
+            stream.read_exact(&mut request).await.unwrap();
+            stream.write_all(&[0x06, 0x00, 0x00, 0x00]).await.unwrap();
+            let reply = [0x06, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0, 0, 0, 0];
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+        let err = Socks6Client::connect(&proxy, &target, None, b"")
+            .await
+            .unwrap_err();
+        assert_eq!(err, Socks5Error::Reply(ReplyCode::ConnectionRefused));
+    });
+}
@@ -0,0 +1,293 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_socks5::{IdleTimeout, Socks5Client, TargetAddr};
+use smol::future::FutureExt;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpStream;
+use smol::Timer;
+
+#[test]
+fn closed_waits_for_unread_data() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            stream.write_all(b"bye").await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        assert_eq!(stream.info().handshake_bytes_read, 2 + 10);
+        assert_eq!(stream.auth_method(), 0x00);
+        assert_eq!(*stream.target_addr(), target);
+        assert_eq!(stream.proxy_addr().to_string(), proxy);
+        assert_eq!(
+            *stream.bound_addr(),
+            TargetAddr::Ip("127.0.0.1:8080".parse().unwrap())
+        );
+
+        let pending = async {
+            stream.closed().await.unwrap();
+            false
+        }
+        .or(async {
+            Timer::after(Duration::from_millis(200)).await;
+            true
+        })
+        .await;
+        assert!(pending);
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"bye");
+        stream.closed().await.unwrap();
+    });
+}
+
+#[test]
+fn shutdown_write_keeps_reading() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            // Only answer once the client is done sending
+            let mut request = vec![];
+            stream.read_to_end(&mut request).await.unwrap();
+            stream.write_all(&request).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await.unwrap();
+        stream.shutdown_write().await.unwrap();
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(stream.bytes_sent(), 18);
+        assert_eq!(stream.bytes_received(), 18);
+    });
+}
+
+#[test]
+fn split_halves_in_separate_tasks() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            let mut request = vec![];
+            stream.read_to_end(&mut request).await.unwrap();
+            stream.write_all(&request).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = stream.split();
+
+        let writing = smol::spawn(async move {
+            writer.write_all(b"ping").await.unwrap();
+            writer.close().await.unwrap();
+        });
+
+        let mut response = vec![];
+        reader.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"ping");
+        writing.await;
+    });
+}
+
+#[test]
+fn tunnel_bytes_after_reply_are_kept() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+
+            // Reply and banner in a single segment, like a server that
+            // speaks first
+            let mut reply = vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90];
+            reply.extend_from_slice(b"220 smtp ready\r\n");
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("mail.example.com".into(), 25);
+        let mut stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+
+        let mut banner = vec![];
+        stream.read_to_end(&mut banner).await.unwrap();
+        assert_eq!(banner, b"220 smtp ready\r\n");
+    });
+}
+
+#[test]
+fn handshake_over_existing_stream() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            stream.write_all(b"data").await.unwrap();
+        })
+        .await;
+
+        let stream = TcpStream::connect(&proxy).await.unwrap();
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::handshake_over(stream, &target, None)
+            .await
+            .unwrap();
+
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"data");
+    });
+}
+
+#[test]
+fn idle_timeout_aborts_quiet_connection() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+
+            // Keep the connection busy for a while, then go quiet
+            for _ in 0..3 {
+                Timer::after(Duration::from_millis(60)).await;
+                stream.write_all(b"x").await.unwrap();
+            }
+            let mut rest = vec![];
+            stream.read_to_end(&mut rest).await.unwrap();
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap()
+            .idle_timeout(Duration::from_millis(150));
+
+        let mut buf = [0u8; 3];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(err.get_ref().unwrap().is::<IdleTimeout>());
+        let err = stream.write(b"late").await.unwrap_err();
+        assert!(err.get_ref().unwrap().is::<IdleTimeout>());
+
+        // The proxy sees the connection closed
+        server.await;
+    });
+}
+
+#[test]
+fn connect_info_reports_phase_timings() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            // The target is slow to answer
+            Timer::after(Duration::from_millis(100)).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        let timings = stream.info().timings;
+        assert!(timings.request >= Duration::from_millis(100));
+        assert!(timings.method_selection < Duration::from_millis(100));
+        assert!(timings.authentication < Duration::from_millis(100));
+        assert_eq!(
+            timings.total(),
+            timings.connect + timings.method_selection + timings.authentication + timings.request
+        );
+    });
+}
+
+#[test]
+fn connect_info_reports_the_dialed_proxy_ip() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+        let dialed: SocketAddr = proxy.parse().unwrap();
+
+        // The name may resolve to ::1 too, where nothing listens
+        let proxy = format!("localhost:{}", dialed.port());
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let stream = Socks5Client::connect_stream(&proxy, &target, None)
+            .await
+            .unwrap();
+        assert_eq!(stream.info().proxy_connected_ip, dialed.ip());
+        assert_eq!(stream.info().proxy_addr, dialed);
+    });
+}
+
+#[test]
+fn each_connection_gets_its_own_id() {
+    smol::block_on(async {
+        let mut ids = vec![];
+        for _ in 0..2 {
+            let (proxy, _server) = common::serve_once(|mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                common::reply_ok(&mut stream).await;
+            })
+            .await;
+
+            let target = TargetAddr::Domain("example.com".into(), 80);
+            let stream = Socks5Client::connect_stream(&proxy, &target, None)
+                .await
+                .unwrap();
+            ids.push(stream.info().connection_id);
+        }
+
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[0].to_string(), ids[1].to_string());
+    });
+}
@@ -0,0 +1,80 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::time::{Duration, Instant};
+
+use async_socks5::{Socks5Client, Socks5Server, Throttled};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpListener;
+
+#[test]
+fn throttled_stream_limits_each_direction() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = smol::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let mut server = Throttled::new(server).write_limit(1000);
+        let mut client = Throttled::new(client).read_limit(1_000_000);
+
+        let started = Instant::now();
+        let writer = smol::spawn(async move {
+            // A second's worth bursts, the rest trickles in
+            server.write_all(&[7u8; 1500]).await.unwrap();
+        });
+        let mut buf = vec![0u8; 1500];
+        client.read_exact(&mut buf).await.unwrap();
+        writer.await;
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+        assert!(buf.iter().all(|&b| b == 7));
+    });
+}
+
+#[test]
+fn server_caps_download() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        smol::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            stream.write_all(&[1u8; 3000]).await.unwrap();
+        })
+        .detach();
+
+        let server = Socks5Server::new().download_limit(2000);
+        let listener = server.bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        smol::spawn(async move {
+            let client = listener.accept().await.unwrap().unwrap();
+            client.await.unwrap();
+        })
+        .detach();
+
+        let mut stream = Socks5Client::connect(&proxy, &target_addr, None)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let mut buf = vec![0u8; 3000];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    });
+}
@@ -0,0 +1,220 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::future::pending;
+use std::time::Duration;
+
+use async_socks5::{
+    Phase, ReplyCode, Sleep, Socks5Client, Socks5Config, Socks5Error, TargetAddr, Timer,
+};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Timer whose deadlines have always already passed
+#[derive(Debug)]
+struct ExpiredTimer;
+
+impl Timer for ExpiredTimer {
+    fn sleep(&self, _: Duration) -> Sleep {
+        Box::pin(async {})
+    }
+}
+
+/// Timer whose deadlines never pass
+#[derive(Debug)]
+struct FrozenTimer;
+
+impl Timer for FrozenTimer {
+    fn sleep(&self, _: Duration) -> Sleep {
+        Box::pin(pending())
+    }
+}
+
+#[test]
+fn stalled_proxy_times_out() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            pending::<()>().await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .force_remote_dns(true)
+            .timeout(Duration::from_secs(3600))
+            .timer(ExpiredTimer);
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let err = config.connect(&target).await.unwrap_err();
+        assert_eq!(err, Socks5Error::Timeout(Phase::Total));
+    });
+}
+
+#[test]
+fn deadline_not_reached_connects() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy)
+            .force_remote_dns(true)
+            .timeout(Duration::ZERO)
+            .timer(FrozenTimer);
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        config.connect(&target).await.unwrap();
+        server.await;
+    });
+}
+
+#[test]
+fn banner_read_or_timeout() {
+    smol::block_on(async {
+        for banner in [&b"220 ftp ready\r\n"[..], b""] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                common::reply_ok(&mut stream).await;
+                stream.write_all(banner).await.unwrap();
+                smol::Timer::after(Duration::from_secs(1)).await;
+            })
+            .await;
+
+            let target = TargetAddr::Domain("ftp.example.com".into(), 21);
+            let result = Socks5Client::connect_expect_banner(
+                &proxy,
+                &target,
+                None,
+                Duration::from_millis(100),
+            )
+            .await;
+
+            match result {
+                Ok((_, read)) => assert_eq!(read, banner),
+                Err(e) => assert!(banner.is_empty() && e == Socks5Error::Timeout(Phase::Target)),
+            }
+        }
+    });
+}
+
+#[test]
+fn reply_stages_time_out_separately() {
+    smol::block_on(async {
+        // An error REP is reported without waiting for the bound address,
+        // while a stalled bound address runs into the second timeout.
+        for rep in [0x05, 0x00] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                stream.write_all(&[0x05, rep, 0x00, 0x01]).await.unwrap();
+                smol::Timer::after(Duration::from_secs(1)).await;
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .reply_timeouts(Duration::from_millis(500), Duration::from_millis(50));
+            let err = config.connect_with_domain("example.com", 80).await;
+
+            match rep {
+                0x00 => assert_eq!(err.unwrap_err(), Socks5Error::Timeout(Phase::Reply)),
+                _ => assert!(matches!(
+                    err,
+                    Err(Socks5Error::Reply(ReplyCode::ConnectionRefused))
+                )),
+            }
+        }
+    });
+}
+
+#[test]
+fn connect_with_timeout_covers_auth() {
+    smol::block_on(async {
+        // Stall after the method selection, in the middle of authenticating
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            pending::<()>().await;
+        })
+        .await;
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let err = Socks5Client::connect_with_timeout(
+            &proxy,
+            &target,
+            Some(("user", "pass")),
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, Socks5Error::Timeout(Phase::Total));
+    });
+}
+
+#[test]
+fn read_timeout_catches_stalled_reply() {
+    smol::block_on(async {
+        // Half a method selection, then nothing
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::read_greeting(&mut stream).await;
+            stream.write_all(&[0x05]).await.unwrap();
+            pending::<()>().await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).read_timeout(Duration::from_millis(50));
+        let err = config.connect_with_domain("example.com", 80).await;
+        assert_eq!(
+            err.unwrap_err(),
+            Socks5Error::Timeout(Phase::MethodSelection)
+        );
+    });
+}
+
+#[test]
+fn phase_timeouts_name_the_phase() {
+    smol::block_on(async {
+        // Select username/password, then stall, first in the middle of
+        // authenticating and then before the reply
+        for phase in [Phase::Authentication, Phase::Reply] {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::read_greeting(&mut stream).await;
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+                if phase == Phase::Reply {
+                    let mut auth = vec![0u8; 1 + 1 + 4 + 1 + 4];
+                    stream.read_exact(&mut auth).await.unwrap();
+                    stream.write_all(&[0x01, 0x00]).await.unwrap();
+                }
+                pending::<()>().await;
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy)
+                .credentials("user", "pass")
+                .phase_timeout(Phase::MethodSelection, Duration::from_secs(3600))
+                .phase_timeout(phase, Duration::from_millis(50));
+            let err = config.connect_with_domain("example.com", 80).await;
+            assert_eq!(err.unwrap_err(), Socks5Error::Timeout(phase));
+        }
+    });
+}
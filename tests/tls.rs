@@ -0,0 +1,163 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "tls")]
+
+use std::sync::Arc;
+
+use async_socks5::rustls::crypto::ring;
+use async_socks5::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use async_socks5::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use async_socks5::{Socks5Client, TargetAddr};
+use futures_rustls::TlsAcceptor;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpListener;
+
+/// Self-signed certificate for `proxy.test`, and its key
+const CERT: &[u8] = include_bytes!("data/proxy.test.der");
+const KEY: &[u8] = include_bytes!("data/proxy.test.key.der");
+
+fn server_config() -> Arc<ServerConfig> {
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(KEY.to_vec()));
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![CertificateDer::from(CERT.to_vec())], key)
+        .unwrap();
+    Arc::new(config)
+}
+
+/// Client configuration trusting only the test certificate
+fn client_config() -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from(CERT.to_vec())).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Accept one TLS connection as a no-auth proxy for `example.com:443`
+/// and echo four bytes.
+async fn serve_tls_proxy(listener: TcpListener) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = TlsAcceptor::from(server_config())
+        .accept(stream)
+        .await
+        .unwrap();
+
+    let mut greeting = [0u8; 3];
+    stream.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(greeting, [0x05, 0x01, 0x00]);
+    stream.write_all(&[0x05, 0x00]).await.unwrap();
+    stream.flush().await.unwrap();
+
+    let mut request = [0u8; 5 + 11 + 2];
+    stream.read_exact(&mut request).await.unwrap();
+    assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, 11]);
+    assert_eq!(&request[5..16], b"example.com");
+    let reply = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    stream.write_all(&reply).await.unwrap();
+    stream.flush().await.unwrap();
+
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    stream.write_all(&buf).await.unwrap();
+    stream.flush().await.unwrap();
+}
+
+#[test]
+fn connect_over_tls_to_the_proxy() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = smol::spawn(serve_tls_proxy(listener));
+
+        let target = TargetAddr::Domain("example.com".into(), 443);
+        let mut stream = Socks5Client::connect_proxy_tls_with(
+            client_config(),
+            "127.0.0.1",
+            port,
+            "proxy.test",
+            &target,
+            None,
+        )
+        .await
+        .unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.await;
+    });
+}
+
+#[test]
+fn connect_stream_over_tls_to_the_proxy() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let server = smol::spawn(serve_tls_proxy(listener));
+
+        let target = TargetAddr::Domain("example.com".into(), 443);
+        let mut stream = Socks5Client::connect_proxy_tls_stream(
+            client_config(),
+            "127.0.0.1",
+            proxy_addr.port(),
+            "proxy.test",
+            &target,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.proxy_addr(), proxy_addr);
+        assert_eq!(stream.target_addr(), &target);
+
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!((stream.bytes_sent(), stream.bytes_received()), (4, 4));
+        server.await;
+    });
+}
+
+#[test]
+fn untrusted_proxy_certificate_rejected() {
+    smol::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let _server = smol::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = TlsAcceptor::from(server_config()).accept(stream).await;
+        });
+
+        // The default configuration only trusts the webpki roots
+        let target = TargetAddr::Domain("example.com".into(), 443);
+        let result =
+            Socks5Client::connect_proxy_tls("127.0.0.1", port, "proxy.test", &target, None).await;
+        assert!(result.is_err());
+    });
+}
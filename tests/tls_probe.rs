@@ -0,0 +1,113 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "tls-probe")]
+
+mod common;
+
+use std::time::Duration;
+
+use async_socks5::{Phase, Socks5Client, Socks5Error};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::TcpStream;
+
+/// Probe a mock proxy that hands the tunnel to `target` after the reply.
+async fn probe<F, Fut>(target: F) -> Result<bool, Socks5Error>
+where
+    F: FnOnce(TcpStream) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let (proxy, _server) = common::serve_once(|mut stream| async move {
+        common::accept_no_auth(&mut stream).await;
+        common::read_request(&mut stream).await;
+        common::reply_ok(&mut stream).await;
+        target(stream).await;
+    })
+    .await;
+
+    let timeout = Duration::from_millis(200);
+    Socks5Client::tls_alive(&proxy, "proxy.test", 443, None, timeout).await
+}
+
+/// Read the whole ClientHello record, so closing doesn't reset the
+/// connection before the client saw our answer.
+async fn read_hello(stream: &mut TcpStream) {
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header).await.unwrap();
+    let mut hello = vec![0u8; u16::from_be_bytes([header[3], header[4]]) as usize];
+    stream.read_exact(&mut hello).await.unwrap();
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_target_alive() {
+    use std::sync::Arc;
+
+    use async_socks5::rustls::crypto::ring;
+    use async_socks5::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use async_socks5::rustls::ServerConfig;
+    use futures_rustls::TlsAcceptor;
+
+    let cert = CertificateDer::from(include_bytes!("data/proxy.test.der").to_vec());
+    let key = include_bytes!("data/proxy.test.key.der").to_vec();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key));
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    smol::block_on(async {
+        let alive = probe(|stream| async move {
+            // The handshake is abandoned after the ServerHello
+            let _ = acceptor.accept(stream).await;
+        })
+        .await;
+        assert!(alive.unwrap());
+    });
+}
+
+#[test]
+fn plain_tcp_target_not_alive() {
+    smol::block_on(async {
+        // Something answering in plain text
+        let alive = probe(|mut stream| async move {
+            read_hello(&mut stream).await;
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+        })
+        .await;
+        assert!(!alive.unwrap());
+
+        // Something closing the connection
+        let alive = probe(|mut stream| async move {
+            read_hello(&mut stream).await;
+        })
+        .await;
+        assert!(!alive.unwrap());
+
+        // Something never answering at all
+        let alive = probe(|stream| async move {
+            smol::future::pending::<()>().await;
+            drop(stream);
+        })
+        .await;
+        assert_eq!(alive.unwrap_err(), Socks5Error::Timeout(Phase::Target));
+    });
+}
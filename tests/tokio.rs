@@ -0,0 +1,61 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "tokio")]
+
+use async_socks5::TargetAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn tokio_connect_with_domain() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap().to_string();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0u8; 3];
+        stream.read_exact(&mut greeting).await.unwrap();
+        stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut request = [0u8; 5 + 11 + 2];
+        stream.read_exact(&mut request).await.unwrap();
+        let reply = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90];
+        stream.write_all(&reply).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        request
+    });
+
+    let mut stream = async_socks5::tokio::connect_with_domain(&proxy, "example.com", 80, None)
+        .await
+        .unwrap();
+
+    let mut data = vec![];
+    stream.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, b"hello");
+
+    let request = server.await.unwrap();
+    assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, 11]);
+
+    let target = TargetAddr::Domain("example.com".into(), 80);
+    assert_eq!(
+        request[..],
+        async_socks5::Socks5Client::build_connect_request(&target).unwrap()[..]
+    );
+}
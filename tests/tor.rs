@@ -0,0 +1,186 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use async_socks5::{IsolationToken, ReplyCode, Socks5Client, Socks5Config, Socks5Error, TorError};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn resolve_ipv4_and_ipv6() {
+    smol::block_on(async {
+        let v6: Ipv6Addr = "2001:db8::53".parse().unwrap();
+        let mut v6_reply = vec![0x05, 0x00, 0x00, 0x04];
+        v6_reply.extend_from_slice(&v6.octets());
+        v6_reply.extend_from_slice(&[0, 0]);
+
+        let replies = [
+            (
+                vec![0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0, 0],
+                IpAddr::from([93, 184, 216, 34]),
+            ),
+            (v6_reply, IpAddr::V6(v6)),
+        ];
+
+        for (reply, expected) in replies {
+            let (proxy, server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                let request = common::read_request(&mut stream).await;
+                stream.write_all(&reply).await.unwrap();
+                request
+            })
+            .await;
+
+            let ip = Socks5Client::resolve(&proxy, "example.com", None)
+                .await
+                .unwrap();
+            assert_eq!(ip, expected);
+
+            let request = server.await;
+            assert_eq!(request[..5], [0x05, 0xf0, 0x00, 0x03, 11]);
+        }
+    });
+}
+
+#[test]
+fn resolve_ptr_hostname() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+
+            let mut reply = vec![0x05, 0x00, 0x00, 0x03, 11];
+            reply.extend_from_slice(b"example.com");
+            reply.extend_from_slice(&[0, 0]);
+            stream.write_all(&reply).await.unwrap();
+            request
+        })
+        .await;
+
+        let ip = IpAddr::from([93, 184, 216, 34]);
+        let host = Socks5Client::resolve_ptr(&proxy, ip, None).await.unwrap();
+        assert_eq!(host, "example.com");
+
+        let request = server.await;
+        assert_eq!(request, [0x05, 0xf1, 0x00, 0x01, 93, 184, 216, 34, 0, 0]);
+    });
+}
+
+#[test]
+fn connect_to_onion_validates_address() {
+    smol::block_on(async {
+        let service = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+        let onion = format!("www.{}.onion", service);
+
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            let request = common::read_request(&mut stream).await;
+            common::reply_ok(&mut stream).await;
+            request
+        })
+        .await;
+
+        Socks5Client::connect_to_onion(&proxy, &onion, 80, None)
+            .await
+            .unwrap();
+        assert_eq!(&server.await[5..5 + onion.len()], onion.as_bytes());
+
+        let invalid = [
+            "example.com".to_string(),
+            "short.onion".to_string(),
+            format!("{}1.onion", &service[1..]),
+            format!(".{}.onion", service),
+        ];
+        for onion in invalid {
+            let err = Socks5Client::connect_to_onion("127.0.0.1:9", &onion, 80, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Socks5Error::InvalidInput(_)), "{}", onion);
+        }
+    });
+}
+
+#[test]
+fn extended_errors_decoded_when_enabled() {
+    smol::block_on(async {
+        let cases = [
+            (true, 0xf3, Socks5Error::Tor(TorError::RendezvousFailed)),
+            (true, 0xf8, Socks5Error::Reply(ReplyCode::Other(0xf8))),
+            (false, 0xf3, Socks5Error::Reply(ReplyCode::Other(0xf3))),
+        ];
+
+        for (decode, rep, expected) in cases {
+            let (proxy, _server) = common::serve_once(move |mut stream| async move {
+                common::accept_no_auth(&mut stream).await;
+                common::read_request(&mut stream).await;
+                let reply = [0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+                stream.write_all(&reply).await.unwrap();
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy).tor_errors(decode);
+            let err = config.connect_with_domain("example.onion", 80).await;
+            assert_eq!(err.unwrap_err(), expected);
+        }
+    });
+}
+
+#[test]
+fn isolation_tokens_sent_as_credentials() {
+    smol::block_on(async {
+        let mut usernames = vec![];
+        let tokens = [
+            IsolationToken::new(),
+            IsolationToken::new(),
+            IsolationToken::from_key("mail"),
+            IsolationToken::from_key("mail"),
+        ];
+
+        for token in &tokens {
+            let (proxy, server) = common::serve_once(|mut stream| async move {
+                assert_eq!(common::read_greeting(&mut stream).await, [0x00, 0x02]);
+                stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+                let mut header = [0u8; 2];
+                stream.read_exact(&mut header).await.unwrap();
+                let mut username = vec![0u8; header[1] as usize];
+                stream.read_exact(&mut username).await.unwrap();
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await.unwrap();
+                let mut password = vec![0u8; len[0] as usize];
+                stream.read_exact(&mut password).await.unwrap();
+                stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+                common::read_request(&mut stream).await;
+                common::reply_ok(&mut stream).await;
+                String::from_utf8(username).unwrap()
+            })
+            .await;
+
+            let config = Socks5Config::new(&proxy).isolation(token);
+            config.connect_with_domain("example.com", 80).await.unwrap();
+            usernames.push(server.await);
+        }
+
+        assert_ne!(usernames[0], usernames[1]);
+        assert_eq!(usernames[2], usernames[3]);
+        assert_eq!(usernames[2], tokens[2].credentials().0);
+    });
+}
@@ -0,0 +1,111 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "tunnel")]
+
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use async_socks5::Socks5Server;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, TcpStream};
+use smol::Timer;
+
+/// Kills the tunnel when the test ends, passing or not.
+struct Tunnel(Child);
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn forwards_connections_through_proxy() {
+    smol::block_on(async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        smol::spawn(async move {
+            loop {
+                let (mut stream, _) = target.accept().await.unwrap();
+                smol::spawn(async move {
+                    let mut buf = [0u8; 4];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    stream.write_all(&buf).await.unwrap();
+                })
+                .detach();
+            }
+        })
+        .detach();
+
+        let server = Socks5Server::new().auth(|user, pass| user == b"me" && pass == b"pw");
+        let proxy = server.bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        smol::spawn(async move {
+            while let Some(client) = proxy.accept().await.unwrap() {
+                smol::spawn(client).detach();
+            }
+        })
+        .detach();
+
+        let listen = {
+            let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            free.local_addr().unwrap()
+        };
+        let _tunnel = Tunnel(
+            Command::new(env!("CARGO_BIN_EXE_socks-tunnel"))
+                .arg(listen.to_string())
+                .arg(format!("socks5://me:pw@{}", proxy_addr))
+                .arg(target_addr.to_string())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap(),
+        );
+
+        let mut attempts = 0;
+        let mut stream = loop {
+            match TcpStream::connect(listen).await {
+                Ok(stream) => break stream,
+                Err(_) if attempts < 100 => {
+                    attempts += 1;
+                    Timer::after(Duration::from_millis(20)).await;
+                }
+                Err(e) => panic!("tunnel didn't start: {}", e),
+            }
+        };
+
+        for _ in 0..2 {
+            stream.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            stream = TcpStream::connect(listen).await.unwrap();
+        }
+    });
+}
+
+#[test]
+fn usage_on_bad_arguments() {
+    let status = Command::new(env!("CARGO_BIN_EXE_socks-tunnel"))
+        .arg("127.0.0.1:0")
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(2));
+}
@@ -0,0 +1,332 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_socks5::{Phase, Socks5Client, Socks5Config, Socks5Error, Socks5UdpSocket, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::UdpSocket;
+
+/// Run a UDP ASSOCIATE against a mock and return the request it received.
+async fn associate_request(source_addr: Option<SocketAddr>) -> Vec<u8> {
+    let (proxy, server) = common::serve_once(|mut stream| async move {
+        common::accept_no_auth(&mut stream).await;
+        let request = common::read_request(&mut stream).await;
+        let reply = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x13, 0x88];
+        stream.write_all(&reply).await.unwrap();
+        request
+    })
+    .await;
+
+    let socket = Socks5Client::udp_associate_from(&proxy, None, source_addr, None)
+        .await
+        .unwrap();
+    assert_eq!(socket.relay_addr(), "127.0.0.1:5000".parse().unwrap());
+
+    server.await
+}
+
+#[test]
+fn udp_associate_unknown_source() {
+    smol::block_on(async {
+        let request = associate_request(None).await;
+        assert_eq!(request, [0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    });
+}
+
+#[test]
+fn udp_associate_explicit_source() {
+    smol::block_on(async {
+        let source = "[::1]:4000".parse().unwrap();
+        let request = associate_request(Some(source)).await;
+
+        let mut expected = vec![0x05, 0x03, 0x00, 0x04];
+        expected.extend_from_slice(&[0; 15]);
+        expected.extend_from_slice(&[0x01, 0x0f, 0xa0]);
+        assert_eq!(request, expected);
+    });
+}
+
+#[test]
+fn udp_associate_reply_times_out() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            // Never reply, but keep the connection open
+            smol::Timer::after(Duration::from_secs(5)).await;
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).read_timeout(Duration::from_millis(200));
+        let err = config.udp_associate(None, None).await;
+        assert!(matches!(err, Err(Socks5Error::Timeout(Phase::Reply))));
+    });
+}
+
+#[test]
+fn udp_header_domain() {
+    let target = TargetAddr::Domain("dns.example".into(), 53);
+    let header = Socks5UdpSocket::encode_header(&target).unwrap();
+
+    let mut expected = vec![0x00, 0x00, 0x00, 0x03, 11];
+    expected.extend_from_slice(b"dns.example");
+    expected.extend_from_slice(&[0x00, 0x35]);
+    assert_eq!(header, expected);
+
+    let mut datagram = header.clone();
+    datagram.extend_from_slice(b"payload");
+    let (addr, len) = Socks5UdpSocket::decode_header(&datagram).unwrap();
+    assert_eq!(addr, target);
+    assert_eq!(&datagram[len..], b"payload");
+}
+
+#[test]
+fn udp_header_ipv4() {
+    let target = TargetAddr::Ip("8.8.8.8:53".parse().unwrap());
+    let header = Socks5UdpSocket::encode_header(&target).unwrap();
+    assert_eq!(header, [0x00, 0x00, 0x00, 0x01, 8, 8, 8, 8, 0x00, 0x35]);
+    assert_eq!(
+        Socks5UdpSocket::decode_header(&header).unwrap(),
+        (target, 10)
+    );
+}
+
+#[test]
+fn udp_header_domain_too_long() {
+    let target = TargetAddr::Domain("a".repeat(256), 53);
+    let err = Socks5UdpSocket::encode_header(&target).unwrap_err();
+    assert!(matches!(err, Socks5Error::InvalidInput(_)));
+}
+
+#[test]
+fn udp_header_truncated() {
+    let err = Socks5UdpSocket::decode_header(&[0x00, 0x00, 0x00, 0x03, 11, b'd']).unwrap_err();
+    assert!(matches!(err, Socks5Error::UnexpectedResponse));
+}
+
+#[test]
+fn udp_header_empty_domain() {
+    let err = Socks5UdpSocket::decode_header(&[0x00, 0x00, 0x00, 0x03, 0, 0x00, 0x35]).unwrap_err();
+    assert!(matches!(err, Socks5Error::UnexpectedResponse));
+}
+
+#[test]
+fn reply_with_empty_domain() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let reply = [0x05, 0x00, 0x00, 0x03, 0, 0x13, 0x88];
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let err = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::UnexpectedResponse));
+    });
+}
+
+#[test]
+fn udp_associate_configured_label() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let reply = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x13, 0x88];
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let config = Socks5Config::new(&proxy).label("udp-proxy");
+        let socket = config.udp_associate(None, None).await.unwrap();
+        assert_eq!(socket.label(), Some("udp-proxy"));
+        assert_eq!(socket.relay_addr(), "127.0.0.1:5000".parse().unwrap());
+    });
+}
+
+#[test]
+fn udp_datagrams_roundtrip_through_relay() {
+    smol::block_on(async {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port().to_be_bytes();
+
+        let (proxy, server) = common::serve_once(move |mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let mut reply = vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+            reply.extend_from_slice(&relay_port);
+            stream.write_all(&reply).await.unwrap();
+
+            // Answer as if 8.8.8.8:53 replied
+            let mut datagram = vec![0u8; 512];
+            let (n, client) = relay.recv_from(&mut datagram).await.unwrap();
+            let mut answer = vec![0x00, 0x00, 0x00, 0x01, 8, 8, 8, 8, 0x00, 0x35];
+            answer.extend_from_slice(b"answer");
+            relay.send_to(&answer, client).await.unwrap();
+
+            datagram.truncate(n);
+            (datagram, stream)
+        })
+        .await;
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let target = TargetAddr::Domain("dns.example".into(), 53);
+        assert_eq!(socket.send_to(b"query", &target).await.unwrap(), 5);
+
+        let mut buf = [0u8; 64];
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"answer");
+        assert_eq!(from, TargetAddr::Ip("8.8.8.8:53".parse().unwrap()));
+
+        let (datagram, _control) = server.await;
+        let mut expected = Socks5UdpSocket::encode_header(&target).unwrap();
+        expected.extend_from_slice(b"query");
+        assert_eq!(datagram, expected);
+    });
+}
+
+#[test]
+fn udp_fragments_sent_and_reassembled() {
+    smol::block_on(async {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port().to_be_bytes();
+
+        let (proxy, server) = common::serve_once(move |mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let mut reply = vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+            reply.extend_from_slice(&relay_port);
+            stream.write_all(&reply).await.unwrap();
+
+            let mut fragments = Vec::new();
+            let mut client = None;
+            for _ in 0..3 {
+                let mut datagram = vec![0u8; 512];
+                let (n, from) = relay.recv_from(&mut datagram).await.unwrap();
+                datagram.truncate(n);
+                fragments.push(datagram);
+                client = Some(from);
+            }
+            let client = client.unwrap();
+
+            let header = |frag| vec![0x00, 0x00, frag, 0x01, 8, 8, 8, 8, 0x00, 0x35];
+            let send = |frag, payload: &[u8]| {
+                let mut datagram = header(frag);
+                datagram.extend_from_slice(payload);
+                datagram
+            };
+            // A sequence broken by an out of order fragment is dropped,
+            // then a complete one arrives
+            for datagram in [
+                send(0x01, b"lost"),
+                send(0x03, b"gap"),
+                send(0x02, b"orphan"),
+                send(0x01, b"hello "),
+                send(0x82, b"world"),
+            ] {
+                relay.send_to(&datagram, client).await.unwrap();
+            }
+            (fragments, stream)
+        })
+        .await;
+
+        let socket = Socks5Config::new(&proxy)
+            .udp_fragmentation(4)
+            .udp_associate(None, None)
+            .await
+            .unwrap();
+        let target = TargetAddr::Ip("8.8.8.8:53".parse().unwrap());
+        assert_eq!(socket.send_to(b"0123456789", &target).await.unwrap(), 10);
+
+        let mut buf = [0u8; 64];
+        let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello world");
+        assert_eq!(from, target);
+
+        let (fragments, _control) = server.await;
+        let frags: Vec<(u8, &[u8])> = fragments.iter().map(|d| (d[2], &d[10..])).collect();
+        assert_eq!(
+            frags,
+            [
+                (0x01, &b"0123"[..]),
+                (0x02, &b"4567"[..]),
+                (0x83, &b"89"[..])
+            ]
+        );
+
+        let err = socket.send_to(&[0u8; 600], &target).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    });
+}
+
+#[test]
+fn udp_associate_binds_the_given_local_port() {
+    smol::block_on(async {
+        let (proxy, _server) = common::serve_once(|mut stream| async move {
+            common::accept_no_auth(&mut stream).await;
+            common::read_request(&mut stream).await;
+            let reply = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x13, 0x88];
+            stream.write_all(&reply).await.unwrap();
+            smol::future::pending::<()>().await;
+        })
+        .await;
+
+        let free = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = free.local_addr().unwrap();
+        drop(free);
+
+        let socket = Socks5Config::new(&proxy)
+            .udp_associate(Some(local_addr), None)
+            .await
+            .unwrap();
+        assert_eq!(socket.local_addr().unwrap(), local_addr);
+    });
+}
+
+#[test]
+fn udp_associate_bind_failure_sends_nothing() {
+    smol::block_on(async {
+        let (proxy, server) = common::serve_once(|mut stream| async move {
+            let mut received = vec![];
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        })
+        .await;
+
+        // The port is taken, so binding fails before the handshake
+        let taken = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let err = Socks5Config::new(&proxy)
+            .udp_associate(Some(taken.local_addr().unwrap()), None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Socks5Error::IoError(e) if e.kind() == std::io::ErrorKind::AddrInUse),
+            "{:?}",
+            err
+        );
+        assert!(server.await.is_empty());
+    });
+}
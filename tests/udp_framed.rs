@@ -0,0 +1,77 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(feature = "sink")]
+
+use std::pin::Pin;
+
+use async_socks5::{Socks5Client, Socks5Error, Socks5Server, TargetAddr};
+use bytes::Bytes;
+use futures_sink::Sink;
+use smol::future::poll_fn;
+use smol::net::UdpSocket;
+use smol::stream::StreamExt;
+
+#[test]
+fn stream_and_sink_roundtrip() {
+    smol::block_on(async {
+        let listener = Socks5Server::new().bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        smol::spawn(async move {
+            while let Some(client) = listener.accept().await.unwrap() {
+                smol::spawn(client).detach();
+            }
+        })
+        .detach();
+
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        smol::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..n], from).await.unwrap();
+        })
+        .detach();
+
+        let socket = Socks5Client::udp_associate(&proxy, None, None)
+            .await
+            .unwrap();
+        let mut framed = socket.framed();
+        let target = TargetAddr::Ip(echo_addr);
+
+        poll_fn(|cx| Pin::new(&mut framed).poll_ready(cx))
+            .await
+            .unwrap();
+        Pin::new(&mut framed)
+            .start_send((Bytes::from_static(b"ping"), target.clone()))
+            .unwrap();
+        poll_fn(|cx| Pin::new(&mut framed).poll_flush(cx))
+            .await
+            .unwrap();
+
+        let (payload, from) = framed.next().await.unwrap().unwrap();
+        assert_eq!(&payload[..], b"ping");
+        assert_eq!(from, target);
+
+        let too_long = TargetAddr::Domain("a".repeat(256), 53);
+        let err = Pin::new(&mut framed)
+            .start_send((Bytes::new(), too_long))
+            .unwrap_err();
+        assert!(matches!(err, Socks5Error::InvalidInput(_)));
+    });
+}
@@ -0,0 +1,58 @@
+/* This file is part of async-socks5
+ *
+ * Copyright (C) 2023 parazyd <parazyd@dyne.org>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#![cfg(unix)]
+
+use async_socks5::{Socks5Client, TargetAddr};
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::unix::UnixListener;
+
+#[test]
+fn proxy_reached_over_unix_socket() {
+    smol::block_on(async {
+        let path = std::env::temp_dir().join(format!("async-socks5-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = smol::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+            let mut request = [0u8; 5 + 11 + 2];
+            stream.read_exact(&mut request).await.unwrap();
+            let reply = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+            stream.write_all(&reply).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            request
+        });
+
+        let target = TargetAddr::Domain("example.com".into(), 80);
+        let mut stream = Socks5Client::connect_unix(&path, &target, None)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let request = server.await;
+        assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, 11]);
+        std::fs::remove_file(&path).unwrap();
+    });
+}